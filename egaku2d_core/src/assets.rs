@@ -0,0 +1,526 @@
+//! Contains the asset manager.
+//!
+//! The asset manager caches textures by the path they were loaded from, and hands out
+//! `TextureHandle`s to them, so loading the same file twice only decodes and uploads it once.
+//! It also remembers when each file was last modified. Calling `poll()` every frame
+//! re-uploads any texture whose file changed on disk, in place, so every handle to it sees the new image.
+//!
+//! Textures can also be loaded asynchronously with `texture_async()`. The file is decoded on a worker thread,
+//! and a `PendingTexture` is returned right away. The decoded images are uploaded on the opengl thread
+//! by calling `upload_pending()` every frame, which stops once it has used up an `UploadBudget`.
+
+use super::*;
+use crate::sprite::*;
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+type Key = (PathBuf, [u8; 2]);
+
+enum PendingState {
+    Loading,
+    Ready(TextureHandle),
+    Failed(String),
+}
+
+///A texture that is being loaded in the background.
+///Cloning it gives another view of the same load.
+#[derive(Clone)]
+pub struct PendingTexture(Rc<RefCell<PendingState>>);
+
+impl PendingTexture {
+    pub fn is_loading(&self) -> bool {
+        matches!(*self.0.borrow(), PendingState::Loading)
+    }
+
+    ///The texture, once it has been uploaded.
+    pub fn handle(&self) -> Option<TextureHandle> {
+        match &*self.0.borrow() {
+            PendingState::Ready(handle) => Some(handle.clone()),
+            _ => None,
+        }
+    }
+
+    ///Why the texture failed to load, if it did.
+    pub fn error(&self) -> Option<String> {
+        match &*self.0.borrow() {
+            PendingState::Failed(e) => Some(e.clone()),
+            _ => None,
+        }
+    }
+}
+
+///Limits how much work `AssetManager::upload_pending()` does in one call.
+///At least one texture is always uploaded if one is ready, so loading always makes progress.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct UploadBudget {
+    pub time: Option<Duration>,
+    pub bytes: Option<usize>,
+}
+
+impl UploadBudget {
+    ///No limit.
+    pub fn new() -> UploadBudget {
+        UploadBudget::default()
+    }
+
+    pub fn with_time(mut self, time: Duration) -> UploadBudget {
+        self.time = Some(time);
+        self
+    }
+
+    pub fn with_bytes(mut self, bytes: usize) -> UploadBudget {
+        self.bytes = Some(bytes);
+        self
+    }
+}
+
+//Counts what `upload_pending()` has used of its budget.
+struct BudgetUse {
+    budget: UploadBudget,
+    start: Instant,
+    bytes: usize,
+    first: bool,
+}
+
+impl BudgetUse {
+    fn new(budget: UploadBudget) -> BudgetUse {
+        BudgetUse {
+            budget,
+            start: Instant::now(),
+            bytes: 0,
+            first: true,
+        }
+    }
+
+    //Returns whether an upload of this many bytes still fits, and counts it if it does.
+    //The first upload always fits.
+    fn take(&mut self, size: usize) -> bool {
+        let over_time = self.budget.time.map(|t| self.start.elapsed() >= t).unwrap_or(false);
+        let over_bytes = self.budget.bytes.map(|b| self.bytes + size > b).unwrap_or(false);
+        if !self.first && (over_time || over_bytes) {
+            return false;
+        }
+        self.first = false;
+        self.bytes += size;
+        true
+    }
+}
+
+///How far along the asynchronous loads are.
+///The counts cover every load since the last time there was nothing left to load.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct LoadProgress {
+    pub loaded: usize,
+    pub failed: usize,
+    pub total: usize,
+}
+
+impl LoadProgress {
+    pub fn is_done(&self) -> bool {
+        self.loaded + self.failed == self.total
+    }
+
+    ///Between 0 and 1, for drawing a loading bar.
+    pub fn fraction(&self) -> f32 {
+        if self.total == 0 {
+            1.0
+        } else {
+            (self.loaded + self.failed) as f32 / self.total as f32
+        }
+    }
+}
+
+struct Job {
+    key: Key,
+    path: PathBuf,
+}
+
+struct Decoded {
+    key: Key,
+    result: Result<([u32; 2], Vec<u8>), String>,
+}
+
+struct PendingEntry {
+    pending: PendingTexture,
+    options: TextureOptions,
+    modified: Option<SystemTime>,
+}
+
+//The worker threads, created the first time something is loaded asynchronously.
+//They exit once the job sender is dropped.
+struct Workers {
+    jobs: mpsc::Sender<Job>,
+    results: mpsc::Receiver<Decoded>,
+}
+
+impl Workers {
+    fn new() -> Workers {
+        let (jobs, job_recv) = mpsc::channel::<Job>();
+        let (result_send, results) = mpsc::channel();
+        let job_recv = Arc::new(Mutex::new(job_recv));
+
+        let num = std::thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(1)
+            .min(4);
+        for _ in 0..num {
+            let job_recv = job_recv.clone();
+            let result_send = result_send.clone();
+            std::thread::spawn(move || loop {
+                let job = match job_recv.lock().unwrap().recv() {
+                    Ok(job) => job,
+                    Err(_) => return,
+                };
+                let result = image::open(&job.path)
+                    .map(|img| {
+                        let img = img.to_rgba();
+                        ([img.width(), img.height()], img.into_raw())
+                    })
+                    .map_err(|e| e.to_string());
+                if result_send.send(Decoded { key: job.key, result }).is_err() {
+                    return;
+                }
+            });
+        }
+        Workers { jobs, results }
+    }
+}
+
+struct TextureEntry {
+    handle: TextureHandle,
+    modified: Option<SystemTime>,
+}
+
+pub struct AssetManager {
+    textures: HashMap<Key, TextureEntry>,
+    poll_interval: Option<Duration>,
+    last_poll: Instant,
+    workers: Option<Workers>,
+    pending: HashMap<Key, PendingEntry>,
+    decoded: VecDeque<Decoded>,
+    progress: LoadProgress,
+}
+
+impl Default for AssetManager {
+    fn default() -> Self {
+        AssetManager::new()
+    }
+}
+
+fn key(path: &Path, grid_dim: [u8; 2]) -> Key {
+    (
+        std::fs::canonicalize(path).unwrap_or_else(|_| path.to_owned()),
+        grid_dim,
+    )
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+//Returns whether the file changed since it was last seen, and remembers the new time if it did.
+//Files that can not be read are left alone.
+fn changed(path: &Path, last: &mut Option<SystemTime>) -> bool {
+    let modified = modified(path);
+    if modified.is_none() || modified == *last {
+        return false;
+    }
+    *last = modified;
+    true
+}
+
+impl AssetManager {
+    ///By default the files are checked for changes at most twice a second.
+    pub fn new() -> AssetManager {
+        AssetManager {
+            textures: HashMap::new(),
+            poll_interval: Some(Duration::from_millis(500)),
+            last_poll: Instant::now(),
+            workers: None,
+            pending: HashMap::new(),
+            decoded: VecDeque::new(),
+            progress: LoadProgress::default(),
+        }
+    }
+
+    ///Set how often `poll()` actually checks the files. None turns off hot reloading.
+    pub fn with_poll_interval(mut self, interval: Option<Duration>) -> AssetManager {
+        self.poll_interval = interval;
+        self
+    }
+
+    pub fn set_poll_interval(&mut self, interval: Option<Duration>) {
+        self.poll_interval = interval;
+    }
+
+    ///Get a handle to the texture at the path, loading it if it is not already cached.
+    ///The options are only applied when the texture is first loaded.
+    ///The same file with a different grid dimension is cached separately.
+    pub fn texture(
+        &mut self,
+        sys: &mut SimpleCanvas,
+        path: impl AsRef<Path>,
+        grid_dim: [u8; 2],
+        options: TextureOptions,
+    ) -> image::ImageResult<TextureHandle> {
+        let path = path.as_ref();
+        let key = key(path, grid_dim);
+
+        if let Some(entry) = self.textures.get(&key) {
+            return Ok(entry.handle.clone());
+        }
+
+        let modified = modified(path);
+        let mut texture = Texture::from_file(sys, path, grid_dim)?;
+        texture.set_options(sys, options);
+        let handle = texture.into_handle();
+
+        self.textures.insert(
+            key,
+            TextureEntry {
+                handle: handle.clone(),
+                modified,
+            },
+        );
+        Ok(handle)
+    }
+
+    ///Start loading the texture at the path on a worker thread, if it is not already cached or loading.
+    ///The texture is uploaded by a later call to `upload_pending()`, after which it is in the cache.
+    ///The options are only applied when the texture is first loaded.
+    pub fn texture_async(
+        &mut self,
+        path: impl AsRef<Path>,
+        grid_dim: [u8; 2],
+        options: TextureOptions,
+    ) -> PendingTexture {
+        let path = path.as_ref();
+        let key = key(path, grid_dim);
+
+        if let Some(entry) = self.textures.get(&key) {
+            return PendingTexture(Rc::new(RefCell::new(PendingState::Ready(
+                entry.handle.clone(),
+            ))));
+        }
+        if let Some(entry) = self.pending.get(&key) {
+            return entry.pending.clone();
+        }
+
+        if self.progress.is_done() {
+            self.progress = LoadProgress::default();
+        }
+        self.progress.total += 1;
+
+        let pending = PendingTexture(Rc::new(RefCell::new(PendingState::Loading)));
+        self.pending.insert(
+            key.clone(),
+            PendingEntry {
+                pending: pending.clone(),
+                options,
+                modified: modified(path),
+            },
+        );
+        self.workers
+            .get_or_insert_with(Workers::new)
+            .jobs
+            .send(Job {
+                key,
+                path: path.to_owned(),
+            })
+            .unwrap();
+        pending
+    }
+
+    ///Upload textures that have finished decoding, until the budget is used up.
+    ///Call this once a frame while anything is loading.
+    pub fn upload_pending(&mut self, sys: &mut SimpleCanvas, budget: UploadBudget) -> LoadProgress {
+        if let Some(workers) = &self.workers {
+            self.decoded.extend(workers.results.try_iter());
+        }
+
+        let mut used = BudgetUse::new(budget);
+        while let Some(decoded) = self.decoded.pop_front() {
+            let size = decoded.result.as_ref().map(|(_, p)| p.len()).unwrap_or(0);
+            if !used.take(size) {
+                self.decoded.push_front(decoded);
+                break;
+            }
+
+            let entry = match self.pending.remove(&decoded.key) {
+                Some(entry) => entry,
+                None => continue,
+            };
+            match decoded.result {
+                Ok(_) if self.textures.contains_key(&decoded.key) => {
+                    //It was loaded synchronously in the mean time.
+                    let handle = self.textures[&decoded.key].handle.clone();
+                    *entry.pending.0.borrow_mut() = PendingState::Ready(handle);
+                    self.progress.loaded += 1;
+                }
+                Ok((dim, pixels)) => {
+                    let mut texture = Texture::from_rgba(sys, dim, &pixels, decoded.key.1);
+                    texture.set_options(sys, entry.options);
+                    let handle = texture.into_handle();
+                    self.textures.insert(
+                        decoded.key,
+                        TextureEntry {
+                            handle: handle.clone(),
+                            modified: entry.modified,
+                        },
+                    );
+                    *entry.pending.0.borrow_mut() = PendingState::Ready(handle);
+                    self.progress.loaded += 1;
+                }
+                Err(e) => {
+                    *entry.pending.0.borrow_mut() = PendingState::Failed(e);
+                    self.progress.failed += 1;
+                }
+            }
+        }
+        self.progress
+    }
+
+    pub fn progress(&self) -> LoadProgress {
+        self.progress
+    }
+
+    ///Returns the number of textures in the cache.
+    pub fn num_textures(&self) -> usize {
+        self.textures.len()
+    }
+
+    ///Remove the textures from the cache that nothing else has a handle to, which deletes them.
+    ///Returns the number of textures removed.
+    pub fn remove_unused(&mut self) -> usize {
+        let before = self.textures.len();
+        self.textures.retain(|_, e| e.handle.handle_count() > 1);
+        before - self.textures.len()
+    }
+
+    ///Re-upload every texture whose file was modified since it was last loaded.
+    ///Does nothing if it was called less than the poll interval ago.
+    ///Returns the path of every texture that was reloaded, along with whether decoding it worked.
+    ///If it failed, the old image is kept, and it is tried again once the file changes again.
+    pub fn poll(&mut self, sys: &mut SimpleCanvas) -> Vec<(PathBuf, image::ImageResult<()>)> {
+        if !self.poll_due() {
+            return Vec::new();
+        }
+
+        let mut reloaded = Vec::new();
+        for ((path, _), entry) in self.textures.iter_mut() {
+            if !changed(path, &mut entry.modified) {
+                continue;
+            }
+
+            let res = image::open(path).map(|img| entry.handle.replace_image(sys, &img));
+            reloaded.push((path.clone(), res));
+        }
+        reloaded
+    }
+
+    //Returns whether the poll interval has passed, and starts the next one if it has.
+    fn poll_due(&mut self) -> bool {
+        match self.poll_interval {
+            Some(interval) if self.last_poll.elapsed() >= interval => {
+                self.last_poll = Instant::now();
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //A fresh directory for one test.
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("egaku2d_assets_{}_{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        dir
+    }
+
+    #[test]
+    fn cache_keys() {
+        let dir = temp_dir("keys");
+        let file = dir.join("a.png");
+        std::fs::write(&file, b"").unwrap();
+
+        //Different paths to the same file share a key, but not with a different grid.
+        let roundabout = dir.join("sub").join("..").join("a.png");
+        assert_eq!(key(&file, [2, 2]), key(&roundabout, [2, 2]));
+        assert_ne!(key(&file, [2, 2]), key(&file, [1, 1]));
+        assert_ne!(key(&file, [2, 2]), key(&dir.join("b.png"), [2, 2]));
+
+        //Paths that do not exist are kept as they are.
+        let missing = dir.join("sub").join("..").join("missing.png");
+        assert_eq!(key(&missing, [1, 1]), (missing.clone(), [1, 1]));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn modified_files() {
+        let dir = temp_dir("modified");
+        let file = dir.join("a.png");
+        std::fs::write(&file, b"").unwrap();
+
+        let mut last = modified(&file);
+        assert!(last.is_some());
+        assert!(!changed(&file, &mut last));
+
+        let later = last.unwrap() + Duration::from_secs(10);
+        std::fs::File::options().write(true).open(&file).unwrap().set_modified(later).unwrap();
+        assert!(changed(&file, &mut last));
+        assert_eq!(last, Some(later));
+        assert!(!changed(&file, &mut last));
+
+        //A file that went missing keeps its old image.
+        std::fs::remove_file(&file).unwrap();
+        assert!(!changed(&file, &mut last));
+        assert_eq!(last, Some(later));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn poll_interval() {
+        let mut a = AssetManager::new().with_poll_interval(None);
+        assert!(!a.poll_due());
+
+        a.set_poll_interval(Some(Duration::from_secs(3600)));
+        assert!(!a.poll_due());
+
+        a.set_poll_interval(Some(Duration::from_secs(0)));
+        assert!(a.poll_due());
+        assert!(a.poll_due());
+    }
+
+    #[test]
+    fn upload_budget() {
+        //No limit.
+        let mut used = BudgetUse::new(UploadBudget::new());
+        assert!((0..10).all(|_| used.take(1 << 30)));
+
+        let mut used = BudgetUse::new(UploadBudget::new().with_bytes(100));
+        assert!(used.take(60));
+        assert!(used.take(40));
+        assert!(!used.take(1));
+
+        //The first upload always fits, even if it is over the budget.
+        let mut used = BudgetUse::new(UploadBudget::new().with_bytes(100));
+        assert!(used.take(500));
+        assert!(!used.take(0));
+
+        let mut used = BudgetUse::new(UploadBudget::new().with_time(Duration::from_secs(0)));
+        assert!(used.take(10));
+        assert!(!used.take(10));
+    }
+}
@@ -1,20 +1,207 @@
 //! Contains all batch drawing code. See the crate level documentation.
+//!
+//! A batch draws a slice of your own structs straight from memory. The struct says where its fields are
+//! by implementing `BatchVertex`, either by hand with `core::mem::offset_of!()`, or with `#[derive(BatchVertex)]`
+//! when the `derive` feature is enabled.
+//!
+//! ```ignore
+//! #[derive(Copy, Clone, BatchVertex)]
+//! struct Bot {
+//!     #[batch(position)]
+//!     pos: [f32; 2],
+//!     vel: [f32; 2],
+//!     #[batch(color)]
+//!     color: [f32; 4],
+//! }
+//! ```
+//!
+//! There are batches of circles, squares, sprites, rects and lines. A batch checks that the struct has
+//! the fields it needs when it is compiled. Sprites use the position, and the index and rotation if there are any,
+//! so a slice of game entities can be drawn as it is:
+//!
+//! ```ignore
+//! #[derive(Copy, Clone, BatchVertex)]
+//! struct Entity {
+//!     #[batch(position)]
+//!     pos: [f32; 2],
+//!     #[batch(rotation)]
+//!     rotation: f32,
+//!     #[batch(index)]
+//!     sprite: u16,
+//!     health: u16,
+//! }
+//! ```
+//!
+//! Rects need `#[batch(bounds)]` instead of a position, and lines need a position and `#[batch(end)]`.
 
 use super::*;
-use core::marker::PhantomData;
-pub struct BatchCircle<T, F> {
-    buffer: vbo::GrowableBuffer<T>,
-    func: F,
-    _p: PhantomData<T>,
+use instanced_shape_program::{InstancedShapeUniformValues, ShapeKind};
+
+#[cfg(feature = "derive")]
+pub use egaku2d_derive::BatchVertex;
+
+///Where the fields of a `BatchVertex` are, in bytes from the start of the struct.
+///The constructors panic if a field does not fit in the struct or is not aligned.
+///Since `BatchVertex::LAYOUT` is a constant, that is a compile error.
+///
+///Circles, squares and sprites are drawn at the position. Lines go from the position to the end,
+///and rects fill the bounds. The radius is only used by circles and squares,
+///and the index and rotation only by sprites.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Layout {
+    pub(crate) size: usize,
+    pub(crate) position: Option<usize>,
+    pub(crate) end: Option<usize>,
+    pub(crate) bounds: Option<usize>,
+    pub(crate) index: Option<usize>,
+    pub(crate) rotation: Option<usize>,
+    pub(crate) color: Option<usize>,
+    pub(crate) radius: Option<usize>,
+}
+
+const fn check(size: usize, offset: usize, field_size: usize, align: usize) {
+    assert!(offset % align == 0, "a batch field is not aligned");
+    assert!(offset + field_size <= size, "a batch field does not fit in the struct");
+}
+
+impl Layout {
+    const fn empty<T>() -> Layout {
+        Layout {
+            size: core::mem::size_of::<T>(),
+            position: None,
+            end: None,
+            bounds: None,
+            index: None,
+            rotation: None,
+            color: None,
+            radius: None,
+        }
+    }
+
+    ///The position of `T` is a `[f32; 2]` at the offset.
+    pub const fn new<T>(position: usize) -> Layout {
+        let mut l = Layout::empty::<T>();
+        check(l.size, position, 8, 4);
+        l.position = Some(position);
+        l
+    }
+
+    ///The bounds of `T` are a `[f32; 4]` at the offset, `[startx,endx,starty,endy]` like `RectSession::add()`.
+    pub const fn rect<T>(bounds: usize) -> Layout {
+        let mut l = Layout::empty::<T>();
+        check(l.size, bounds, 16, 4);
+        l.bounds = Some(bounds);
+        l
+    }
+
+    ///A `[f32; 2]` end of a line, that starts at the position.
+    pub const fn with_end(mut self, end: usize) -> Layout {
+        check(self.size, end, 8, 4);
+        self.end = Some(end);
+        self
+    }
+
+    ///A `u16` index of the tile of a sprite.
+    pub const fn with_index(mut self, index: usize) -> Layout {
+        check(self.size, index, 2, 2);
+        self.index = Some(index);
+        self
+    }
+
+    ///An `f32` rotation of a sprite in radians, like `SpriteSession::add()`.
+    pub const fn with_rotation(mut self, rotation: usize) -> Layout {
+        check(self.size, rotation, 4, 4);
+        self.rotation = Some(rotation);
+        self
+    }
+
+    ///A `[f32; 4]` color, multiplied with the color of the draw.
+    pub const fn with_color(mut self, color: usize) -> Layout {
+        check(self.size, color, 16, 4);
+        self.color = Some(color);
+        self
+    }
+
+    ///An `f32` radius, multiplied with the radius of the draw.
+    pub const fn with_radius(mut self, radius: usize) -> Layout {
+        check(self.size, radius, 4, 4);
+        self.radius = Some(radius);
+        self
+    }
+
+    pub(crate) fn stride(&self) -> i32 {
+        self.size as i32
+    }
+}
+
+///A struct that can be drawn as a batch.
+///
+///```ignore
+///unsafe impl BatchVertex for Bot {
+///    const LAYOUT: Layout = Layout::new::<Bot>(core::mem::offset_of!(Bot, pos))
+///        .with_color(core::mem::offset_of!(Bot, color));
+///}
+///```
+///
+///# Safety
+///
+///The layout has to be made for `Self`, with offsets of fields of `Self` that have the types `Layout` expects,
+///since the gpu reads the struct as raw bytes. Batches check that the size of the layout is the size of `Self`
+///when they are compiled, but not the offsets. `#[derive(BatchVertex)]` always makes a valid layout.
+pub unsafe trait BatchVertex: Copy {
+    const LAYOUT: Layout;
+}
+
+//The layout of a batch, checked to be the size of the struct when compiled,
+//so the gpu never reads past the end of the buffer.
+fn checked_layout<T: BatchVertex>() -> Layout {
+    const {
+        assert!(
+            T::LAYOUT.size == core::mem::size_of::<T>(),
+            "the layout of a batch is not the size of the struct"
+        )
+    };
+    T::LAYOUT
+}
+
+fn send<T>(buffer: &mut vbo::StreamBuffer<T>, bots: &[T]) -> vbo::BufferInfo {
+    buffer.send_to_gpu(bots);
+    buffer.get_info(bots.len())
+}
+
+fn uniforms<'a>(sys: &'a mut SimpleCanvas, un: UniformVals<'a>, buffer: vbo::BufferInfo) -> Uniforms<'a> {
+    let common = sys.common(vec2same(0.0));
+    Uniforms {
+        sys,
+        common,
+        un,
+        buffer: queue::Vertices::Uploaded(buffer),
+    }
+}
+
+fn point_uniforms(layout: Layout, radius: f32) -> ProgramUniformValues<'static> {
+    ProgramUniformValues {
+        mode: gl::POINTS,
+        radius,
+        stride: layout.stride(),
+        texture: None,
+        extruded: false,
+        batch: Some(layout),
+        precision: compact::Precision::Full,
+    }
+}
+
+pub struct BatchCircle<T> {
+    buffer: vbo::StreamBuffer<T>,
     _ns: NotSend,
 }
 
-impl<T, F: Fn(&T) -> &[f32; 2]> BatchCircle<T, F> {
-    pub(crate) fn new(bots: &[T], func: F) -> BatchCircle<T, F> {
+impl<T: BatchVertex> BatchCircle<T> {
+    pub(crate) fn new(bots: &[T]) -> BatchCircle<T> {
+        const { assert!(T::LAYOUT.position.is_some(), "a batch of circles needs a position") };
+        checked_layout::<T>();
         let mut b = BatchCircle {
-            buffer: vbo::GrowableBuffer::new(),
-            func,
-            _p: PhantomData,
+            buffer: vbo::StreamBuffer::new(),
             _ns: ns(),
         };
         b.buffer.send_to_gpu(bots);
@@ -27,42 +214,139 @@ impl<T, F: Fn(&T) -> &[f32; 2]> BatchCircle<T, F> {
         bots: &[T],
         radius: f32,
     ) -> Uniforms<'a> {
-        self.buffer.send_to_gpu(bots);
+        let buffer = send(&mut self.buffer, bots);
+        let un = point_uniforms(checked_layout::<T>(), radius);
+        uniforms(sys, UniformVals::Circle(un), buffer)
+    }
+}
 
-        let stride = if bots.len() < 2 {
-            0i32
-        } else {
-            let first = (self.func)(&bots[0]);
-            let second = (self.func)(&bots[1]);
+pub struct BatchSquare<T> {
+    buffer: vbo::StreamBuffer<T>,
+    _ns: NotSend,
+}
 
-            let a = first as *const _ as usize;
-            let b = second as *const _ as usize;
+impl<T: BatchVertex> BatchSquare<T> {
+    pub(crate) fn new(bots: &[T]) -> BatchSquare<T> {
+        const { assert!(T::LAYOUT.position.is_some(), "a batch of squares needs a position") };
+        checked_layout::<T>();
+        let mut b = BatchSquare {
+            buffer: vbo::StreamBuffer::new(),
+            _ns: ns(),
+        };
+        b.buffer.send_to_gpu(bots);
+        b
+    }
 
-            assert!(b > a);
+    pub fn send_and_uniforms<'a>(
+        &'a mut self,
+        sys: &'a mut SimpleCanvas,
+        bots: &[T],
+        radius: f32,
+    ) -> Uniforms<'a> {
+        let buffer = send(&mut self.buffer, bots);
+        let un = point_uniforms(checked_layout::<T>(), radius);
+        uniforms(sys, UniformVals::Regular(un), buffer)
+    }
+}
 
-            let tsize = core::mem::size_of::<T>();
-            let diff = b - a;
-            assert!(diff >= tsize);
+pub struct BatchSprite<T> {
+    buffer: vbo::StreamBuffer<T>,
+    _ns: NotSend,
+}
 
-            (diff) as i32
+impl<T: BatchVertex> BatchSprite<T> {
+    pub(crate) fn new(bots: &[T]) -> BatchSprite<T> {
+        const { assert!(T::LAYOUT.position.is_some(), "a batch of sprites needs a position") };
+        checked_layout::<T>();
+        let mut b = BatchSprite {
+            buffer: vbo::StreamBuffer::new(),
+            _ns: ns(),
         };
+        b.buffer.send_to_gpu(bots);
+        b
+    }
 
-        let common = UniformCommon {
-            color: sys.color,
-            offset: vec2same(0.0),
+    ///The radius is the same as for `SpriteSession::send_and_uniforms()`.
+    pub fn send_and_uniforms<'a>(
+        &'a mut self,
+        sys: &'a mut SimpleCanvas,
+        bots: &[T],
+        texture: &'a sprite::Texture,
+        radius: f32,
+    ) -> Uniforms<'a> {
+        let buffer = send(&mut self.buffer, bots);
+        let un = sprite_program::SpriteProgramUniformValues {
+            texture,
+            radius: radius * core::f32::consts::SQRT_2,
+            format: VertexFormat::Batch(checked_layout::<T>()),
         };
-        let un = ProgramUniformValues {
-            mode: gl::POINTS,
-            radius,
-            stride,
-            texture: None,
+        uniforms(sys, UniformVals::Sprite(un), buffer)
+    }
+}
+
+pub struct BatchRect<T> {
+    buffer: vbo::StreamBuffer<T>,
+    _ns: NotSend,
+}
+
+impl<T: BatchVertex> BatchRect<T> {
+    pub(crate) fn new(bots: &[T]) -> BatchRect<T> {
+        const { assert!(T::LAYOUT.bounds.is_some(), "a batch of rects needs bounds") };
+        checked_layout::<T>();
+        let mut b = BatchRect {
+            buffer: vbo::StreamBuffer::new(),
+            _ns: ns(),
+        };
+        b.buffer.send_to_gpu(bots);
+        b
+    }
+
+    pub fn send_and_uniforms<'a>(&'a mut self, sys: &'a mut SimpleCanvas, bots: &[T]) -> Uniforms<'a> {
+        let buffer = send(&mut self.buffer, bots);
+        let un = InstancedShapeUniformValues {
+            kind: ShapeKind::Rect,
+            radius: 0.0,
+            batch: Some(checked_layout::<T>()),
         };
+        uniforms(sys, UniformVals::InstancedShape(un), buffer)
+    }
+}
 
-        Uniforms {
-            sys,
-            common,
-            un: UniformVals::Circle(un),
-            buffer: self.buffer.get_info(bots.len()),
-        }
+pub struct BatchLine<T> {
+    buffer: vbo::StreamBuffer<T>,
+    _ns: NotSend,
+}
+
+impl<T: BatchVertex> BatchLine<T> {
+    pub(crate) fn new(bots: &[T]) -> BatchLine<T> {
+        const {
+            assert!(
+                T::LAYOUT.position.is_some() && T::LAYOUT.end.is_some(),
+                "a batch of lines needs a position and an end"
+            )
+        };
+        checked_layout::<T>();
+        let mut b = BatchLine {
+            buffer: vbo::StreamBuffer::new(),
+            _ns: ns(),
+        };
+        b.buffer.send_to_gpu(bots);
+        b
+    }
+
+    ///The radius is half the width of the lines, like `SimpleCanvas::lines()`.
+    pub fn send_and_uniforms<'a>(
+        &'a mut self,
+        sys: &'a mut SimpleCanvas,
+        bots: &[T],
+        radius: f32,
+    ) -> Uniforms<'a> {
+        let buffer = send(&mut self.buffer, bots);
+        let un = InstancedShapeUniformValues {
+            kind: ShapeKind::Line,
+            radius,
+            batch: Some(checked_layout::<T>()),
+        };
+        uniforms(sys, UniformVals::InstancedShape(un), buffer)
     }
 }
@@ -0,0 +1,234 @@
+//! Contains the 2d camera.
+//!
+//! A camera looks at a position in the world with a zoom and a rotation,
+//! and draws what it sees into a rectangle of the window.
+//! It builds the matrix that every program uses to go from world space to clip space,
+//! and can convert points between world space and window pixels, for example to find what the mouse is over.
+
+use super::*;
+
+///Uses the builder pattern.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Camera2D {
+    ///The world position at the center of the viewport.
+    pub position: [f32; 2],
+    ///How many pixels one world unit takes up.
+    pub zoom: f32,
+    ///The rotation of the camera in radians. The world appears rotated the opposite way.
+    pub rotation: f32,
+    ///The rectangle of the window to draw into, `[startx,endx,starty,endy]` in pixels from the top left.
+    pub viewport: [f32; 4],
+}
+
+impl Camera2D {
+    ///A camera where one world unit is one pixel, and the top left of the viewport is the world origin.
+    pub fn new(viewport: [f32; 4]) -> Camera2D {
+        Camera2D {
+            position: [
+                (viewport[1] - viewport[0]) / 2.0,
+                (viewport[3] - viewport[2]) / 2.0,
+            ],
+            zoom: 1.0,
+            rotation: 0.0,
+            viewport,
+        }
+    }
+
+    ///A camera that fills the window with `game_width` world units across,
+    ///with the world origin at the top left. This is what `SimpleCanvas::set_viewport()` uses.
+    pub fn from_width(window_dim: [f32; 2], game_width: f32) -> Camera2D {
+        let zoom = window_dim[0] / game_width;
+        Camera2D {
+            position: [window_dim[0] / zoom / 2.0, window_dim[1] / zoom / 2.0],
+            zoom,
+            rotation: 0.0,
+            viewport: [0.0, window_dim[0], 0.0, window_dim[1]],
+        }
+    }
+
+    pub fn with_position(mut self, position: [f32; 2]) -> Camera2D {
+        self.position = position;
+        self
+    }
+
+    pub fn with_zoom(mut self, zoom: f32) -> Camera2D {
+        self.zoom = zoom;
+        self
+    }
+
+    pub fn with_rotation(mut self, rotation: f32) -> Camera2D {
+        self.rotation = rotation;
+        self
+    }
+
+    pub fn with_viewport(mut self, viewport: [f32; 4]) -> Camera2D {
+        self.viewport = viewport;
+        self
+    }
+
+    ///Multiply the zoom, keeping the world point under a window pixel in place.
+    ///Useful for zooming to the mouse cursor.
+    pub fn zoom_at(&mut self, screen: [f32; 2], factor: f32) {
+        let before = self.screen_to_world(screen);
+        self.zoom *= factor;
+        let after = self.screen_to_world(screen);
+        self.position[0] += before[0] - after[0];
+        self.position[1] += before[1] - after[1];
+    }
+
+    fn viewport_center(&self) -> [f32; 2] {
+        let v = self.viewport;
+        [(v[0] + v[1]) / 2.0, (v[2] + v[3]) / 2.0]
+    }
+
+    ///Convert a world position to a position in window pixels.
+    pub fn world_to_screen(&self, world: [f32; 2]) -> [f32; 2] {
+        let (s, c) = self.rotation.sin_cos();
+        let dx = world[0] - self.position[0];
+        let dy = world[1] - self.position[1];
+        let center = self.viewport_center();
+        [
+            center[0] + self.zoom * (c * dx + s * dy),
+            center[1] + self.zoom * (-s * dx + c * dy),
+        ]
+    }
+
+    ///Convert a position in window pixels, like the mouse position, to a world position.
+    pub fn screen_to_world(&self, screen: [f32; 2]) -> [f32; 2] {
+        let (s, c) = self.rotation.sin_cos();
+        let center = self.viewport_center();
+        let x = (screen[0] - center[0]) / self.zoom;
+        let y = (screen[1] - center[1]) / self.zoom;
+        [
+            self.position[0] + c * x - s * y,
+            self.position[1] + s * x + c * y,
+        ]
+    }
+
+    ///The smallest world space rectangle that contains everything the camera can see.
+    pub fn visible_rect(&self) -> Rect<f32> {
+        let v = self.viewport;
+        let corners = [
+            self.screen_to_world([v[0], v[2]]),
+            self.screen_to_world([v[1], v[2]]),
+            self.screen_to_world([v[0], v[3]]),
+            self.screen_to_world([v[1], v[3]]),
+        ];
+        let mut r = Rect::new(corners[0][0], corners[0][0], corners[0][1], corners[0][1]);
+        for c in corners.iter() {
+            r.x.start = r.x.start.min(c[0]);
+            r.x.end = r.x.end.max(c[0]);
+            r.y.start = r.y.start.min(c[1]);
+            r.y.end = r.y.end.max(c[1]);
+        }
+        r
+    }
+
+    ///The column major matrix that takes world space to clip space.
+    pub fn matrix(&self) -> [[f32; 3]; 3] {
+        let (s, c) = self.rotation.sin_cos();
+        let w = self.viewport[1] - self.viewport[0];
+        let h = self.viewport[3] - self.viewport[2];
+
+        //Clip space has y going up, so y is flipped.
+        let m00 = 2.0 * self.zoom * c / w;
+        let m01 = 2.0 * self.zoom * s / w;
+        let m10 = 2.0 * self.zoom * s / h;
+        let m11 = -2.0 * self.zoom * c / h;
+
+        let [px, py] = self.position;
+        let tx = -(m00 * px + m01 * py);
+        let ty = -(m10 * px + m11 * py);
+
+        [[m00, m10, 0.0], [m01, m11, 0.0], [tx, ty, 1.0]]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: [f32; 2], b: [f32; 2]) -> bool {
+        (a[0] - b[0]).abs() < 1e-3 && (a[1] - b[1]).abs() < 1e-3
+    }
+
+    //A rotated and zoomed camera drawing into a viewport that does not start at the window origin.
+    fn camera() -> Camera2D {
+        Camera2D::new([100.0, 500.0, 50.0, 350.0])
+            .with_position([-30.0, 70.0])
+            .with_zoom(2.5)
+            .with_rotation(0.7)
+    }
+
+    #[test]
+    fn round_trip() {
+        let cam = camera();
+        for &p in [[0.0, 0.0], [-30.0, 70.0], [123.4, -56.7], [1000.0, 2000.0]].iter() {
+            assert!(close(cam.screen_to_world(cam.world_to_screen(p)), p));
+            assert!(close(cam.world_to_screen(cam.screen_to_world(p)), p));
+        }
+        //The camera position is at the center of the viewport.
+        assert!(close(cam.world_to_screen([-30.0, 70.0]), [300.0, 200.0]));
+    }
+
+    #[test]
+    fn matrix_matches_world_to_screen() {
+        let cam = camera();
+        let m = cam.matrix();
+        let v = cam.viewport;
+        for &p in [[0.0, 0.0], [123.4, -56.7]].iter() {
+            let clip = [
+                m[0][0] * p[0] + m[1][0] * p[1] + m[2][0],
+                m[0][1] * p[0] + m[1][1] * p[1] + m[2][1],
+            ];
+            let screen = [
+                v[0] + (clip[0] + 1.0) / 2.0 * (v[1] - v[0]),
+                v[2] + (1.0 - clip[1]) / 2.0 * (v[3] - v[2]),
+            ];
+            assert!(close(screen, cam.world_to_screen(p)));
+        }
+    }
+
+    #[test]
+    fn zoom_at_keeps_the_anchor() {
+        let mut cam = camera();
+        let anchor = [420.0, 80.0];
+        let world = cam.screen_to_world(anchor);
+        cam.zoom_at(anchor, 3.0);
+        assert_eq!(cam.zoom, 7.5);
+        assert!(close(cam.screen_to_world(anchor), world));
+        assert!(close(cam.world_to_screen(world), anchor));
+        cam.zoom_at(anchor, 0.1);
+        assert!(close(cam.screen_to_world(anchor), world));
+    }
+
+    #[test]
+    fn visible_rect() {
+        let cam = Camera2D::new([0.0, 400.0, 0.0, 200.0]).with_zoom(2.0);
+        let r = cam.visible_rect();
+        assert_eq!(r, Rect::new(100.0, 300.0, 50.0, 150.0));
+
+        //A quarter turn swaps the width and height.
+        let r = cam.with_rotation(core::f32::consts::FRAC_PI_2).visible_rect();
+        assert!(close([r.x.start, r.x.end], [150.0, 250.0]));
+        assert!(close([r.y.start, r.y.end], [0.0, 200.0]));
+
+        //Every corner of a rotated view is inside the rect.
+        let cam = camera();
+        let r = cam.visible_rect();
+        let v = cam.viewport;
+        for &p in [[v[0], v[2]], [v[1], v[2]], [v[0], v[3]], [v[1], v[3]]].iter() {
+            let w = cam.screen_to_world(p);
+            assert!(w[0] >= r.x.start - 1e-3 && w[0] <= r.x.end + 1e-3);
+            assert!(w[1] >= r.y.start - 1e-3 && w[1] <= r.y.end + 1e-3);
+        }
+    }
+
+    #[test]
+    fn from_width() {
+        let cam = Camera2D::from_width([800.0, 600.0], 400.0);
+        assert_eq!(cam.zoom, 2.0);
+        assert!(close(cam.screen_to_world([0.0, 0.0]), [0.0, 0.0]));
+        assert!(close(cam.screen_to_world([800.0, 600.0]), [400.0, 300.0]));
+    }
+}
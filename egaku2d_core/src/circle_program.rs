@@ -1,6 +1,7 @@
 use super::*;
 use crate::gl;
 use crate::shader::*;
+use crate::state::{GlState, UniformValue, VaoKey};
 use crate::vbo::BufferInfo;
 use std::ffi::CString;
 use std::str;
@@ -11,6 +12,12 @@ pub struct ProgramUniformValues<'a> {
     pub mode: u32,
     pub stride: i32,
     pub texture: Option<(&'a sprite::Texture, f32, [f32; 2])>,
+    ///If the buffer is made of `LineVertex`, in which case the radius is the half width of the lines.
+    pub extruded: bool,
+    ///If the buffer is a batch of user structs.
+    pub batch: Option<batch::Layout>,
+    ///How the positions in the buffer are stored.
+    pub precision: compact::Precision,
 }
 impl<'a> ProgramUniformValues<'a> {
     pub fn new(radius: f32, mode: u32) -> Self {
@@ -19,22 +26,108 @@ impl<'a> ProgramUniformValues<'a> {
             radius,
             texture: None,
             stride: 0,
+            extruded: false,
+            batch: None,
+            precision: compact::Precision::Full,
         }
     }
+
+    ///For buffers of `LineVertex`, where each vertex is pushed out by the radius.
+    pub fn new_extruded(radius: f32) -> Self {
+        ProgramUniformValues {
+            mode: gl::TRIANGLES,
+            radius,
+            texture: None,
+            stride: core::mem::size_of::<LineVertex>() as i32,
+            extruded: true,
+            batch: None,
+            precision: compact::Precision::Full,
+        }
+    }
+
+    ///Where the position is in each element of the buffer.
+    pub(crate) fn position_offset(&self) -> usize {
+        self.batch.and_then(|b| b.position).unwrap_or(0)
+    }
+
+    ///Tells vertex array objects of the different kinds of buffers apart.
+    pub(crate) fn vao_variant(&self) -> u32 {
+        self.extruded as u32 | (self.batch.is_some() as u32) << 1 | self.precision.variant() << 2
+    }
+}
+
+///Points the extrude attribute at the buffer, if it is a buffer of `LineVertex`.
+///Part of setting up a vertex array object. Returns the number of calls made.
+pub(crate) unsafe fn enable_extrude(extrude_attr: GLint, un: &ProgramUniformValues) -> usize {
+    if !un.extruded {
+        return 0;
+    }
+    gl::EnableVertexAttribArray(extrude_attr as GLuint);
+    gl_ok!();
+
+    gl::VertexAttribPointer(
+        extrude_attr as GLuint,
+        2,
+        gl::FLOAT,
+        gl::FALSE,
+        un.stride,
+        (4 * 2) as *const _,
+    );
+    gl_ok!();
+    2
+}
+
+///Sets the line width, or the constant extrude for buffers without one.
+///Shared by every program that uses `VS_SRC`.
+pub(crate) unsafe fn set_extrude(
+    state: &mut GlState,
+    extrude_attr: GLint,
+    line_width_uniform: GLint,
+    un: &ProgramUniformValues,
+) {
+    if un.extruded {
+        state.uniform(line_width_uniform, UniformValue::Float(un.radius));
+    } else {
+        //With the attribute array disabled, every vertex gets this constant value.
+        gl::VertexAttrib2f(extrude_attr as GLuint, 0.0, 0.0);
+        gl_ok!();
+    }
 }
 
 // Shader sources
 pub static VS_SRC: &'static str = "
 #version 300 es
 in vec2 position;
+in vec2 extrude;
+in vec4 vertex_color;
+in float vertex_radius;
+in vec2 instance_offset;
+in vec4 instance_matrix;
+in vec4 instance_color;
+out vec4 vinstance_color;
 out vec2 pos;
+uniform vec2 position_origin;
+uniform float position_step;
 uniform vec2 offset;
 uniform mat3 mmatrix;
 uniform float point_size;
+uniform float line_width;
+uniform mat3 transform;
 void main() {
-    gl_PointSize = point_size;
-    vec3 pp=vec3(position+offset,1.0);
-    pos=position*0.005;
+    gl_PointSize = point_size*vertex_radius;
+    vinstance_color=instance_color*vertex_color;
+    vec2 p=position*position_step+position_origin;
+    vec3 t=transform*vec3(mat2(instance_matrix)*p+instance_offset,1.0);
+
+    //Keep the extrude perpendicular to the transformed line, and keep its length.
+    mat2 m=mat2(transform)*mat2(instance_matrix);
+    vec2 e=mat2(m[1][1],-m[1][0],-m[0][1],m[0][0])*extrude;
+    if(dot(e,e)>0.0){
+        e=normalize(e)*length(extrude);
+    }
+
+    vec3 pp=vec3(t.xy/t.z+e*line_width+offset,1.0);
+    pos=p*0.005;
     gl_Position = vec4(mmatrix*pp.xyz, 1.0);
 }";
 
@@ -44,6 +137,7 @@ pub static CIRCLE_FS_SRC: &'static str = "
 precision mediump float;
 uniform vec4 bcol;
 out vec4 out_color;
+in vec4 vinstance_color;
 in vec2 pos;
 in float ps;
 
@@ -55,7 +149,7 @@ void main() {
         discard;
     }
 
-    out_color = bcol;
+    out_color = bcol*vinstance_color;
 }";
 
 pub static REGULAR_FS_SRC: &'static str = "
@@ -64,61 +158,65 @@ precision mediump float;
 uniform vec4 bcol;
 in vec2 pos;
 out vec4 out_color;
+in vec4 vinstance_color;
 
 void main() {
-    out_color=bcol;
+    out_color=bcol*vinstance_color;
 }";
 
 #[repr(transparent)]
 #[derive(Copy, Clone, Debug, Default)]
 pub struct Vertex(pub [f32; 2]);
 
+///A vertex with its position stored in half the space. See the `compact` module.
+#[repr(transparent)]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct CompactVertex(pub [u16; 2]);
+
+///A vertex of a line or arrow.
+///It is pushed out from its position along `extrude` times the line radius when drawn,
+///so the width can change without rebuilding the geometry.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct LineVertex {
+    pub pos: [f32; 2],
+    pub extrude: [f32; 2],
+}
+
 #[derive(Debug)]
 pub struct CircleProgram {
     pub program: GLuint,
     pub matrix_uniform: GLint,
+    pub transform_uniform: GLint,
+    pub(crate) instance_attrs: instance::InstanceAttrs,
     pub offset_uniform: GLint,
     pub point_size_uniform: GLint,
     pub bcol_uniform: GLint,
+    pub line_width_uniform: GLint,
     pub pos_attr: GLint,
+    pub extrude_attr: GLint,
+    pub color_attr: GLint,
+    pub radius_attr: GLint,
+    pub position_origin_uniform: GLint,
+    pub position_step_uniform: GLint,
 }
 
 #[derive(Debug)]
 pub struct PointMul(pub f32);
 
 impl CircleProgram {
-    pub fn set_viewport(
-        &mut self,
-        window_dim: FixedAspectVec2,
-        game_width: f32,
-    ) -> PointMul {
-        let game_height = window_dim.ratio.height_over_width() as f32 * game_width;
-
-        let scalex = 2.0 / game_width;
-        let scaley = 2.0 / game_height;
-
-        let tx = -1.0;
-        let ty = 1.0;
-
-        let matrix = [[scalex, 0.0, 0.0], [0.0, -scaley, 0.0], [tx, ty, 1.0]];
-
+    pub fn set_matrix(&mut self, matrix: &[[f32; 3]; 3]) {
         unsafe {
             gl::UseProgram(self.program);
             gl_ok!();
-            gl::UniformMatrix3fv(
-                self.matrix_uniform,
-                1,
-                0,
-                std::mem::transmute(&matrix[0][0]),
-            );
+            gl::UniformMatrix3fv(self.matrix_uniform, 1, 0, matrix[0].as_ptr());
             gl_ok!();
         }
-
-        PointMul(window_dim.width as f32 / game_width)
     }
 
     pub(crate) fn set_buffer_and_draw(
         &mut self,
+        state: &mut GlState,
         common: &UniformCommon,
         un: &ProgramUniformValues,
         buffer_info: BufferInfo,
@@ -129,47 +227,87 @@ impl CircleProgram {
         //let square=un.rect;
         let buffer_id = buffer_info.id;
         let offset = common.offset;
-        let length = buffer_info.length;
         let stride = un.stride;
 
         unsafe {
-            gl::UseProgram(self.program);
-            gl_ok!();
-
-            gl::Uniform2f(self.offset_uniform, offset.x, offset.y);
-            gl_ok!();
-
-            gl::Uniform1f(self.point_size_uniform, point_size);
-            gl_ok!();
-
-            gl::Uniform4fv(self.bcol_uniform, 1, col.as_ptr() as *const _);
-            gl_ok!();
-
-            gl::BindBuffer(gl::ARRAY_BUFFER, buffer_id);
-            gl_ok!();
-
-            gl::EnableVertexAttribArray(self.pos_attr as GLuint);
-            gl_ok!();
-
-            gl::VertexAttribPointer(
-                self.pos_attr as GLuint,
-                2,
-                gl::FLOAT,
-                gl::FALSE as GLboolean,
-                stride as i32,
-                core::ptr::null(),
-            );
-            gl_ok!();
-
-            gl::DrawArrays(mode, 0 as i32, length as i32);
+            state.use_program(self.program);
+
+            state.uniform(self.offset_uniform, UniformValue::Vec2([offset.x, offset.y]));
+            state.uniform(self.transform_uniform, UniformValue::Mat3(common.transform.matrix()));
+            state.uniform(self.point_size_uniform, UniformValue::Float(point_size));
+            state.uniform(self.bcol_uniform, UniformValue::Vec4(col));
+
+            let (origin, step) = un.precision.origin_and_step();
+            state.uniform(self.position_origin_uniform, UniformValue::Vec2(origin));
+            state.uniform(self.position_step_uniform, UniformValue::Float(step));
+
+            let key = VaoKey {
+                program: self.program,
+                buffer: buffer_id,
+                stride,
+                variant: un.vao_variant(),
+                batch: un.batch,
+            };
+            state.bind_vertex_array(key, || {
+                gl::BindBuffer(gl::ARRAY_BUFFER, buffer_id);
+                gl_ok!();
+
+                gl::EnableVertexAttribArray(self.pos_attr as GLuint);
+                gl_ok!();
+
+                gl::VertexAttribPointer(
+                    self.pos_attr as GLuint,
+                    2,
+                    un.precision.gl_type(),
+                    gl::FALSE as GLboolean,
+                    stride,
+                    un.position_offset() as *const _,
+                );
+                gl_ok!();
+
+                let mut calls = 3 + enable_extrude(self.extrude_attr, un);
+                if let Some(layout) = un.batch {
+                    for &(attr, size, offset) in
+                        [(self.color_attr, 4, layout.color), (self.radius_attr, 1, layout.radius)].iter()
+                    {
+                        if let Some(offset) = offset {
+                            gl::EnableVertexAttribArray(attr as GLuint);
+                            gl_ok!();
+
+                            gl::VertexAttribPointer(
+                                attr as GLuint,
+                                size,
+                                gl::FLOAT,
+                                gl::FALSE,
+                                stride,
+                                offset as *const _,
+                            );
+                            gl_ok!();
+                            calls += 2;
+                        }
+                    }
+                }
+                calls
+            });
+
+            set_extrude(state, self.extrude_attr, self.line_width_uniform, un);
+
+            //With the attribute arrays disabled, every vertex gets these constant values.
+            let layout = un.batch;
+            if layout.and_then(|l| l.color).is_none() {
+                gl::VertexAttrib4f(self.color_attr as GLuint, 1.0, 1.0, 1.0, 1.0);
+                gl_ok!();
+            }
+            if layout.and_then(|l| l.radius).is_none() {
+                gl::VertexAttrib1f(self.radius_attr as GLuint, 1.0);
+                gl_ok!();
+            }
 
-            gl_ok!();
+            self.instance_attrs.enable(common.instances);
 
-            gl::DisableVertexAttribArray(self.pos_attr as GLuint);
-            gl_ok!();
+            self.instance_attrs.draw(mode, buffer_info, common.instances);
 
-            gl::BindBuffer(gl::ARRAY_BUFFER, 0);
-            gl_ok!();
+            self.instance_attrs.disable(common.instances);
         }
     }
 
@@ -204,6 +342,13 @@ impl CircleProgram {
                 gl::GetUniformLocation(program,temp.as_ptr());
             gl_ok!();
 
+            let temp= CString::new("transform").unwrap();
+            let transform_uniform: GLint =
+                gl::GetUniformLocation(program,temp.as_ptr());
+            gl_ok!();
+
+            let instance_attrs = instance::InstanceAttrs::new(program);
+
             let temp=CString::new("bcol").unwrap();
             let bcol_uniform: GLint =
                 gl::GetUniformLocation(program, temp.as_ptr());
@@ -214,18 +359,56 @@ impl CircleProgram {
                 gl::GetUniformLocation(program, temp.as_ptr());
             gl_ok!();
 
+            let temp=CString::new("line_width").unwrap();
+            let line_width_uniform: GLint =
+                gl::GetUniformLocation(program, temp.as_ptr());
+            gl_ok!();
+
             let temp=CString::new("position").unwrap();
             let pos_attr =
                 gl::GetAttribLocation(program, temp.as_ptr());
             gl_ok!();
 
+            let temp=CString::new("extrude").unwrap();
+            let extrude_attr =
+                gl::GetAttribLocation(program, temp.as_ptr());
+            gl_ok!();
+
+            let temp=CString::new("vertex_color").unwrap();
+            let color_attr =
+                gl::GetAttribLocation(program, temp.as_ptr());
+            gl_ok!();
+
+            let temp=CString::new("vertex_radius").unwrap();
+            let radius_attr =
+                gl::GetAttribLocation(program, temp.as_ptr());
+            gl_ok!();
+
+            let temp=CString::new("position_origin").unwrap();
+            let position_origin_uniform =
+                gl::GetUniformLocation(program, temp.as_ptr());
+            gl_ok!();
+
+            let temp=CString::new("position_step").unwrap();
+            let position_step_uniform =
+                gl::GetUniformLocation(program, temp.as_ptr());
+            gl_ok!();
+
             CircleProgram {
                 program,
+                position_origin_uniform,
+                position_step_uniform,
                 offset_uniform,
                 point_size_uniform,
                 matrix_uniform,
+                transform_uniform,
+                instance_attrs,
                 bcol_uniform,
+                line_width_uniform,
                 pos_attr,
+                extrude_attr,
+                color_attr,
+                radius_attr,
             }
         }
     }
@@ -0,0 +1,395 @@
+//! Contains the compact vertex formats.
+//!
+//! By default positions are stored as two `f32`s. Circle, square, rect and sprite sessions can instead store
+//! them in half the space with `with_precision()`, which their saves keep. That halves the size of circles,
+//! squares and rects, and takes sprites from twelve bytes to eight, at the cost of precision.
+//!
+//! `Precision::Half` stores two `f16`s. It keeps 11 bits of precision at any scale, and works best for positions
+//! close to the origin. `Precision::Quantized` stores two `i16`s, that count steps of a fixed size away from an origin.
+//! It has the same precision everywhere in the area it covers, so it suits big maps.
+//! Positions that do not fit are clamped to the largest that do.
+//!
+//! Line and arrow sessions always store full positions. Their vertices also store the direction they are
+//! pushed out in to give the line its width, so they would need compact formats and attribute setups of their own,
+//! and storing the position in half the space would only take a quarter off their size.
+//!
+//! `Precision::max_error()` says how far a position can move when it is stored:
+//!
+//! ```
+//! use egaku2d_core::compact::{Precision, Quantized};
+//!
+//! let map = [-1000.0, 3000.0, 0.0, 2000.0];
+//! for precision in [Precision::Half, Precision::Quantized(Quantized::covering(map))].iter() {
+//!     for i in 0..1000 {
+//!         let p = [-1000.0 + i as f32 * 4.0037, i as f32 * 1.9993];
+//!         let q = precision.round_trip(p);
+//!         let error = (p[0] - q[0]).abs().max((p[1] - q[1]).abs());
+//!         assert!(error <= precision.max_error(p));
+//!     }
+//! }
+//! assert!(Precision::Half.max_error([1000.0, 0.0]) <= 0.5);
+//! assert!(Quantized::covering(map).max_error() < 0.04);
+//! ```
+
+use super::*;
+
+///How the positions of vertices are stored.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum Precision {
+    ///Two `f32`s.
+    #[default]
+    Full,
+    ///Two `f16`s. Up to 65504 away from the origin.
+    Half,
+    ///Two `i16`s, relative to an origin.
+    Quantized(Quantized),
+}
+
+///Positions stored as a whole number of steps away from an origin.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Quantized {
+    origin: [f32; 2],
+    step: f32,
+}
+
+//The most steps a position can be from the origin.
+const MAX_STEPS: f32 = i16::MAX as f32;
+
+impl Quantized {
+    ///Covers 32767 steps in every direction from the origin.
+    pub fn new(origin: [f32; 2], step: f32) -> Quantized {
+        assert!(step > 0.0, "the step has to be positive");
+        Quantized { origin, step }
+    }
+
+    ///The smallest step that covers the rect `[startx,endx,starty,endy]`.
+    pub fn covering(rect: [f32; 4]) -> Quantized {
+        let [sx, ex, sy, ey] = rect;
+        let origin = [(sx + ex) * 0.5, (sy + ey) * 0.5];
+        let half = ((ex - sx).abs().max((ey - sy).abs())) * 0.5;
+        //Leave a little room, so rounding never pushes the edges out of range.
+        let step = half * (1.0 + 1e-4) / MAX_STEPS;
+        Quantized::new(origin, if step > 0.0 { step } else { 1.0 })
+    }
+
+    pub fn origin(&self) -> [f32; 2] {
+        self.origin
+    }
+
+    pub fn step(&self) -> f32 {
+        self.step
+    }
+
+    ///Half a step. Positions in range move at most this much, ignoring the rounding of `f32`s.
+    pub fn max_error(&self) -> f32 {
+        self.step * 0.5
+    }
+
+    fn encode(&self, a: f32, origin: f32) -> u16 {
+        ((a - origin) / self.step).round().clamp(-MAX_STEPS, MAX_STEPS) as i16 as u16
+    }
+
+    fn decode(&self, a: u16, origin: f32) -> f32 {
+        a as i16 as f32 * self.step + origin
+    }
+}
+
+impl Precision {
+    ///Where a position ends up after it is stored.
+    pub fn round_trip(&self, p: PointType) -> PointType {
+        match self {
+            Precision::Full => p,
+            Precision::Half => [f16_to_f32(f32_to_f16(p[0])), f16_to_f32(f32_to_f16(p[1]))],
+            Precision::Quantized(q) => {
+                let [x, y] = self.encode(p);
+                [q.decode(x, q.origin[0]), q.decode(y, q.origin[1])]
+            }
+        }
+    }
+
+    ///How far each coordinate of a position in range can move when it is stored.
+    pub fn max_error(&self, p: PointType) -> f32 {
+        let largest = p[0].abs().max(p[1].abs());
+        match self {
+            Precision::Full => 0.0,
+            //Rounding to 11 bits, or half of the smallest subnormal.
+            Precision::Half => (largest * f32::powi(2.0, -11)).max(f32::powi(2.0, -25)),
+            //Plus a few roundings of the f32 math on the way there and back.
+            Precision::Quantized(q) => {
+                let origin = q.origin[0].abs().max(q.origin[1].abs());
+                q.max_error() + (largest + origin) * f32::EPSILON * 4.0
+            }
+        }
+    }
+
+    pub(crate) fn encode(&self, p: PointType) -> [u16; 2] {
+        match self {
+            //Only compact vertices are encoded.
+            Precision::Full => unreachable!(),
+            Precision::Half => [f32_to_f16(p[0]), f32_to_f16(p[1])],
+            Precision::Quantized(q) => [q.encode(p[0], q.origin[0]), q.encode(p[1], q.origin[1])],
+        }
+    }
+
+    ///The type of the position attribute.
+    pub(crate) fn gl_type(&self) -> GLenum {
+        match self {
+            Precision::Full => gl::FLOAT,
+            Precision::Half => gl::HALF_FLOAT,
+            Precision::Quantized(_) => gl::SHORT,
+        }
+    }
+
+    ///The shaders turn the stored position into `position*step+origin`.
+    pub(crate) fn origin_and_step(&self) -> ([f32; 2], f32) {
+        match self {
+            Precision::Full | Precision::Half => ([0.0; 2], 1.0),
+            Precision::Quantized(q) => (q.origin, q.step),
+        }
+    }
+
+    ///Tells vertex array objects of the different precisions apart.
+    pub(crate) fn variant(&self) -> u32 {
+        match self {
+            Precision::Full => 0,
+            Precision::Half => 1,
+            Precision::Quantized(_) => 2,
+        }
+    }
+}
+
+//Rounds to the nearest, ties to even. Too large values are clamped to the largest half float.
+fn f32_to_f16(a: f32) -> u16 {
+    const MAX: u32 = 0x7bff;
+    let bits = a.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exp = ((bits >> 23) & 0xff) as i32;
+    let man = bits & 0x7f_ffff;
+
+    if exp == 0xff && man != 0 {
+        return sign | 0x7e00;
+    }
+
+    let e = exp - 127 + 15;
+    let (half, rem, halfway) = if e >= 0x1f {
+        (MAX, 0, 1)
+    } else if e <= 0 {
+        //A subnormal half float, or zero.
+        if e < -10 {
+            return sign;
+        }
+        let man = man | 0x80_0000;
+        let shift = (14 - e) as u32;
+        (man >> shift, man & ((1 << shift) - 1), 1 << (shift - 1))
+    } else {
+        (((e as u32) << 10) | (man >> 13), man & 0x1fff, 0x1000)
+    };
+    let round = rem > halfway || (rem == halfway && half & 1 == 1);
+    sign | (half + round as u32).min(MAX) as u16
+}
+
+fn f16_to_f32(a: u16) -> f32 {
+    let sign = if a & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exp = ((a >> 10) & 0x1f) as i32;
+    let man = (a & 0x3ff) as f32;
+    match exp {
+        0 => sign * man * f32::powi(2.0, -24),
+        0x1f if man == 0.0 => sign * f32::INFINITY,
+        0x1f => f32::NAN,
+        _ => sign * (1.0 + man / 1024.0) * f32::powi(2.0, exp - 15),
+    }
+}
+
+//Compact shapes are half the size, and compact sprites lose the same four bytes.
+const _: () = assert!(2 * mem::size_of::<circle_program::CompactVertex>() == mem::size_of::<circle_program::Vertex>());
+const _: () = assert!(mem::size_of::<sprite_program::CompactVertex>() + 4 == mem::size_of::<sprite_program::Vertex>());
+
+///A vertex type that has a compact version.
+pub(crate) trait Compact: Copy + core::fmt::Debug {
+    type Compact: Copy + core::fmt::Debug;
+    fn compact(&self, precision: &Precision) -> Self::Compact;
+}
+
+impl Compact for circle_program::Vertex {
+    type Compact = circle_program::CompactVertex;
+    fn compact(&self, precision: &Precision) -> Self::Compact {
+        circle_program::CompactVertex(precision.encode(self.0))
+    }
+}
+
+impl Compact for sprite_program::Vertex {
+    type Compact = sprite_program::CompactVertex;
+    fn compact(&self, precision: &Precision) -> Self::Compact {
+        sprite_program::CompactVertex {
+            pos: precision.encode(self.pos),
+            index: self.index,
+            rotation: self.rotation,
+        }
+    }
+}
+
+pub(crate) fn compact_all<V: Compact>(verts: &[V], precision: &Precision) -> Vec<V::Compact> {
+    verts.iter().map(|v| v.compact(precision)).collect()
+}
+
+//Sessions hand over their vertices as they are, so they can be queued,
+//unless they are compact, in which case they are compacted and sent right away.
+pub(crate) fn points<'a>(
+    sys: &mut SimpleCanvas,
+    verts: &'a [circle_program::Vertex],
+    precision: Precision,
+) -> queue::Vertices<'a> {
+    match precision {
+        Precision::Full => queue::Vertices::Points(verts),
+        _ => {
+            sys.compact_buffer.send_to_gpu(&compact_all(verts, &precision));
+            queue::Vertices::Uploaded(sys.compact_buffer.get_info(verts.len()))
+        }
+    }
+}
+
+pub(crate) fn sprites<'a>(
+    sys: &mut SimpleCanvas,
+    verts: &'a [sprite_program::Vertex],
+    precision: Precision,
+) -> queue::Vertices<'a> {
+    match precision {
+        Precision::Full => queue::Vertices::Sprites(verts),
+        _ => {
+            sys.compact_sprite_buffer.send_to_gpu(&compact_all(verts, &precision));
+            queue::Vertices::Uploaded(sys.compact_sprite_buffer.get_info(verts.len()))
+        }
+    }
+}
+
+pub(crate) fn sprite_format(precision: Precision) -> VertexFormat {
+    match precision {
+        Precision::Full => VertexFormat::Plain,
+        _ => VertexFormat::Compact(precision),
+    }
+}
+
+///The buffer of a save, in the precision it was saved with.
+#[derive(Debug)]
+pub(crate) enum SavedVertices<V: Compact> {
+    Full(vbo::StaticBuffer<V>),
+    Compact(vbo::StaticBuffer<V::Compact>, Precision),
+}
+
+impl<V: Compact> SavedVertices<V> {
+    pub(crate) fn new(verts: &[V], precision: Precision) -> SavedVertices<V> {
+        match precision {
+            Precision::Full => SavedVertices::Full(vbo::StaticBuffer::new(verts)),
+            _ => SavedVertices::Compact(vbo::StaticBuffer::new(&compact_all(verts, &precision)), precision),
+        }
+    }
+
+    pub(crate) fn precision(&self) -> Precision {
+        match self {
+            SavedVertices::Full(_) => Precision::Full,
+            SavedVertices::Compact(_, precision) => *precision,
+        }
+    }
+
+    pub(crate) fn vertices(&self) -> queue::Vertices<'_> {
+        match self {
+            SavedVertices::Full(b) => queue::Vertices::Saved(b.get_info(), b.handle()),
+            SavedVertices::Compact(b, _) => queue::Vertices::Saved(b.get_info(), b.handle()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn half_zeros_and_specials() {
+        assert_eq!(f32_to_f16(0.0), 0x0000);
+        assert_eq!(f32_to_f16(-0.0), 0x8000);
+        assert_eq!(f16_to_f32(0x8000).to_bits(), (-0.0f32).to_bits());
+        assert_eq!(f32_to_f16(1.0), 0x3c00);
+        assert_eq!(f32_to_f16(-2.0), 0xc000);
+        assert_eq!(f32_to_f16(65504.0), 0x7bff);
+
+        assert_eq!(f32_to_f16(f32::NAN), 0x7e00);
+        assert!(f16_to_f32(0x7e00).is_nan());
+        assert_eq!(f16_to_f32(0x7c00), f32::INFINITY);
+    }
+
+    #[test]
+    fn half_overflow_clamps() {
+        //Halfway between the largest half float and infinity.
+        assert_eq!(f32_to_f16(65520.0), 0x7bff);
+        assert_eq!(f32_to_f16(1e10), 0x7bff);
+        assert_eq!(f32_to_f16(-1e10), 0xfbff);
+        assert_eq!(f32_to_f16(f32::INFINITY), 0x7bff);
+        assert_eq!(f32_to_f16(f32::NEG_INFINITY), 0xfbff);
+    }
+
+    #[test]
+    fn half_subnormals() {
+        let sub = |n: f32| n * f32::powi(2.0, -25);
+        assert_eq!(f32_to_f16(sub(2.0)), 0x0001);
+        assert_eq!(f32_to_f16(sub(2046.0)), 0x03ff);
+        assert_eq!(f32_to_f16(sub(2048.0)), 0x0400);
+        //Ties go to even.
+        assert_eq!(f32_to_f16(sub(1.0)), 0x0000);
+        assert_eq!(f32_to_f16(sub(3.0)), 0x0002);
+        assert_eq!(f32_to_f16(-sub(5.0)), 0x8002);
+        assert_eq!(f32_to_f16(sub(1.5)), 0x0001);
+        //Too small to round up to the smallest subnormal.
+        assert_eq!(f32_to_f16(sub(0.5)), 0x0000);
+        assert_eq!(f32_to_f16(-f32::MIN_POSITIVE), 0x8000);
+    }
+
+    #[test]
+    fn half_ties_to_even() {
+        let ulp = f32::powi(2.0, -10);
+        assert_eq!(f32_to_f16(1.0 + ulp * 0.5), 0x3c00);
+        assert_eq!(f32_to_f16(1.0 + ulp * 1.5), 0x3c02);
+        assert_eq!(f32_to_f16(1.0 + ulp * 0.5 + f32::EPSILON), 0x3c01);
+        assert_eq!(f32_to_f16(1.0 + ulp * 0.5 - f32::EPSILON), 0x3c00);
+        //Rounding up can carry into the exponent.
+        assert_eq!(f32_to_f16(2.0 - ulp * 0.5), 0x4000);
+    }
+
+    #[test]
+    fn half_round_trips() {
+        //Every finite half float converts back to itself.
+        for h in (0..0x7c00).chain(0x8000..0xfc00) {
+            assert_eq!(f32_to_f16(f16_to_f32(h)), h);
+        }
+    }
+
+    #[test]
+    fn quantized_clamps() {
+        let q = Quantized::new([100.0, -50.0], 0.5);
+        let p = Precision::Quantized(q);
+        assert_eq!(p.encode([100.0, -50.0]), [0, 0]);
+        assert_eq!(p.encode([101.0, -51.0]), [2, (-2i16) as u16]);
+
+        //Points past the range in any direction end up at its edge.
+        let edge = MAX_STEPS * 0.5;
+        assert_eq!(p.encode([1e6, -1e6]), [i16::MAX as u16, (-i16::MAX) as u16]);
+        assert_eq!(p.round_trip([1e6, -1e6]), [100.0 + edge, -50.0 - edge]);
+        assert_eq!(p.round_trip([-1e9, 1e9]), [100.0 - edge, -50.0 + edge]);
+        assert_eq!(p.round_trip([100.0 + edge, 0.0]), [100.0 + edge, 0.0]);
+        assert_eq!(p.round_trip([100.0 + edge + 10.0, 0.0]), [100.0 + edge, 0.0]);
+    }
+
+    #[test]
+    fn covering_does_not_clamp() {
+        let rect = [-1000.0, 3000.0, 0.0, 2000.0];
+        let q = Quantized::covering(rect);
+        let p = Precision::Quantized(q);
+        for &corner in [[rect[0], rect[2]], [rect[1], rect[2]], [rect[0], rect[3]], [rect[1], rect[3]]].iter() {
+            let [x, y] = p.encode(corner);
+            assert!((x as i16).unsigned_abs() < i16::MAX as u16);
+            assert!((y as i16).unsigned_abs() < i16::MAX as u16);
+            let back = p.round_trip(corner);
+            assert!((back[0] - corner[0]).abs() <= p.max_error(corner));
+            assert!((back[1] - corner[1]).abs() <= p.max_error(corner));
+        }
+    }
+}
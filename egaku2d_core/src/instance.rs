@@ -0,0 +1,180 @@
+//! Contains instanced drawing.
+//!
+//! Any save or session can be drawn many times in one draw call by passing
+//! an array of `Instance`s to `Uniforms::with_instances()`. Every instance has its own
+//! offset, transform and color, which are applied in the vertex shader on top of the buffer.
+//! Instances that stay the same from frame to frame can be uploaded once into an `InstanceSave`,
+//! and drawn with `Uniforms::with_saved_instances()`.
+
+use super::*;
+use crate::vbo::BufferInfo;
+use std::ffi::CString;
+
+///One copy of the drawn buffer.
+///The transform is applied first, then the offset is added, then the transform of `Uniforms::with_transform()` is applied.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Instance {
+    pub offset: [f32; 2],
+    ///The column major rotation and scale.
+    pub matrix: [[f32; 2]; 2],
+    ///Multiplied with the color of the canvas.
+    pub color: [f32; 4],
+}
+
+impl Default for Instance {
+    fn default() -> Self {
+        Instance::new([0.0, 0.0])
+    }
+}
+
+impl Instance {
+    pub fn new(offset: [f32; 2]) -> Instance {
+        Instance {
+            offset,
+            matrix: [[1.0, 0.0], [0.0, 1.0]],
+            color: [1.0; 4],
+        }
+    }
+
+    ///Use the rotation and scale of the transform, and add its translation to the offset.
+    ///Projective transforms are not supported.
+    pub fn with_transform(mut self, transform: &transform::Affine2D) -> Instance {
+        let m = transform.matrix();
+        self.matrix = [[m[0][0], m[0][1]], [m[1][0], m[1][1]]];
+        self.offset[0] += m[2][0];
+        self.offset[1] += m[2][1];
+        self
+    }
+
+    pub fn with_color(mut self, color: [f32; 4]) -> Instance {
+        self.color = color;
+        self
+    }
+}
+
+pub struct InstanceSave {
+    _ns: NotSend,
+    buffer: vbo::StaticBuffer<Instance>,
+}
+
+impl InstanceSave {
+    pub fn new(_sys: &mut SimpleCanvas, instances: &[Instance]) -> InstanceSave {
+        InstanceSave {
+            _ns: ns(),
+            buffer: vbo::StaticBuffer::new(instances),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.buffer.get_info().length
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub(crate) fn get_info(&self) -> BufferInfo {
+        self.buffer.get_info()
+    }
+}
+
+//The instance attributes every vertex shader has.
+#[derive(Debug)]
+pub(crate) struct InstanceAttrs {
+    offset: GLint,
+    matrix: GLint,
+    color: GLint,
+}
+
+impl InstanceAttrs {
+    pub(crate) unsafe fn new(program: GLuint) -> InstanceAttrs {
+        let temp = CString::new("instance_offset").unwrap();
+        let offset = gl::GetAttribLocation(program, temp.as_ptr());
+        gl_ok!();
+
+        let temp = CString::new("instance_matrix").unwrap();
+        let matrix = gl::GetAttribLocation(program, temp.as_ptr());
+        gl_ok!();
+
+        let temp = CString::new("instance_color").unwrap();
+        let color = gl::GetAttribLocation(program, temp.as_ptr());
+        gl_ok!();
+
+        InstanceAttrs {
+            offset,
+            matrix,
+            color,
+        }
+    }
+
+    fn attrs(&self) -> [(GLint, i32, usize); 3] {
+        [(self.offset, 2, 0), (self.matrix, 4, 4 * 2), (self.color, 4, 4 * 6)]
+    }
+
+    ///Point the attributes at the instance buffer, or give every vertex the default instance.
+    ///Binds the instance buffer, so call it after the vertex attributes are set up.
+    pub(crate) unsafe fn enable(&self, instances: Option<BufferInfo>) {
+        match instances {
+            Some(info) => {
+                gl::BindBuffer(gl::ARRAY_BUFFER, info.id);
+                gl_ok!();
+
+                for &(attr, size, offset) in self.attrs().iter() {
+                    gl::EnableVertexAttribArray(attr as GLuint);
+                    gl_ok!();
+
+                    gl::VertexAttribPointer(
+                        attr as GLuint,
+                        size,
+                        gl::FLOAT,
+                        gl::FALSE,
+                        core::mem::size_of::<Instance>() as i32,
+                        (offset + info.first * core::mem::size_of::<Instance>()) as *const _,
+                    );
+                    gl_ok!();
+
+                    gl::VertexAttribDivisor(attr as GLuint, 1);
+                    gl_ok!();
+                }
+            }
+            None => {
+                //With the attribute arrays disabled, every vertex gets these constant values.
+                gl::VertexAttrib2f(self.offset as GLuint, 0.0, 0.0);
+                gl_ok!();
+                gl::VertexAttrib4f(self.matrix as GLuint, 1.0, 0.0, 0.0, 1.0);
+                gl_ok!();
+                gl::VertexAttrib4f(self.color as GLuint, 1.0, 1.0, 1.0, 1.0);
+                gl_ok!();
+            }
+        }
+    }
+
+    ///Draw the vertices once, or once per instance.
+    pub(crate) unsafe fn draw(&self, mode: GLenum, vertices: BufferInfo, instances: Option<BufferInfo>) {
+        let first = vertices.first as i32;
+        let length = vertices.length as i32;
+        match instances {
+            Some(info) => {
+                gl::DrawArraysInstanced(mode, first, length, info.length as i32);
+            }
+            None => {
+                gl::DrawArrays(mode, first, length);
+            }
+        }
+        gl_ok!();
+    }
+
+    ///Undo `enable()`, so the attribute locations can be used by other programs.
+    pub(crate) unsafe fn disable(&self, instances: Option<BufferInfo>) {
+        if instances.is_some() {
+            for &(attr, _, _) in self.attrs().iter() {
+                gl::VertexAttribDivisor(attr as GLuint, 0);
+                gl_ok!();
+
+                gl::DisableVertexAttribArray(attr as GLuint);
+                gl_ok!();
+            }
+        }
+    }
+}
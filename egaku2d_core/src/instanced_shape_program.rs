@@ -0,0 +1,343 @@
+use super::*;
+use crate::gl;
+use crate::shader::*;
+use crate::state::{GlState, UniformValue, VaoKey};
+use crate::vbo::BufferInfo;
+use std::ffi::CString;
+
+// Shader sources
+static VS_SRC: &str = "
+#version 300 es
+in vec2 corner;
+in vec2 record_start;
+in vec2 record_end;
+in vec4 record_color;
+out vec4 vcolor;
+uniform int kind;
+uniform float line_width;
+uniform vec2 offset;
+uniform mat3 mmatrix;
+uniform mat3 transform;
+
+vec2 apply(vec2 p){
+    vec3 t=transform*vec3(p,1.0);
+    return t.xy/t.z;
+}
+
+void main() {
+    vec4 record=vec4(record_start,record_end);
+    vcolor=record_color;
+    vec2 p;
+    if(kind==0){
+        //The record is a rect, [startx,endx,starty,endy], and the corner picks one of its corners.
+        p=apply(vec2(mix(record.x,record.y,corner.x),mix(record.z,record.w,corner.y)));
+    }else{
+        //The record is a line from xy to zw. The corner is how far along the line,
+        //and how many widths out from it.
+        vec2 a=apply(record.xy);
+        vec2 b=apply(record.zw);
+        vec2 d=b-a;
+        vec2 n=vec2(0.0);
+        if(dot(d,d)>0.0){
+            n=normalize(vec2(-d.y,d.x));
+        }
+        p=mix(a,b,corner.x)+n*corner.y*line_width;
+    }
+    gl_Position = vec4(mmatrix*vec3(p+offset,1.0), 1.0);
+}";
+
+static FS_SRC: &str = "
+#version 300 es
+precision mediump float;
+uniform vec4 bcol;
+in vec4 vcolor;
+out vec4 out_color;
+
+void main() {
+    out_color=bcol*vcolor;
+}";
+
+///One rect, or one line or arrow from the first point to the second.
+#[repr(transparent)]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Record(pub [f32; 4]);
+
+///Which template mesh each record is expanded into.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ShapeKind {
+    Rect,
+    Line,
+    Arrow,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct InstancedShapeUniformValues {
+    pub kind: ShapeKind,
+    ///The half width of lines and arrows.
+    pub radius: f32,
+    ///If the records are a batch of user structs.
+    pub batch: Option<batch::Layout>,
+}
+
+//The template meshes. Lines and arrows are the same as what `LineSession` and `ArrowSession` make.
+const RECT_TEMPLATE: [[f32; 2]; 6] = [
+    [1.0, 0.0],
+    [0.0, 0.0],
+    [0.0, 1.0],
+    [0.0, 1.0],
+    [1.0, 1.0],
+    [1.0, 0.0],
+];
+const LINE_TEMPLATE: [[f32; 2]; 6] = [
+    [0.0, 1.0],
+    [0.0, -1.0],
+    [1.0, 1.0],
+    [0.0, -1.0],
+    [1.0, 1.0],
+    [1.0, -1.0],
+];
+const ARROW_TEMPLATE: [[f32; 2]; 9] = [
+    [0.0, 1.0],
+    [0.0, -1.0],
+    [0.8, 1.0],
+    [0.0, -1.0],
+    [0.8, 1.0],
+    [0.8, -1.0],
+    [1.0, 0.0],
+    [0.8, 2.5],
+    [0.8, -2.5],
+];
+
+#[derive(Debug)]
+pub struct InstancedShapeProgram {
+    pub program: GLuint,
+    pub matrix_uniform: GLint,
+    pub transform_uniform: GLint,
+    pub offset_uniform: GLint,
+    pub bcol_uniform: GLint,
+    pub kind_uniform: GLint,
+    pub line_width_uniform: GLint,
+    pub corner_attr: GLint,
+    pub start_attr: GLint,
+    pub end_attr: GLint,
+    pub color_attr: GLint,
+    rect_template: vbo::StaticBuffer<[f32; 2]>,
+    line_template: vbo::StaticBuffer<[f32; 2]>,
+    arrow_template: vbo::StaticBuffer<[f32; 2]>,
+}
+
+impl InstancedShapeProgram {
+    pub fn set_matrix(&mut self, matrix: &[[f32; 3]; 3]) {
+        unsafe {
+            gl::UseProgram(self.program);
+            gl_ok!();
+            gl::UniformMatrix3fv(self.matrix_uniform, 1, 0, matrix[0].as_ptr());
+            gl_ok!();
+        }
+    }
+
+    pub(crate) fn set_buffer_and_draw(
+        &mut self,
+        state: &mut GlState,
+        common: &UniformCommon,
+        un: &InstancedShapeUniformValues,
+        buffer_info: BufferInfo,
+    ) {
+        let col = common.color;
+        let offset = common.offset;
+        let (kind, template) = match un.kind {
+            ShapeKind::Rect => (0, self.rect_template.get_info()),
+            ShapeKind::Line => (1, self.line_template.get_info()),
+            ShapeKind::Arrow => (1, self.arrow_template.get_info()),
+        };
+
+        //Where the two halves of the record, and the color, are in each element of the buffer.
+        let (stride, start, end, color) = match un.batch {
+            Some(layout) => {
+                let (start, end) = match layout.bounds {
+                    Some(bounds) => (bounds, bounds + 8),
+                    None => (layout.position.unwrap(), layout.end.unwrap()),
+                };
+                (layout.size, start, end, layout.color)
+            }
+            None => (core::mem::size_of::<Record>(), 0, 8, None),
+        };
+        let fields = [
+            (self.start_attr, 2, Some(start)),
+            (self.end_attr, 2, Some(end)),
+            (self.color_attr, 4, color),
+        ];
+
+        unsafe {
+            state.use_program(self.program);
+
+            state.uniform(self.offset_uniform, UniformValue::Vec2([offset.x, offset.y]));
+            state.uniform(self.transform_uniform, UniformValue::Mat3(common.transform.matrix()));
+            state.uniform(self.bcol_uniform, UniformValue::Vec4(col));
+            state.uniform(self.kind_uniform, UniformValue::Int(kind));
+            state.uniform(self.line_width_uniform, UniformValue::Float(un.radius));
+
+            //The template and the record buffer together pick the vertex array object.
+            let key = VaoKey {
+                program: self.program,
+                buffer: buffer_info.id,
+                stride: stride as i32,
+                variant: template.id,
+                batch: None,
+            };
+            state.bind_vertex_array(key, || {
+                gl::BindBuffer(gl::ARRAY_BUFFER, template.id);
+                gl_ok!();
+
+                gl::EnableVertexAttribArray(self.corner_attr as GLuint);
+                gl_ok!();
+
+                gl::VertexAttribPointer(
+                    self.corner_attr as GLuint,
+                    2,
+                    gl::FLOAT,
+                    gl::FALSE,
+                    core::mem::size_of::<[f32; 2]>() as i32,
+                    core::ptr::null(),
+                );
+                gl_ok!();
+
+                let mut calls = 3;
+                for &(attr, _, offset) in fields.iter() {
+                    if offset.is_some() {
+                        gl::EnableVertexAttribArray(attr as GLuint);
+                        gl_ok!();
+
+                        gl::VertexAttribDivisor(attr as GLuint, 1);
+                        gl_ok!();
+                        calls += 2;
+                    }
+                }
+                calls
+            });
+
+            //Where the records start changes from draw to draw, so the pointer is always set.
+            gl::BindBuffer(gl::ARRAY_BUFFER, buffer_info.id);
+            gl_ok!();
+
+            let first = buffer_info.first * stride;
+            for &(attr, size, offset) in fields.iter() {
+                if let Some(offset) = offset {
+                    gl::VertexAttribPointer(
+                        attr as GLuint,
+                        size,
+                        gl::FLOAT,
+                        gl::FALSE,
+                        stride as i32,
+                        (first + offset) as *const _,
+                    );
+                    gl_ok!();
+                }
+            }
+
+            if color.is_none() {
+                //With the attribute array disabled, every instance gets this constant value.
+                gl::VertexAttrib4f(self.color_attr as GLuint, 1.0, 1.0, 1.0, 1.0);
+                gl_ok!();
+            }
+
+            gl::DrawArraysInstanced(
+                gl::TRIANGLES,
+                0,
+                template.length as i32,
+                buffer_info.length as i32,
+            );
+            gl_ok!();
+        }
+    }
+
+    pub fn new() -> InstancedShapeProgram {
+        unsafe {
+            // Create GLSL shaders
+            let vs = compile_shader(VS_SRC, gl::VERTEX_SHADER);
+            gl_ok!();
+
+            let fs = compile_shader(FS_SRC, gl::FRAGMENT_SHADER);
+            gl_ok!();
+
+            let program = link_program(vs, fs);
+            gl_ok!();
+
+            gl::DeleteShader(fs);
+            gl_ok!();
+
+            gl::DeleteShader(vs);
+            gl_ok!();
+
+            gl::UseProgram(program);
+            gl_ok!();
+
+            let temp = CString::new("mmatrix").unwrap();
+            let matrix_uniform: GLint = gl::GetUniformLocation(program, temp.as_ptr());
+            gl_ok!();
+
+            let temp = CString::new("transform").unwrap();
+            let transform_uniform: GLint = gl::GetUniformLocation(program, temp.as_ptr());
+            gl_ok!();
+
+            let temp = CString::new("bcol").unwrap();
+            let bcol_uniform: GLint = gl::GetUniformLocation(program, temp.as_ptr());
+            gl_ok!();
+
+            let temp = CString::new("offset").unwrap();
+            let offset_uniform: GLint = gl::GetUniformLocation(program, temp.as_ptr());
+            gl_ok!();
+
+            let temp = CString::new("kind").unwrap();
+            let kind_uniform: GLint = gl::GetUniformLocation(program, temp.as_ptr());
+            gl_ok!();
+
+            let temp = CString::new("line_width").unwrap();
+            let line_width_uniform: GLint = gl::GetUniformLocation(program, temp.as_ptr());
+            gl_ok!();
+
+            let temp = CString::new("corner").unwrap();
+            let corner_attr = gl::GetAttribLocation(program, temp.as_ptr());
+            gl_ok!();
+
+            let temp = CString::new("record_start").unwrap();
+            let start_attr = gl::GetAttribLocation(program, temp.as_ptr());
+            gl_ok!();
+
+            let temp = CString::new("record_end").unwrap();
+            let end_attr = gl::GetAttribLocation(program, temp.as_ptr());
+            gl_ok!();
+
+            let temp = CString::new("record_color").unwrap();
+            let color_attr = gl::GetAttribLocation(program, temp.as_ptr());
+            gl_ok!();
+
+            InstancedShapeProgram {
+                program,
+                matrix_uniform,
+                transform_uniform,
+                offset_uniform,
+                bcol_uniform,
+                kind_uniform,
+                line_width_uniform,
+                corner_attr,
+                start_attr,
+                end_attr,
+                color_attr,
+                rect_template: vbo::StaticBuffer::new(&RECT_TEMPLATE),
+                line_template: vbo::StaticBuffer::new(&LINE_TEMPLATE),
+                arrow_template: vbo::StaticBuffer::new(&ARROW_TEMPLATE),
+            }
+        }
+    }
+}
+
+impl Drop for InstancedShapeProgram {
+    fn drop(&mut self) {
+        // Cleanup
+        unsafe {
+            gl::DeleteProgram(self.program);
+            gl_ok!();
+        }
+    }
+}
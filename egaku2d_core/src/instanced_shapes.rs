@@ -0,0 +1,187 @@
+//! Contains the instanced rect, line and arrow sessions.
+//!
+//! These work the same as the sessions in `shapes`, but only upload one record of four floats per shape,
+//! instead of six or nine vertices. The shader expands every record into a template mesh
+//! that is shared by all of them, so they are cheaper to send every frame when there are many of them.
+//! They can not be drawn with `Uniforms::with_instances()`, since they are already instanced.
+
+use super::*;
+use instanced_shape_program::{InstancedShapeUniformValues, Record, ShapeKind};
+
+fn uniforms(
+    sys: &mut SimpleCanvas,
+    kind: ShapeKind,
+    radius: f32,
+    offset: Vec2<f32>,
+    buffer: vbo::BufferInfo,
+) -> Uniforms<'_> {
+    let common = sys.common(offset);
+    let un = InstancedShapeUniformValues {
+        kind,
+        radius,
+        batch: None,
+    };
+    Uniforms {
+        sys,
+        common,
+        un: UniformVals::InstancedShape(un),
+        buffer: queue::Vertices::Uploaded(buffer),
+    }
+}
+
+pub struct InstancedRectSave {
+    _ns: NotSend,
+    buffer: vbo::StaticBuffer<Record>,
+}
+
+impl InstancedRectSave {
+    pub fn uniforms<'a>(&'a self, sys: &'a mut SimpleCanvas) -> Uniforms<'a> {
+        let offset = sys.offset;
+        uniforms(sys, ShapeKind::Rect, 0.0, offset, self.buffer.get_info())
+    }
+}
+
+pub struct InstancedRectSession {
+    pub(crate) records: Vec<Record>,
+}
+
+impl Default for InstancedRectSession {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl InstancedRectSession {
+    pub fn new() -> Self {
+        InstancedRectSession {
+            records: Vec::new(),
+        }
+    }
+
+    pub fn save(&mut self, _sys: &mut SimpleCanvas) -> InstancedRectSave {
+        InstancedRectSave {
+            _ns: ns(),
+            buffer: vbo::StaticBuffer::new(&self.records),
+        }
+    }
+
+    pub fn append(&mut self, other: &mut Self) {
+        self.records.append(&mut other.records);
+    }
+
+    pub fn send_and_uniforms<'a>(&'a mut self, sys: &'a mut SimpleCanvas) -> Uniforms<'a> {
+        sys.instanced_shape_buffer.send_to_gpu(&self.records);
+        let buffer = sys.instanced_shape_buffer.get_info(self.records.len());
+        uniforms(sys, ShapeKind::Rect, 0.0, vec2same(0.0), buffer)
+    }
+
+    ///The rect is `[startx,endx,starty,endy]`.
+    #[inline(always)]
+    pub fn add(&mut self, rect: [f32; 4]) -> &mut Self {
+        self.records.push(Record(rect));
+        self
+    }
+}
+
+pub struct InstancedLineSave {
+    _ns: NotSend,
+    radius: f32,
+    buffer: vbo::StaticBuffer<Record>,
+}
+
+impl InstancedLineSave {
+    pub fn uniforms<'a>(&'a self, sys: &'a mut SimpleCanvas) -> Uniforms<'a> {
+        let offset = sys.offset;
+        uniforms(sys, ShapeKind::Line, self.radius, offset, self.buffer.get_info())
+    }
+}
+
+pub struct InstancedLineSession {
+    pub(crate) radius: f32,
+    pub(crate) records: Vec<Record>,
+}
+
+impl InstancedLineSession {
+    pub fn new(radius: f32) -> Self {
+        InstancedLineSession {
+            radius,
+            records: Vec::new(),
+        }
+    }
+
+    pub fn save(&mut self, _sys: &mut SimpleCanvas) -> InstancedLineSave {
+        InstancedLineSave {
+            _ns: ns(),
+            radius: self.radius,
+            buffer: vbo::StaticBuffer::new(&self.records),
+        }
+    }
+
+    pub fn append(&mut self, other: &mut Self) {
+        self.records.append(&mut other.records);
+    }
+
+    pub fn send_and_uniforms<'a>(&'a mut self, sys: &'a mut SimpleCanvas) -> Uniforms<'a> {
+        sys.instanced_shape_buffer.send_to_gpu(&self.records);
+        let buffer = sys.instanced_shape_buffer.get_info(self.records.len());
+        let offset = sys.offset;
+        uniforms(sys, ShapeKind::Line, self.radius, offset, buffer)
+    }
+
+    #[inline(always)]
+    pub fn add(&mut self, start: PointType, end: PointType) -> &mut Self {
+        self.records.push(Record([start[0], start[1], end[0], end[1]]));
+        self
+    }
+}
+
+pub struct InstancedArrowSave {
+    _ns: NotSend,
+    radius: f32,
+    buffer: vbo::StaticBuffer<Record>,
+}
+
+impl InstancedArrowSave {
+    pub fn uniforms<'a>(&'a self, sys: &'a mut SimpleCanvas) -> Uniforms<'a> {
+        let offset = sys.offset;
+        uniforms(sys, ShapeKind::Arrow, self.radius, offset, self.buffer.get_info())
+    }
+}
+
+pub struct InstancedArrowSession {
+    pub(crate) radius: f32,
+    pub(crate) records: Vec<Record>,
+}
+
+impl InstancedArrowSession {
+    pub fn new(radius: f32) -> Self {
+        InstancedArrowSession {
+            radius,
+            records: Vec::new(),
+        }
+    }
+
+    pub fn save(&mut self, _sys: &mut SimpleCanvas) -> InstancedArrowSave {
+        InstancedArrowSave {
+            _ns: ns(),
+            radius: self.radius,
+            buffer: vbo::StaticBuffer::new(&self.records),
+        }
+    }
+
+    pub fn append(&mut self, other: &mut Self) {
+        self.records.append(&mut other.records);
+    }
+
+    pub fn send_and_uniforms<'a>(&'a mut self, sys: &'a mut SimpleCanvas) -> Uniforms<'a> {
+        sys.instanced_shape_buffer.send_to_gpu(&self.records);
+        let buffer = sys.instanced_shape_buffer.get_info(self.records.len());
+        uniforms(sys, ShapeKind::Arrow, self.radius, vec2same(0.0), buffer)
+    }
+
+    #[inline(always)]
+    pub fn add(&mut self, start: PointType, end: PointType) -> &mut Self {
+        self.records.push(Record([start[0], start[1], end[0], end[1]]));
+        self
+    }
+}
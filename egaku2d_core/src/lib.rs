@@ -24,11 +24,33 @@ mod shader;
 pub mod sprite;
 mod vbo;
 
+mod state;
+
+///Contains the texture array sprite drawing code.
+pub mod sprite_array;
+
+pub mod text;
+
+pub mod tilemap;
+
+#[cfg(feature = "tiled")]
+pub mod tiled;
+
+pub mod assets;
+
+pub mod camera;
+
+pub mod transform;
+
+pub mod instance;
+
+pub mod queue;
+
 ///Macro that asserts that there are no opengl errors.
 #[macro_export]
 macro_rules! gl_ok {
     () => {
-        assert_eq!(gl::GetError(), gl::NO_ERROR);
+        assert_eq!(gl::GetError(), gl::NO_ERROR)
     };
 }
 
@@ -49,6 +71,21 @@ pub mod batch;
 
 mod textured_shape_program;
 
+mod uv_program;
+
+mod instanced_shape_program;
+
+///Contains the instanced rect, line and arrow sessions.
+pub mod instanced_shapes;
+
+///Contains the retained objects, whose elements can be changed one at a time.
+pub mod retained;
+
+pub mod compact;
+
+///Contains the nine-slice panel drawing session and save objects.
+pub mod nine_slice;
+
 ///All the opengl functions generated from the gl_generator crate.
 pub mod gl {
     include!(concat!(env!("OUT_DIR"), "/bindings.rs"));
@@ -61,6 +98,10 @@ pub mod shapes;
 use self::uniforms::UniformCommon;
 use self::uniforms::*;
 
+pub use vbo::StreamStats;
+
+pub use state::GlStats;
+
 ///Represents a Vec2 with the specified aspect ratio
 ///and the specified width. The height of the Vec2
 ///can be inferred by the aspect ratio.
@@ -109,10 +150,70 @@ pub mod uniforms {
         pub(crate) sys: &'a mut SimpleCanvas,
         pub(crate) un: UniformVals<'a>,
         pub(crate) common: UniformCommon,
-        pub(crate) buffer: BufferInfo,
+        pub(crate) buffer: queue::Vertices<'a>,
+    }
+
+    ///What units the sizes passed to sessions and saves are in.
+    ///This covers the radius of circles, squares and sprites, the size of text, and the radius of lines and arrows.
+    #[derive(Copy, Clone, Debug, PartialEq, Eq)]
+    pub enum SizeSpace {
+        ///World units, so things grow and shrink with the zoom of the camera.
+        World,
+        ///Pixels of the window, so things stay the same size on screen no matter the zoom.
+        Screen,
+    }
+
+    ///How the colors drawn are combined with what is already drawn.
+    #[derive(Copy, Clone, Debug, PartialEq, Eq)]
+    pub enum BlendMode {
+        ///Blend by the alpha of the color drawn.
+        Alpha,
+        ///Add the color drawn, weighted by its alpha. Good for lights and glows.
+        Additive,
+        ///Multiply with what is already drawn, ignoring alpha. Good for shadows.
+        Multiply,
+    }
+
+    impl BlendMode {
+        pub(crate) fn factors(self) -> (GLenum, GLenum) {
+            match self {
+                BlendMode::Alpha => (gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA),
+                BlendMode::Additive => (gl::SRC_ALPHA, gl::ONE),
+                BlendMode::Multiply => (gl::DST_COLOR, gl::ZERO),
+            }
+        }
     }
 
     impl<'a> Uniforms<'a> {
+        ///Override the size space of the canvas for this draw.
+        pub fn with_size_space(&mut self, size_space: SizeSpace) -> &mut Self {
+            self.common.size_space = size_space;
+            self
+        }
+
+        ///Transform the positions of the buffer before the offset is added.
+        ///Replaces any transform set before.
+        ///Lines keep their width, and points like circles and sprites keep their size and rotation.
+        pub fn with_transform(&mut self, transform: transform::Affine2D) -> &mut Self {
+            self.common.transform = transform;
+            self
+        }
+
+        ///Draw the buffer once for every instance, in one draw call.
+        ///The instances are uploaded right away, so the slice does not need to outlive the uniforms.
+        ///Ignored by the sessions in `instanced_shapes`, which are already instanced.
+        pub fn with_instances(&mut self, instances: &[instance::Instance]) -> &mut Self {
+            self.sys.instance_buffer.send_to_gpu(instances);
+            self.common.instances = Some(self.sys.instance_buffer.get_info(instances.len()));
+            self
+        }
+
+        ///Draw the buffer once for every saved instance, in one draw call.
+        pub fn with_saved_instances(&mut self, instances: &'a instance::InstanceSave) -> &mut Self {
+            self.common.instances = Some(instances.get_info());
+            self
+        }
+
         pub fn with_offset(&mut self, offset: [f32; 2]) -> &mut Self {
             self.common.offset += vec2(offset[0], offset[1]);
             self
@@ -140,56 +241,60 @@ pub mod uniforms {
                 UniformVals::Circle(s) => {
                     s.texture = Some((texture, scale, offset));
                 }
+                UniformVals::Uv(s) => {
+                    s.texture = texture;
+                }
+                //A texture array has to be passed when the uniforms are made.
+                UniformVals::SpriteArray(_) => {}
+                UniformVals::InstancedShape(_) => {}
             }
             self
         }
 
+        ///Draw with a different blend mode than `BlendMode::Alpha`.
+        pub fn with_blend(&mut self, blend: BlendMode) -> &mut Self {
+            self.common.blend = blend;
+            self
+        }
+
         pub fn draw(&mut self) {
-            match &self.un {
-                UniformVals::Sprite(a) => {
-                    self.sys
-                        .sprite_program
-                        .set_buffer_and_draw(&self.common, a, self.buffer);
-                }
-                UniformVals::Regular(a) => {
-                    if a.texture.is_some() {
-                        self.sys.textured_shape_program.set_buffer_and_draw(
-                            &self.common,
-                            a,
-                            self.buffer,
-                        );
-                    } else {
-                        self.sys
-                            .regular_program
-                            .set_buffer_and_draw(&self.common, a, self.buffer);
-                    }
-                }
-                UniformVals::Circle(a) => {
-                    if a.texture.is_some() {
-                        self.sys.textured_circle_program.set_buffer_and_draw(
-                            &self.common,
-                            a,
-                            self.buffer,
-                        );
-                    } else {
-                        self.sys
-                            .circle_program
-                            .set_buffer_and_draw(&self.common, a, self.buffer);
-                    }
-                }
+            let buffer = self.buffer.upload(self.sys);
+            self.sys.draw_uniforms(&self.common, self.un, buffer);
+        }
+
+        ///Draw later, when the render queue of the canvas is flushed. Lower layers are drawn first.
+        ///See the `queue` module for what is merged.
+        ///Only sessions and saves of untextured circles, squares, rects, lines and arrows, and of sprites can be queued,
+        ///without instances. Anything else is drawn right away, after flushing the queue so the order is kept.
+        pub fn queue(&mut self, layer: i32) {
+            let mut queue = core::mem::take(&mut self.sys.queue);
+            let queued = queue.push(layer, self);
+            self.sys.queue = queue;
+            if !queued {
+                self.sys.flush();
+                self.draw();
             }
         }
     }
 
+    #[derive(Copy, Clone, PartialEq)]
     pub(crate) struct UniformCommon {
         pub(crate) offset: Vec2<f32>,
         pub(crate) color: [f32; 4],
+        pub(crate) size_space: SizeSpace,
+        pub(crate) transform: transform::Affine2D,
+        pub(crate) instances: Option<BufferInfo>,
+        pub(crate) blend: BlendMode,
     }
 
+    #[derive(Copy, Clone)]
     pub(crate) enum UniformVals<'a> {
         Sprite(SpriteProgramUniformValues<'a>),
+        SpriteArray(sprite_program::SpriteArrayUniformValues<'a>),
         Regular(ProgramUniformValues<'a>),
         Circle(ProgramUniformValues<'a>),
+        Uv(uv_program::UvProgramUniformValues<'a>),
+        InstancedShape(instanced_shape_program::InstancedShapeUniformValues),
     }
 }
 
@@ -199,21 +304,38 @@ pub mod uniforms {
 ///x grows as you go right.
 pub struct SimpleCanvas {
     _ns: NotSend,
+    state: state::GlState,
+    queue: queue::RenderQueue,
     circle_program: CircleProgram,
     regular_program: CircleProgram,
     sprite_program: SpriteProgram,
+    sprite_array_program: SpriteProgram,
     textured_shape_program: textured_shape_program::TexturedShapeProgram,
     textured_circle_program: textured_shape_program::TexturedShapeProgram,
+    uv_program: uv_program::UvProgram,
+    instanced_shape_program: instanced_shape_program::InstancedShapeProgram,
     point_mul: PointMul,
+    camera: camera::Camera2D,
+    window_dim: [f32; 2],
 
     //It is important to note that this buffers might not be empty when a session object is dropped.
     //the buffers are cleared on creation of a session.
     //this allows us to not have to implement Drop for the session to make sure that the buffer is cleared.
     //if they were to implement drop, they would be slightly less egronomic to use.
-    circle_buffer: vbo::GrowableBuffer<circle_program::Vertex>,
-    sprite_buffer: vbo::GrowableBuffer<sprite_program::Vertex>,
+    circle_buffer: vbo::StreamBuffer<circle_program::Vertex>,
+    sprite_buffer: vbo::StreamBuffer<sprite_program::Vertex>,
+    //Sessions that use a compact precision are sent through these.
+    compact_buffer: vbo::StreamBuffer<circle_program::CompactVertex>,
+    compact_sprite_buffer: vbo::StreamBuffer<sprite_program::CompactVertex>,
+    sprite_array_buffer: vbo::StreamBuffer<sprite_program::ArrayVertex>,
+    line_buffer: vbo::StreamBuffer<circle_program::LineVertex>,
+    instance_buffer: vbo::StreamBuffer<instance::Instance>,
+    instanced_shape_buffer: vbo::StreamBuffer<instanced_shape_program::Record>,
+    text_buffer: vbo::StreamBuffer<sprite_program::ColoredVertex>,
+    uv_buffer: vbo::StreamBuffer<uv_program::Vertex>,
     color: [f32; 4], //Default color used
     offset: Vec2<f32>, //Default offset
+    size_space: uniforms::SizeSpace, //Default size space
 }
 
 impl SimpleCanvas {
@@ -225,92 +347,417 @@ impl SimpleCanvas {
         self.color = color;
     }
 
+    ///How much the sessions have uploaded, summed over the vertex buffers of the canvas.
+    ///Saves, batches and retained objects are not counted.
+    pub fn stream_stats(&self) -> StreamStats {
+        let mut stats = self.circle_buffer.stats();
+        stats += self.sprite_buffer.stats();
+        stats += self.compact_buffer.stats();
+        stats += self.compact_sprite_buffer.stats();
+        stats += self.sprite_array_buffer.stats();
+        stats += self.line_buffer.stats();
+        stats += self.instance_buffer.stats();
+        stats += self.instanced_shape_buffer.stats();
+        stats += self.text_buffer.stats();
+        stats += self.uv_buffer.stats();
+        stats
+    }
+
+    ///Set the counters of `stream_stats()` back to zero, for example at the start of every frame.
+    pub fn reset_stream_stats(&mut self) {
+        self.circle_buffer.reset_stats();
+        self.sprite_buffer.reset_stats();
+        self.compact_buffer.reset_stats();
+        self.compact_sprite_buffer.reset_stats();
+        self.sprite_array_buffer.reset_stats();
+        self.line_buffer.reset_stats();
+        self.instance_buffer.reset_stats();
+        self.instanced_shape_buffer.reset_stats();
+        self.text_buffer.reset_stats();
+        self.uv_buffer.reset_stats();
+    }
+
+    ///How many opengl calls the draws of the last finished frame made, and how many the state tracker skipped.
+    pub fn gl_stats(&self) -> GlStats {
+        self.state.last_frame()
+    }
+
+    ///Flush the render queue, and end the frame for `gl_stats()`. Called by `swap_buffers()`.
+    pub fn finish_frame(&mut self) {
+        self.flush();
+        self.state.finish_frame();
+    }
+
+    ///Set what units sizes are in for every draw from now on.
+    ///The default is `SizeSpace::Screen`. It can be overridden per draw with `Uniforms::with_size_space()`.
+    pub fn set_size_space(&mut self, size_space: uniforms::SizeSpace) {
+        self.size_space = size_space;
+    }
+
+    pub fn size_space(&self) -> uniforms::SizeSpace {
+        self.size_space
+    }
+
+    //The uniforms every draw starts with, drawn with the current defaults at this offset.
+    pub(crate) fn common(&self, offset: Vec2<f32>) -> UniformCommon {
+        UniformCommon {
+            color: self.color,
+            offset,
+            size_space: self.size_space,
+            transform: transform::Affine2D::identity(),
+            instances: None,
+            blend: uniforms::BlendMode::Alpha,
+        }
+    }
+
+    ///The rectangle of the world that is currently visible,
+    ///taking into account the global offset.
+    ///If the camera is rotated, this is the smallest rectangle that contains the view.
+    pub fn view_rect(&self) -> Rect<f32> {
+        let mut r = self.camera.visible_rect();
+        r.x.start -= self.offset.x;
+        r.x.end -= self.offset.x;
+        r.y.start -= self.offset.y;
+        r.y.end -= self.offset.y;
+        r
+    }
+
+    ///Fill the window with `game_width` world units across, with the origin in the top left.
+    ///This replaces the camera.
     pub fn set_viewport(&mut self, window_dim: FixedAspectVec2, game_width: f32) {
-        self.point_mul = self.circle_program.set_viewport(window_dim, game_width);
-        let _ = self.regular_program.set_viewport(window_dim, game_width);
-        let _ = self.sprite_program.set_viewport(window_dim, game_width);
-        let _ = self
-            .textured_shape_program
-            .set_viewport(window_dim, game_width);
-        let _ = self
-            .textured_circle_program
-            .set_viewport(window_dim, game_width);
+        let height = window_dim.ratio.height_over_width() * window_dim.width;
+        self.window_dim = [window_dim.width as f32, height as f32];
+        self.set_camera(camera::Camera2D::from_width(self.window_dim, game_width));
     }
 
-    //Unsafe since user might create two instances, both of
-    //which could make opengl calls simultaneously
-    pub unsafe fn new(window_dim: FixedAspectVec2) -> SimpleCanvas {
-        let circle_buffer = vbo::GrowableBuffer::new();
-        let sprite_buffer = vbo::GrowableBuffer::new();
+    pub fn camera(&self) -> &camera::Camera2D {
+        &self.camera
+    }
+
+    ///Look at the world through this camera from now on.
+    ///The sizes of things given in world units are updated to match the zoom.
+    pub fn set_camera(&mut self, camera: camera::Camera2D) {
+        //Whatever was queued was meant for the old camera.
+        self.flush();
+        let matrix = camera.matrix();
+        self.circle_program.set_matrix(&matrix);
+        self.regular_program.set_matrix(&matrix);
+        self.sprite_program.set_matrix(&matrix);
+        self.sprite_array_program.set_matrix(&matrix);
+        self.textured_shape_program.set_matrix(&matrix);
+        self.textured_circle_program.set_matrix(&matrix);
+        self.uv_program.set_matrix(&matrix);
+        self.instanced_shape_program.set_matrix(&matrix);
+        //Setting the matrices switched programs behind the back of the state tracker.
+        self.state.forget_program();
+        self.point_mul = PointMul(camera.zoom);
+
+        //Opengl measures the viewport from the bottom left of the window.
+        let v = camera.viewport;
+        unsafe {
+            gl::Viewport(
+                v[0] as i32,
+                (self.window_dim[1] - v[3]) as i32,
+                (v[1] - v[0]) as i32,
+                (v[3] - v[2]) as i32,
+            );
+            gl_ok!();
+        }
+        self.camera = camera;
+    }
 
-        let mut circle_program = CircleProgram::new(circle_program::CIRCLE_FS_SRC);
+    ///Convert a position in window pixels, like the mouse position, to a world position.
+    pub fn screen_to_world(&self, screen: [f32; 2]) -> [f32; 2] {
+        self.camera.screen_to_world(screen)
+    }
 
-        let mut regular_program = CircleProgram::new(circle_program::REGULAR_FS_SRC);
+    ///Convert a world position to a position in window pixels.
+    pub fn world_to_screen(&self, world: [f32; 2]) -> [f32; 2] {
+        self.camera.world_to_screen(world)
+    }
 
-        let mut textured_shape_program = textured_shape_program::TexturedShapeProgram::new(
+    //Unsafe since user might create two instances, both of
+    //which could make opengl calls simultaneously
+    pub unsafe fn new(window_dim: FixedAspectVec2) -> SimpleCanvas {
+        let circle_buffer = vbo::StreamBuffer::new();
+        let sprite_buffer = vbo::StreamBuffer::new();
+        let compact_buffer = vbo::StreamBuffer::new();
+        let compact_sprite_buffer = vbo::StreamBuffer::new();
+        let sprite_array_buffer = vbo::StreamBuffer::new();
+        let line_buffer = vbo::StreamBuffer::new();
+        let instance_buffer = vbo::StreamBuffer::new();
+        let instanced_shape_buffer = vbo::StreamBuffer::new();
+        let text_buffer = vbo::StreamBuffer::new();
+        let uv_buffer = vbo::StreamBuffer::new();
+
+        let circle_program = CircleProgram::new(circle_program::CIRCLE_FS_SRC);
+
+        let regular_program = CircleProgram::new(circle_program::REGULAR_FS_SRC);
+
+        let textured_shape_program = textured_shape_program::TexturedShapeProgram::new(
             textured_shape_program::REGULAR_FS_SRC,
         );
-        let mut textured_circle_program = textured_shape_program::TexturedShapeProgram::new(
+        let textured_circle_program = textured_shape_program::TexturedShapeProgram::new(
             textured_shape_program::CIRCLE_FS_SRC,
         );
 
-        let mut sprite_program = SpriteProgram::new();
+        let sprite_program = SpriteProgram::new();
+        let sprite_array_program = SpriteProgram::new_array();
 
-        let point_mul = circle_program.set_viewport(window_dim, window_dim.width as f32);
-        let _ = regular_program.set_viewport(window_dim, window_dim.width as f32);
-        let _ = sprite_program.set_viewport(window_dim, window_dim.width as f32);
-        let _ = textured_shape_program.set_viewport(window_dim, window_dim.width as f32);
-        let _ = textured_circle_program.set_viewport(window_dim, window_dim.width as f32);
+        let uv_program = uv_program::UvProgram::new();
 
-        gl::Enable(gl::BLEND);
-        gl_ok!();
-        gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
-        gl_ok!();
+        let instanced_shape_program = instanced_shape_program::InstancedShapeProgram::new();
 
-        SimpleCanvas {
+        let height = window_dim.ratio.height_over_width() * window_dim.width;
+        let window_dim = [window_dim.width as f32, height as f32];
+        let camera = camera::Camera2D::from_width(window_dim, window_dim[0]);
+
+        let mut state = state::GlState::new();
+        state.set_blend(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
+
+        let mut canvas = SimpleCanvas {
             _ns: ns(),
-            point_mul,
+            state,
+            queue: queue::RenderQueue::default(),
+            point_mul: PointMul(camera.zoom),
+            camera,
+            window_dim,
             sprite_program,
+            sprite_array_program,
             regular_program,
             circle_program,
             circle_buffer,
             sprite_buffer,
+            compact_buffer,
+            compact_sprite_buffer,
+            sprite_array_buffer,
+            line_buffer,
+            instance_buffer,
+            instanced_shape_buffer,
+            text_buffer,
+            uv_buffer,
+            uv_program,
+            instanced_shape_program,
             textured_shape_program,
             textured_circle_program,
             color: [1.0; 4],
-            offset: vec2same(0.0)
+            offset: vec2same(0.0),
+            size_space: uniforms::SizeSpace::Screen,
+        };
+        canvas.set_camera(camera);
+        canvas
+    }
+
+    ///How much to multiply sizes in the size space by, to get pixels and world units.
+    fn size_muls(&self, size_space: uniforms::SizeSpace) -> (f32, f32) {
+        let point_mul = self.point_mul.0;
+        match size_space {
+            uniforms::SizeSpace::World => (point_mul, 1.0),
+            uniforms::SizeSpace::Screen => (1.0, 1.0 / point_mul),
+        }
+    }
+
+    fn set_blend(&mut self, blend: uniforms::BlendMode) {
+        let (src, dst) = blend.factors();
+        unsafe {
+            self.state.set_blend(src, dst);
+        }
+    }
+
+    pub(crate) fn draw_uniforms(
+        &mut self,
+        common: &UniformCommon,
+        mut un: UniformVals,
+        buffer: vbo::BufferInfo,
+    ) {
+        self.set_blend(common.blend);
+
+        //Convert the sizes to what the shaders expect.
+        //Point sizes are in pixels, and line widths are in world units.
+        let (to_pixels, to_world) = self.size_muls(common.size_space);
+        match &mut un {
+            UniformVals::Sprite(a) => a.radius *= to_pixels,
+            UniformVals::SpriteArray(a) => a.radius *= to_pixels,
+            UniformVals::Regular(a) | UniformVals::Circle(a) => {
+                if a.extruded {
+                    a.radius *= to_world;
+                } else {
+                    a.radius *= to_pixels;
+                }
+            }
+            UniformVals::InstancedShape(a) => a.radius *= to_world,
+            UniformVals::Uv(_) => {}
+        }
+
+        match &un {
+            UniformVals::Sprite(a) => {
+                self.sprite_program
+                    .set_buffer_and_draw(&mut self.state, common, a, buffer);
+            }
+            UniformVals::Regular(a) => {
+                if a.texture.is_some() {
+                    self.textured_shape_program.set_buffer_and_draw(
+                        &mut self.state,
+                        common,
+                        a,
+                        buffer,
+                    );
+                } else {
+                    self.regular_program
+                        .set_buffer_and_draw(&mut self.state, common, a, buffer);
+                }
+            }
+            UniformVals::SpriteArray(a) => {
+                self.sprite_array_program.set_array_buffer_and_draw(
+                    &mut self.state,
+                    common,
+                    a,
+                    buffer,
+                );
+            }
+            UniformVals::Uv(a) => {
+                self.uv_program
+                    .set_buffer_and_draw(&mut self.state, common, a, buffer);
+            }
+            UniformVals::InstancedShape(a) => {
+                self.instanced_shape_program.set_buffer_and_draw(
+                    &mut self.state,
+                    common,
+                    a,
+                    buffer,
+                );
+            }
+            UniformVals::Circle(a) => {
+                if a.texture.is_some() {
+                    self.textured_circle_program.set_buffer_and_draw(
+                        &mut self.state,
+                        common,
+                        a,
+                        buffer,
+                    );
+                } else {
+                    self.circle_program
+                        .set_buffer_and_draw(&mut self.state, common, a, buffer);
+                }
+            }
+        }
+    }
+
+    ///Draw sprites that were queued, with what was kept of their texture.
+    pub(crate) fn draw_sprite_texture(
+        &mut self,
+        common: &UniformCommon,
+        texture: sprite_program::SpriteTexture,
+        radius: f32,
+        format: VertexFormat,
+        buffer: vbo::BufferInfo,
+    ) {
+        self.set_blend(common.blend);
+        let (to_pixels, _) = self.size_muls(common.size_space);
+        self.sprite_program.draw(
+            &mut self.state,
+            common,
+            texture,
+            radius * to_pixels,
+            format,
+            buffer,
+        );
+    }
+
+    ///Draw the sprites that sessions queue into the layer from the top of the world to the bottom,
+    ///after everything else in the layer. See the `queue` module.
+    pub fn set_y_sort(&mut self, layer: i32, y_sort: bool) {
+        self.queue.set_y_sort(layer, y_sort);
+    }
+
+    ///Draw everything in the render queue. Also done by `swap_buffers()` and `set_camera()`.
+    pub fn flush(&mut self) {
+        if self.queue.is_empty() {
+            return;
         }
+        let mut queue = core::mem::take(&mut self.queue);
+        queue.flush(self);
+        self.queue = queue;
     }
 
     pub fn sprites(&mut self) -> sprite::SpriteSession {
-        sprite::SpriteSession { verts: Vec::new() }
+        sprite::SpriteSession::new()
+    }
+
+    ///Sprites that can come from any layer of a texture array.
+    pub fn sprite_arrays(&mut self) -> sprite_array::SpriteArraySession {
+        sprite_array::SpriteArraySession::new()
+    }
+
+    ///The size is the size of the glyphs, like the radius of sprites.
+    pub fn text(&mut self, size: f32) -> text::TextSession {
+        text::TextSession::new(size)
+    }
+
+    pub fn nine_slices(&mut self, slice: nine_slice::NineSlice) -> nine_slice::NineSliceSession {
+        nine_slice::NineSliceSession::new(slice)
     }
 
     pub fn circles(&mut self) -> CircleSession {
-        CircleSession { verts: Vec::new() }
+        CircleSession::new()
     }
 
     pub fn squares(&mut self) -> SquareSession {
-        SquareSession { verts: Vec::new() }
+        SquareSession::new()
     }
     pub fn rects(&mut self) -> RectSession {
-        RectSession { verts: Vec::new() }
+        RectSession::new()
     }
+    ///The radius is applied when drawing, in the size space of the draw.
     pub fn arrows(&mut self, radius: f32) -> ArrowSession {
-        let kk = self.point_mul.0;
-
-        ArrowSession {
-            radius: radius * kk,
-            verts: Vec::new(),
-        }
+        ArrowSession::new(radius)
     }
 
+    ///The radius is applied when drawing, in the size space of the draw.
     pub fn lines(&mut self, radius: f32) -> LineSession {
-        let kk = self.point_mul.0;
-        LineSession {
-            radius: radius * kk,
-            verts: Vec::new(),
-        }
+        LineSession::new(radius)
+    }
+
+    ///Circles that are kept on the gpu, and can be changed one at a time. See the `retained` module.
+    pub fn retained_circles(&mut self) -> retained::RetainedCircles {
+        retained::RetainedCircles::new()
+    }
+
+    pub fn retained_squares(&mut self) -> retained::RetainedSquares {
+        retained::RetainedSquares::new()
+    }
+
+    pub fn retained_rects(&mut self) -> retained::RetainedRects {
+        retained::RetainedRects::new()
+    }
+
+    ///The radius is applied when drawing, in the size space of the draw.
+    pub fn retained_lines(&mut self, radius: f32) -> retained::RetainedLines {
+        retained::RetainedLines::new(radius)
+    }
+
+    ///The radius is applied when drawing, in the size space of the draw.
+    pub fn retained_arrows(&mut self, radius: f32) -> retained::RetainedArrows {
+        retained::RetainedArrows::new(radius)
+    }
+
+    pub fn retained_sprites(&mut self) -> retained::RetainedSprites {
+        retained::RetainedSprites::new()
+    }
+
+    ///Like `rects()`, but only uploads one record per rect.
+    pub fn rects_instanced(&mut self) -> instanced_shapes::InstancedRectSession {
+        instanced_shapes::InstancedRectSession::new()
+    }
+
+    ///Like `lines()`, but only uploads one record per line.
+    pub fn lines_instanced(&mut self, radius: f32) -> instanced_shapes::InstancedLineSession {
+        instanced_shapes::InstancedLineSession::new(radius)
+    }
+
+    ///Like `arrows()`, but only uploads one record per arrow.
+    pub fn arrows_instanced(&mut self, radius: f32) -> instanced_shapes::InstancedArrowSession {
+        instanced_shapes::InstancedArrowSession::new(radius)
     }
 
     pub fn clear_color(&mut self, back_color: [f32; 3]) {
@@ -323,16 +770,28 @@ impl SimpleCanvas {
         }
     }
 
-    ///The closure is a function to extract a reference to the
-    ///vertex data for a given object T
-    ///
-    ///This function is unsafe because 
-    ///the reference returned by the closure must be a reference into a member of T.
-    pub unsafe fn batch_circles<T, F: Fn(&T) -> &[f32; 2]>(
-        &mut self,
-        bots: &[T],
-        func: F,
-    ) -> batch::BatchCircle<T, F> {
-        batch::BatchCircle::new(bots, func)
+    ///Draw circles straight from a slice of structs. See the `batch` module.
+    pub fn batch_circles<T: batch::BatchVertex>(&mut self, bots: &[T]) -> batch::BatchCircle<T> {
+        batch::BatchCircle::new(bots)
+    }
+
+    ///Draw squares straight from a slice of structs. See the `batch` module.
+    pub fn batch_squares<T: batch::BatchVertex>(&mut self, bots: &[T]) -> batch::BatchSquare<T> {
+        batch::BatchSquare::new(bots)
+    }
+
+    ///Draw sprites straight from a slice of structs. See the `batch` module.
+    pub fn batch_sprites<T: batch::BatchVertex>(&mut self, bots: &[T]) -> batch::BatchSprite<T> {
+        batch::BatchSprite::new(bots)
+    }
+
+    ///Draw rects straight from a slice of structs. See the `batch` module.
+    pub fn batch_rects<T: batch::BatchVertex>(&mut self, bots: &[T]) -> batch::BatchRect<T> {
+        batch::BatchRect::new(bots)
+    }
+
+    ///Draw lines straight from a slice of structs. See the `batch` module.
+    pub fn batch_lines<T: batch::BatchVertex>(&mut self, bots: &[T]) -> batch::BatchLine<T> {
+        batch::BatchLine::new(bots)
     }
 }
@@ -0,0 +1,276 @@
+//! Contains the nine-slice (9-patch) panel drawing code.
+//!
+//! A nine-slice takes a region of a texture and splits it into a 3x3 grid using border insets.
+//! When drawn into a rectangle, the four corners keep their size, the four edges stretch or tile
+//! along their length, and the center fills the rest. Any number of panels can be added to one session,
+//! and they are all drawn in one draw call.
+//!
+//! The texture coordinates are kept in pixels, and only divided by the size of the texture when drawing,
+//! so panels still line up after the texture is resized.
+
+use super::*;
+use crate::sprite::Texture;
+use uv_program::Vertex;
+
+//The most tiles an edge or the center can be split into along one axis.
+const MAX_TILES: usize = 4096;
+
+///How the edges or the center of a nine-slice fill up their space.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SliceMode {
+    Stretch,
+    Tile,
+}
+
+///Describes how to cut up a texture region.
+#[derive(Copy, Clone, Debug)]
+pub struct NineSlice {
+    region: Rect<f32>,
+    insets: [f32; 4],
+    scale: f32,
+    edge_mode: SliceMode,
+    center_mode: SliceMode,
+}
+
+impl NineSlice {
+    ///The region is `[startx,endx,starty,endy]` in texture pixels.
+    ///The insets are `[left,right,top,bottom]` in texture pixels, measured from the edges of the region.
+    pub fn new(region: [f32; 4], insets: [f32; 4]) -> NineSlice {
+        NineSlice {
+            region: From::from(region),
+            insets,
+            scale: 1.0,
+            edge_mode: SliceMode::Stretch,
+            center_mode: SliceMode::Stretch,
+        }
+    }
+
+    ///Set how many world units one texture pixel takes up in the corners, and when tiling.
+    pub fn with_scale(mut self, scale: f32) -> NineSlice {
+        self.scale = scale;
+        self
+    }
+
+    pub fn with_edge_mode(mut self, mode: SliceMode) -> NineSlice {
+        self.edge_mode = mode;
+        self
+    }
+
+    pub fn with_center_mode(mut self, mode: SliceMode) -> NineSlice {
+        self.center_mode = mode;
+        self
+    }
+
+    fn add_panel(&self, rect: Rect<f32>, verts: &mut Vec<Vertex>) {
+        let [l, r, t, b] = self.insets;
+        let reg = self.region;
+
+        //The corners shrink if the panel is too small to fit them.
+        let fit = |a: f32, b: f32, space: f32| {
+            let total = (a + b) * self.scale;
+            if total > space && total > 0.0 {
+                space / total
+            } else {
+                1.0
+            }
+        };
+        let kx = fit(l, r, rect.x.distance());
+        let ky = fit(t, b, rect.y.distance());
+
+        //Split the texture region and the world rect into three pieces along each axis.
+        let tex_x = [reg.x.start, reg.x.start + l, reg.x.end - r, reg.x.end];
+        let tex_y = [reg.y.start, reg.y.start + t, reg.y.end - b, reg.y.end];
+        let world_x = [
+            rect.x.start,
+            rect.x.start + l * self.scale * kx,
+            rect.x.end - r * self.scale * kx,
+            rect.x.end,
+        ];
+        let world_y = [
+            rect.y.start,
+            rect.y.start + t * self.scale * ky,
+            rect.y.end - b * self.scale * ky,
+            rect.y.end,
+        ];
+
+        for j in 0..3 {
+            for i in 0..3 {
+                let mode_x = match (i, j) {
+                    (1, 1) => self.center_mode,
+                    (1, _) => self.edge_mode,
+                    _ => SliceMode::Stretch,
+                };
+                let mode_y = match (i, j) {
+                    (1, 1) => self.center_mode,
+                    (_, 1) => self.edge_mode,
+                    _ => SliceMode::Stretch,
+                };
+                self.add_patch(
+                    [world_x[i], world_x[i + 1], world_y[j], world_y[j + 1]],
+                    [tex_x[i], tex_x[i + 1], tex_y[j], tex_y[j + 1]],
+                    [mode_x, mode_y],
+                    verts,
+                );
+            }
+        }
+    }
+
+    //Split one axis of a patch into segments of world and texture ranges.
+    fn segments(&self, world: [f32; 2], tex: [f32; 2], mode: SliceMode) -> Vec<([f32; 2], [f32; 2])> {
+        let tex_len = tex[1] - tex[0];
+        let seg_len = tex_len * self.scale;
+        if mode == SliceMode::Stretch || seg_len <= 0.0 {
+            return vec![(world, tex)];
+        }
+
+        //Count the tiles first. Adding the length of a tile over and over can get stuck on large coordinates.
+        //If there would be too many, they are stretched to fit.
+        let space = world[1] - world[0];
+        let wanted = (space / seg_len).ceil();
+        let (num, seg_len) = if wanted > MAX_TILES as f32 {
+            (MAX_TILES, space / MAX_TILES as f32)
+        } else {
+            (wanted.max(1.0) as usize, seg_len)
+        };
+
+        (0..num)
+            .map(|i| {
+                let start = world[0] + i as f32 * seg_len;
+                let end = if i + 1 == num { world[1] } else { start + seg_len };
+                let used = ((end - start) / seg_len).min(1.0);
+                ([start, end], [tex[0], tex[0] + tex_len * used])
+            })
+            .collect()
+    }
+
+    fn add_patch(&self, world: [f32; 4], tex: [f32; 4], mode: [SliceMode; 2], verts: &mut Vec<Vertex>) {
+        if world[1] <= world[0] || world[3] <= world[2] {
+            return;
+        }
+
+        for (wx, tx) in self.segments([world[0], world[1]], [tex[0], tex[1]], mode[0]) {
+            for (wy, ty) in self.segments([world[2], world[3]], [tex[2], tex[3]], mode[1]) {
+                let v = |x: usize, y: usize| Vertex {
+                    pos: [wx[x], wy[y]],
+                    uv: [tx[x], ty[y]],
+                };
+                verts.extend_from_slice(&[v(1, 0), v(0, 0), v(0, 1), v(0, 1), v(1, 1), v(1, 0)]);
+            }
+        }
+    }
+}
+
+pub struct NineSliceSave {
+    _ns: NotSend,
+    buffer: vbo::StaticBuffer<Vertex>,
+}
+
+impl NineSliceSave {
+    pub fn uniforms<'a>(&'a self, sys: &'a mut SimpleCanvas, texture: &'a Texture) -> Uniforms<'a> {
+        let common = sys.common(sys.offset);
+        let un = uv_program::UvProgramUniformValues { texture };
+        Uniforms {
+            sys,
+            common,
+            un: UniformVals::Uv(un),
+            buffer: queue::Vertices::Uploaded(self.buffer.get_info()),
+        }
+    }
+}
+
+pub struct NineSliceSession {
+    pub(crate) slice: NineSlice,
+    pub(crate) verts: Vec<Vertex>,
+}
+
+impl NineSliceSession {
+    pub fn new(slice: NineSlice) -> Self {
+        NineSliceSession {
+            slice,
+            verts: Vec::new(),
+        }
+    }
+
+    ///Add a panel that fills the rect `[startx,endx,starty,endy]` in world space.
+    #[inline(always)]
+    pub fn add(&mut self, rect: [f32; 4]) -> &mut Self {
+        self.slice.add_panel(From::from(rect), &mut self.verts);
+        self
+    }
+
+    pub fn append(&mut self, other: &mut Self) {
+        self.verts.append(&mut other.verts);
+    }
+
+    pub fn save(&mut self, _sys: &mut SimpleCanvas) -> NineSliceSave {
+        NineSliceSave {
+            _ns: ns(),
+            buffer: vbo::StaticBuffer::new(&self.verts),
+        }
+    }
+
+    pub fn send_and_uniforms<'a>(
+        &'a mut self,
+        sys: &'a mut SimpleCanvas,
+        texture: &'a Texture,
+    ) -> Uniforms<'a> {
+        sys.uv_buffer.send_to_gpu(&self.verts);
+
+        let common = sys.common(sys.offset);
+        let un = uv_program::UvProgramUniformValues { texture };
+
+        let buffer = sys.uv_buffer.get_info(self.verts.len());
+        Uniforms {
+            sys,
+            common,
+            un: UniformVals::Uv(un),
+            buffer: queue::Vertices::Uploaded(buffer),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn slice() -> NineSlice {
+        NineSlice::new([0.0, 30.0, 0.0, 30.0], [10.0; 4]).with_edge_mode(SliceMode::Tile)
+    }
+
+    #[test]
+    fn tiles() {
+        let s = slice().with_scale(2.0);
+        let segs = s.segments([0.0, 50.0], [10.0, 20.0], SliceMode::Tile);
+        assert_eq!(
+            segs,
+            vec![
+                ([0.0, 20.0], [10.0, 20.0]),
+                ([20.0, 40.0], [10.0, 20.0]),
+                ([40.0, 50.0], [10.0, 15.0])
+            ]
+        );
+        assert_eq!(s.segments([0.0, 40.0], [10.0, 20.0], SliceMode::Tile).len(), 2);
+        assert_eq!(s.segments([0.0, 50.0], [10.0, 20.0], SliceMode::Stretch), vec![([0.0, 50.0], [10.0, 20.0])]);
+    }
+
+    #[test]
+    fn tiles_far_away() {
+        //Adding 0.001 to 1e9 does not change it.
+        let s = slice().with_scale(0.0001);
+        let segs = s.segments([1e9, 1e9 + 1000.0], [10.0, 20.0], SliceMode::Tile);
+        assert_eq!(segs.len(), MAX_TILES);
+        assert_eq!(segs[0].0[0], 1e9);
+        assert_eq!(segs[MAX_TILES - 1].0[1], 1e9 + 1000.0);
+
+        let segs = s.segments([0.0, 1e9], [10.0, 20.0], SliceMode::Tile);
+        assert_eq!(segs.len(), MAX_TILES);
+    }
+
+    #[test]
+    fn pixel_uvs() {
+        let mut verts = Vec::new();
+        slice().add_panel(Rect::new(0.0, 100.0, 0.0, 20.0), &mut verts);
+        assert!(verts.iter().all(|v| (0.0..=30.0).contains(&v.uv[0]) && (0.0..=30.0).contains(&v.uv[1])));
+        assert!(verts.iter().any(|v| v.uv == [30.0, 30.0]));
+    }
+}
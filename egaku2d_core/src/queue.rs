@@ -0,0 +1,334 @@
+//! Contains the deferred render queue.
+//!
+//! Instead of drawing right away with `Uniforms::draw()`, the uniforms of circle, square, rect, line, arrow
+//! and sprite sessions and saves can be queued with `Uniforms::queue()`. The queue keeps a copy of the vertices
+//! of sessions, and keeps the buffers of saves and the textures of sprites alive, and draws everything when it is flushed,
+//! by `SimpleCanvas::flush()` or at `swap_buffers()`.
+//! Sessions with a compact precision are compacted and sent when their uniforms are made, so they can not be queued.
+//! Like anything else that can not be queued, they flush the queue and are drawn right away.
+//!
+//! Every command has a sort key made of its layer, program, texture and blend mode.
+//! Lower layers are drawn first. Within a layer commands are drawn in the order they were queued,
+//! and neighbouring commands with the same key and the same uniforms are merged into one draw call.
+//! All the vertices of one kind are uploaded together in one upload per flush.
+//!
+//! A layer can be y-sorted with `SimpleCanvas::set_y_sort()`, for top-down games. The sprites queued by sessions
+//! into that layer are then drawn after everything else in it, from the top of the world to the bottom,
+//! so sprites lower down are drawn in front. Sprites next to each other in that order that share a texture
+//! are still merged.
+
+use super::*;
+use crate::sprite::QueuedTexture;
+use crate::sprite_program::SpriteTexture;
+use crate::vbo::{BufferHandle, BufferInfo};
+use core::ops::Range;
+
+///Where the vertices of a `Uniforms` are.
+///Sessions that can be queued hand over their vertices, and only upload them when drawn right away.
+#[derive(Copy, Clone)]
+pub(crate) enum Vertices<'a> {
+    Uploaded(BufferInfo),
+    ///The buffer of a save, with the handle that keeps it alive.
+    Saved(BufferInfo, &'a BufferHandle),
+    Points(&'a [circle_program::Vertex]),
+    Lines(&'a [circle_program::LineVertex]),
+    Sprites(&'a [sprite_program::Vertex]),
+}
+
+impl<'a> Vertices<'a> {
+    ///Upload the vertices into the streaming buffers of the canvas, if they are not already uploaded.
+    pub(crate) fn upload(self, sys: &mut SimpleCanvas) -> BufferInfo {
+        match self {
+            Vertices::Uploaded(info) | Vertices::Saved(info, _) => info,
+            Vertices::Points(verts) => {
+                sys.circle_buffer.send_to_gpu(verts);
+                sys.circle_buffer.get_info(verts.len())
+            }
+            Vertices::Lines(verts) => {
+                sys.line_buffer.send_to_gpu(verts);
+                sys.line_buffer.get_info(verts.len())
+            }
+            Vertices::Sprites(verts) => {
+                sys.sprite_buffer.send_to_gpu(verts);
+                sys.sprite_buffer.get_info(verts.len())
+            }
+        }
+    }
+}
+
+///Decides the order of layers, and which neighbouring commands can share a draw call.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+struct SortKey {
+    layer: i32,
+    program: u32,
+    texture: GLuint,
+    blend: uniforms::BlendMode,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Draw {
+    Shape {
+        circle: bool,
+        mode: u32,
+        radius: f32,
+        extruded: bool,
+        precision: compact::Precision,
+    },
+    Sprite {
+        texture: SpriteTexture,
+        radius: f32,
+        format: VertexFormat,
+    },
+}
+
+//The vertices of a command. The ranges index into the vertices kept by the queue.
+#[derive(Clone, Debug)]
+enum Source {
+    Points(Range<usize>),
+    Lines(Range<usize>),
+    Sprites(Range<usize>),
+    //The handle only keeps the buffer alive.
+    Saved { info: BufferInfo, _handle: BufferHandle },
+}
+
+#[derive(Clone)]
+struct Command {
+    key: SortKey,
+    common: UniformCommon,
+    draw: Draw,
+    source: Source,
+    //Only keeps the texture of sprites alive.
+    _texture: Option<QueuedTexture>,
+}
+
+impl Command {
+    fn merges_with(&self, other: &Command) -> bool {
+        let same_kind = match (&self.source, &other.source) {
+            (Source::Points(_), Source::Points(_))
+            | (Source::Lines(_), Source::Lines(_))
+            | (Source::Sprites(_), Source::Sprites(_)) => true,
+            //Saves are in buffers of their own.
+            _ => false,
+        };
+        same_kind && self.key == other.key && self.draw == other.draw && self.common == other.common
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct RenderQueue {
+    points: Vec<circle_program::Vertex>,
+    lines: Vec<circle_program::LineVertex>,
+    sprites: Vec<sprite_program::Vertex>,
+    commands: Vec<Command>,
+    y_sorted: Vec<i32>,
+}
+
+impl RenderQueue {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.commands.is_empty()
+    }
+
+    pub(crate) fn set_y_sort(&mut self, layer: i32, y_sort: bool) {
+        self.y_sorted.retain(|&l| l != layer);
+        if y_sort {
+            self.y_sorted.push(layer);
+        }
+    }
+
+    ///Copy the vertices of the uniforms into the queue. Returns false if they can not be queued.
+    pub(crate) fn push(&mut self, layer: i32, uniforms: &Uniforms) -> bool {
+        let common = uniforms.common;
+        //The queue has no way to keep the instances alive until it is flushed.
+        if common.instances.is_some() {
+            return false;
+        }
+
+        let (draw, program, texture, queued) = match uniforms.un {
+            UniformVals::Regular(a) | UniformVals::Circle(a) if a.texture.is_none() => {
+                let circle = matches!(uniforms.un, UniformVals::Circle(_));
+                let draw = Draw::Shape {
+                    circle,
+                    mode: a.mode,
+                    radius: a.radius,
+                    extruded: a.extruded,
+                    precision: a.precision,
+                };
+                let program = if circle {
+                    uniforms.sys.circle_program.program
+                } else {
+                    uniforms.sys.regular_program.program
+                };
+                (draw, program, 0, None)
+            }
+            UniformVals::Sprite(a) => {
+                let texture = SpriteTexture::new(a.texture);
+                let draw = Draw::Sprite {
+                    texture,
+                    radius: a.radius,
+                    format: a.format,
+                };
+                let queued = QueuedTexture::new(a.texture);
+                (draw, uniforms.sys.sprite_program.program, texture.id, Some(queued))
+            }
+            _ => return false,
+        };
+
+        let source = match uniforms.buffer {
+            Vertices::Points(verts) => Source::Points(append(&mut self.points, verts)),
+            Vertices::Lines(verts) => Source::Lines(append(&mut self.lines, verts)),
+            Vertices::Sprites(verts) => Source::Sprites(append(&mut self.sprites, verts)),
+            Vertices::Saved(info, handle) => Source::Saved {
+                info,
+                _handle: handle.clone(),
+            },
+            Vertices::Uploaded(_) => return false,
+        };
+
+        self.commands.push(Command {
+            key: SortKey {
+                layer,
+                program,
+                texture,
+                blend: common.blend,
+            },
+            common,
+            draw,
+            source,
+            _texture: queued,
+        });
+        true
+    }
+
+    //Split the sprites of y-sorted layers into one command per sprite,
+    //and move them to the end of their layer, from the top to the bottom.
+    fn y_sort(&mut self) {
+        let commands = core::mem::take(&mut self.commands);
+        let mut sorted: Vec<(f32, Command)> = Vec::new();
+        for c in commands {
+            if let Some((_, s)) = sorted.first() {
+                if s.key.layer != c.key.layer {
+                    self.append_sorted(&mut sorted);
+                }
+            }
+            match &c.source {
+                Source::Sprites(range) if self.y_sorted.contains(&c.key.layer) => {
+                    for i in range.clone() {
+                        //Sort by where the sprite ends up in the world.
+                        let pos = c.common.transform.transform_point(self.sprites[i].pos);
+                        let y = pos[1] + c.common.offset.y;
+                        let mut single = c.clone();
+                        single.source = Source::Sprites(i..i + 1);
+                        sorted.push((y, single));
+                    }
+                }
+                _ => self.commands.push(c),
+            }
+        }
+        self.append_sorted(&mut sorted);
+    }
+
+    fn append_sorted(&mut self, sorted: &mut Vec<(f32, Command)>) {
+        //A stable sort, so sprites at the same height keep the order they were queued in.
+        sorted.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(core::cmp::Ordering::Equal));
+        self.commands.extend(sorted.drain(..).map(|(_, c)| c));
+    }
+
+    ///Draw and clear everything in the queue.
+    pub(crate) fn flush(&mut self, sys: &mut SimpleCanvas) {
+        if self.commands.is_empty() {
+            return;
+        }
+
+        //A stable sort, so the order of submission is kept within a layer.
+        self.commands.sort_by_key(|c| c.key.layer);
+        if !self.y_sorted.is_empty() {
+            self.y_sort();
+        }
+
+        //Merge neighbouring commands, and lay out their vertices in the order they are drawn.
+        let mut points = Vec::with_capacity(self.points.len());
+        let mut lines = Vec::with_capacity(self.lines.len());
+        let mut sprites = Vec::with_capacity(self.sprites.len());
+        let mut runs: Vec<(usize, Range<usize>)> = Vec::new();
+        for (i, c) in self.commands.iter().enumerate() {
+            let range = match &c.source {
+                Source::Points(r) => append(&mut points, &self.points[r.clone()]),
+                Source::Lines(r) => append(&mut lines, &self.lines[r.clone()]),
+                Source::Sprites(r) => append(&mut sprites, &self.sprites[r.clone()]),
+                Source::Saved { info, .. } => 0..info.length,
+            };
+            match runs.last_mut() {
+                Some((first, run)) if self.commands[*first].merges_with(c) => run.end = range.end,
+                _ => runs.push((i, range)),
+            }
+        }
+
+        let points = upload(&mut sys.circle_buffer, &points);
+        let lines = upload(&mut sys.line_buffer, &lines);
+        let sprites = upload(&mut sys.sprite_buffer, &sprites);
+
+        for (first, range) in runs {
+            let c = &self.commands[first];
+            let base = match &c.source {
+                Source::Points(_) => points,
+                Source::Lines(_) => lines,
+                Source::Sprites(_) => sprites,
+                Source::Saved { info, .. } => *info,
+            };
+            let buffer = BufferInfo {
+                id: base.id,
+                first: base.first + range.start,
+                length: range.end - range.start,
+            };
+            match c.draw {
+                Draw::Shape {
+                    circle,
+                    mode,
+                    radius,
+                    extruded,
+                    precision,
+                } => {
+                    let mut un = if extruded {
+                        ProgramUniformValues::new_extruded(radius)
+                    } else {
+                        ProgramUniformValues::new(radius, mode)
+                    };
+                    un.precision = precision;
+                    let un = if circle {
+                        UniformVals::Circle(un)
+                    } else {
+                        UniformVals::Regular(un)
+                    };
+                    sys.draw_uniforms(&c.common, un, buffer);
+                }
+                Draw::Sprite {
+                    texture,
+                    radius,
+                    format,
+                } => {
+                    sys.draw_sprite_texture(&c.common, texture, radius, format, buffer);
+                }
+            }
+        }
+
+        self.points.clear();
+        self.lines.clear();
+        self.sprites.clear();
+        //This also drops the handles of the saves, and deletes the textures that were dropped while queued.
+        self.commands.clear();
+    }
+}
+
+fn append<T: Copy>(dst: &mut Vec<T>, src: &[T]) -> Range<usize> {
+    let start = dst.len();
+    dst.extend_from_slice(src);
+    start..dst.len()
+}
+
+fn upload<T>(buffer: &mut vbo::StreamBuffer<T>, verts: &[T]) -> BufferInfo {
+    if verts.is_empty() {
+        return buffer.get_info(0);
+    }
+    buffer.send_to_gpu(verts);
+    buffer.get_info(verts.len())
+}
@@ -0,0 +1,585 @@
+//! Contains the retained circle, square, rect, line, arrow and sprite objects.
+//!
+//! A save can not change once it is made, and a session sends everything again every time it is drawn.
+//! A retained object is in between. Every element added with `insert()` gets a `Handle`, that can later be used
+//! to `set()` or `remove()` that one element. The vertices stay on the gpu, and only the elements that changed
+//! are sent again the next time `uniforms()` is called, so a big scene with a few moving parts stays cheap.
+//!
+//! The elements are kept packed together. Removing an element moves the last element into its place,
+//! so the order the elements are drawn in is not kept. Handles of removed elements are reused by later inserts,
+//! but an old handle never refers to a new element.
+//!
+//! Retained objects can be queued like saves. The changes are sent by `uniforms()`, so a queued draw shows the
+//! elements as they were when it was queued, and changes made after that need a new `uniforms()` call to be drawn.
+//! That call writes into the same buffer, so draw a retained object at most once before the queue is flushed.
+
+use super::*;
+use core::ops::Range;
+
+///Refers to one element of a retained object.
+///A handle only means something to the object that returned it.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Handle {
+    index: u32,
+    generation: u32,
+}
+
+//Where a handle points. The generation goes up every time the slot is freed, so old handles stop working.
+#[derive(Copy, Clone, Debug)]
+struct Slot {
+    generation: u32,
+    element: Option<usize>,
+}
+
+//The vertices on the cpu side, and which elements changed since they were last sent.
+//Every element is the same number of vertices.
+#[derive(Debug)]
+struct Elements<V> {
+    per: usize,
+    verts: Vec<V>,
+    slots: Vec<Slot>,
+    //Slots that are not used, to be reused by inserts.
+    free: Vec<u32>,
+    //The slot of every element, in the order they are in the buffer.
+    owners: Vec<u32>,
+    changed: Vec<usize>,
+}
+
+impl<V: Copy> Elements<V> {
+    fn new(per: usize) -> Elements<V> {
+        Elements {
+            per,
+            verts: Vec::new(),
+            slots: Vec::new(),
+            free: Vec::new(),
+            owners: Vec::new(),
+            changed: Vec::new(),
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.owners.len()
+    }
+
+    fn element(&self, handle: Handle) -> Option<usize> {
+        self.slots
+            .get(handle.index as usize)
+            .filter(|s| s.generation == handle.generation)
+            .and_then(|s| s.element)
+    }
+
+    fn insert(&mut self, verts: &[V]) -> Handle {
+        debug_assert_eq!(verts.len(), self.per);
+        let element = self.owners.len();
+        let index = match self.free.pop() {
+            Some(index) => index,
+            None => {
+                self.slots.push(Slot {
+                    generation: 0,
+                    element: None,
+                });
+                (self.slots.len() - 1) as u32
+            }
+        };
+        let slot = &mut self.slots[index as usize];
+        slot.element = Some(element);
+        self.owners.push(index);
+        self.verts.extend_from_slice(verts);
+        self.changed.push(element);
+        Handle {
+            index,
+            generation: slot.generation,
+        }
+    }
+
+    fn set(&mut self, handle: Handle, verts: &[V]) -> bool {
+        match self.element(handle) {
+            Some(element) => {
+                let per = self.per;
+                self.verts[element * per..(element + 1) * per].copy_from_slice(verts);
+                self.changed.push(element);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn remove(&mut self, handle: Handle) -> bool {
+        let element = match self.element(handle) {
+            Some(element) => element,
+            None => return false,
+        };
+        let slot = &mut self.slots[handle.index as usize];
+        slot.element = None;
+        slot.generation = slot.generation.wrapping_add(1);
+        self.free.push(handle.index);
+
+        //Move the last element into the hole, so the elements stay packed.
+        let per = self.per;
+        let last = self.owners.len() - 1;
+        if element != last {
+            self.verts.copy_within(last * per..(last + 1) * per, element * per);
+            let moved = self.owners[last];
+            self.owners[element] = moved;
+            self.slots[moved as usize].element = Some(element);
+            self.changed.push(element);
+        }
+        self.owners.pop();
+        self.verts.truncate(last * per);
+        true
+    }
+
+    fn clear(&mut self) {
+        for &index in self.owners.iter() {
+            let slot = &mut self.slots[index as usize];
+            slot.element = None;
+            slot.generation = slot.generation.wrapping_add(1);
+            self.free.push(index);
+        }
+        self.owners.clear();
+        self.verts.clear();
+        self.changed.clear();
+    }
+
+    //The vertices that changed since the last call, merged into ranges in order.
+    fn take_changes(&mut self) -> Vec<Range<usize>> {
+        let len = self.owners.len();
+        let per = self.per;
+        self.changed.retain(|&e| e < len);
+        self.changed.sort_unstable();
+        self.changed.dedup();
+
+        let mut ranges: Vec<Range<usize>> = Vec::new();
+        for e in self.changed.drain(..) {
+            let range = e * per..(e + 1) * per;
+            match ranges.last_mut() {
+                Some(last) if last.end == range.start => last.end = range.end,
+                _ => ranges.push(range),
+            }
+        }
+        ranges
+    }
+}
+
+#[derive(Debug)]
+struct Storage<V> {
+    _ns: NotSend,
+    elements: Elements<V>,
+    buffer: vbo::DynamicBuffer<V>,
+}
+
+impl<V: Copy> Storage<V> {
+    fn new(per: usize) -> Storage<V> {
+        Storage {
+            _ns: ns(),
+            elements: Elements::new(per),
+            buffer: vbo::DynamicBuffer::new(),
+        }
+    }
+
+    //Send what changed, and point at the whole buffer.
+    fn upload(&mut self) -> queue::Vertices<'_> {
+        let changes = self.elements.take_changes();
+        self.buffer.update(&self.elements.verts, &changes);
+        let info = self.buffer.get_info(self.elements.verts.len());
+        queue::Vertices::Saved(info, self.buffer.handle())
+    }
+}
+
+fn uniforms<'a>(sys: &'a mut SimpleCanvas, un: UniformVals<'a>, buffer: queue::Vertices<'a>) -> Uniforms<'a> {
+    let common = sys.common(sys.offset);
+    Uniforms {
+        sys,
+        common,
+        un,
+        buffer,
+    }
+}
+
+pub struct RetainedCircles {
+    storage: Storage<circle_program::Vertex>,
+}
+
+impl RetainedCircles {
+    pub(crate) fn new() -> RetainedCircles {
+        RetainedCircles {
+            storage: Storage::new(1),
+        }
+    }
+
+    pub fn insert(&mut self, point: PointType) -> Handle {
+        self.storage.elements.insert(&[circle_program::Vertex(point)])
+    }
+
+    ///Returns false if the element was removed.
+    pub fn set(&mut self, handle: Handle, point: PointType) -> bool {
+        self.storage.elements.set(handle, &[circle_program::Vertex(point)])
+    }
+
+    ///Returns false if the element was already removed.
+    pub fn remove(&mut self, handle: Handle) -> bool {
+        self.storage.elements.remove(handle)
+    }
+
+    pub fn contains(&self, handle: Handle) -> bool {
+        self.storage.elements.element(handle).is_some()
+    }
+
+    pub fn len(&self) -> usize {
+        self.storage.elements.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    ///Remove every element.
+    pub fn clear(&mut self) {
+        self.storage.elements.clear();
+    }
+
+    pub fn uniforms<'a>(&'a mut self, sys: &'a mut SimpleCanvas, radius: f32) -> Uniforms<'a> {
+        let un = ProgramUniformValues::new(radius, gl::POINTS);
+        uniforms(sys, UniformVals::Circle(un), self.storage.upload())
+    }
+}
+
+pub struct RetainedSquares {
+    storage: Storage<circle_program::Vertex>,
+}
+
+impl RetainedSquares {
+    pub(crate) fn new() -> RetainedSquares {
+        RetainedSquares {
+            storage: Storage::new(1),
+        }
+    }
+
+    pub fn insert(&mut self, point: PointType) -> Handle {
+        self.storage.elements.insert(&[circle_program::Vertex(point)])
+    }
+
+    ///Returns false if the element was removed.
+    pub fn set(&mut self, handle: Handle, point: PointType) -> bool {
+        self.storage.elements.set(handle, &[circle_program::Vertex(point)])
+    }
+
+    ///Returns false if the element was already removed.
+    pub fn remove(&mut self, handle: Handle) -> bool {
+        self.storage.elements.remove(handle)
+    }
+
+    pub fn contains(&self, handle: Handle) -> bool {
+        self.storage.elements.element(handle).is_some()
+    }
+
+    pub fn len(&self) -> usize {
+        self.storage.elements.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    ///Remove every element.
+    pub fn clear(&mut self) {
+        self.storage.elements.clear();
+    }
+
+    pub fn uniforms<'a>(&'a mut self, sys: &'a mut SimpleCanvas, radius: f32) -> Uniforms<'a> {
+        let un = ProgramUniformValues::new(radius, gl::POINTS);
+        uniforms(sys, UniformVals::Regular(un), self.storage.upload())
+    }
+}
+
+pub struct RetainedRects {
+    storage: Storage<circle_program::Vertex>,
+}
+
+impl RetainedRects {
+    pub(crate) fn new() -> RetainedRects {
+        RetainedRects {
+            storage: Storage::new(6),
+        }
+    }
+
+    ///The rect is `[startx,endx,starty,endy]`, like `RectSession::add()`.
+    pub fn insert(&mut self, rect: [f32; 4]) -> Handle {
+        self.storage.elements.insert(&RectSession::create_rect(rect))
+    }
+
+    ///Returns false if the element was removed.
+    pub fn set(&mut self, handle: Handle, rect: [f32; 4]) -> bool {
+        self.storage.elements.set(handle, &RectSession::create_rect(rect))
+    }
+
+    ///Returns false if the element was already removed.
+    pub fn remove(&mut self, handle: Handle) -> bool {
+        self.storage.elements.remove(handle)
+    }
+
+    pub fn contains(&self, handle: Handle) -> bool {
+        self.storage.elements.element(handle).is_some()
+    }
+
+    pub fn len(&self) -> usize {
+        self.storage.elements.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    ///Remove every element.
+    pub fn clear(&mut self) {
+        self.storage.elements.clear();
+    }
+
+    pub fn uniforms<'a>(&'a mut self, sys: &'a mut SimpleCanvas) -> Uniforms<'a> {
+        let un = ProgramUniformValues::new(0.0, gl::TRIANGLES);
+        uniforms(sys, UniformVals::Regular(un), self.storage.upload())
+    }
+}
+
+pub struct RetainedLines {
+    radius: f32,
+    storage: Storage<circle_program::LineVertex>,
+}
+
+impl RetainedLines {
+    pub(crate) fn new(radius: f32) -> RetainedLines {
+        RetainedLines {
+            radius,
+            storage: Storage::new(6),
+        }
+    }
+
+    pub fn insert(&mut self, start: PointType, end: PointType) -> Handle {
+        self.storage.elements.insert(&LineSession::create_line(start, end))
+    }
+
+    ///Returns false if the element was removed.
+    pub fn set(&mut self, handle: Handle, start: PointType, end: PointType) -> bool {
+        self.storage.elements.set(handle, &LineSession::create_line(start, end))
+    }
+
+    ///Returns false if the element was already removed.
+    pub fn remove(&mut self, handle: Handle) -> bool {
+        self.storage.elements.remove(handle)
+    }
+
+    pub fn contains(&self, handle: Handle) -> bool {
+        self.storage.elements.element(handle).is_some()
+    }
+
+    pub fn len(&self) -> usize {
+        self.storage.elements.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    ///Remove every element.
+    pub fn clear(&mut self) {
+        self.storage.elements.clear();
+    }
+
+    pub fn uniforms<'a>(&'a mut self, sys: &'a mut SimpleCanvas) -> Uniforms<'a> {
+        let un = ProgramUniformValues::new_extruded(self.radius);
+        uniforms(sys, UniformVals::Regular(un), self.storage.upload())
+    }
+}
+
+pub struct RetainedArrows {
+    radius: f32,
+    storage: Storage<circle_program::LineVertex>,
+}
+
+impl RetainedArrows {
+    pub(crate) fn new(radius: f32) -> RetainedArrows {
+        RetainedArrows {
+            radius,
+            storage: Storage::new(9),
+        }
+    }
+
+    pub fn insert(&mut self, start: PointType, end: PointType) -> Handle {
+        self.storage.elements.insert(&ArrowSession::create_arrow(start, end))
+    }
+
+    ///Returns false if the element was removed.
+    pub fn set(&mut self, handle: Handle, start: PointType, end: PointType) -> bool {
+        self.storage.elements.set(handle, &ArrowSession::create_arrow(start, end))
+    }
+
+    ///Returns false if the element was already removed.
+    pub fn remove(&mut self, handle: Handle) -> bool {
+        self.storage.elements.remove(handle)
+    }
+
+    pub fn contains(&self, handle: Handle) -> bool {
+        self.storage.elements.element(handle).is_some()
+    }
+
+    pub fn len(&self) -> usize {
+        self.storage.elements.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    ///Remove every element.
+    pub fn clear(&mut self) {
+        self.storage.elements.clear();
+    }
+
+    pub fn uniforms<'a>(&'a mut self, sys: &'a mut SimpleCanvas) -> Uniforms<'a> {
+        let un = ProgramUniformValues::new_extruded(self.radius);
+        uniforms(sys, UniformVals::Regular(un), self.storage.upload())
+    }
+}
+
+pub struct RetainedSprites {
+    storage: Storage<sprite_program::Vertex>,
+}
+
+impl RetainedSprites {
+    pub(crate) fn new() -> RetainedSprites {
+        RetainedSprites {
+            storage: Storage::new(1),
+        }
+    }
+
+    ///The same as `SpriteSession::add()`.
+    pub fn insert(&mut self, point: PointType, index: u16, rotation: f32) -> Handle {
+        let vert = sprite::SpriteSession::create_sprite(point, index, rotation);
+        self.storage.elements.insert(&[vert])
+    }
+
+    ///Returns false if the element was removed.
+    pub fn set(&mut self, handle: Handle, point: PointType, index: u16, rotation: f32) -> bool {
+        let vert = sprite::SpriteSession::create_sprite(point, index, rotation);
+        self.storage.elements.set(handle, &[vert])
+    }
+
+    ///Returns false if the element was already removed.
+    pub fn remove(&mut self, handle: Handle) -> bool {
+        self.storage.elements.remove(handle)
+    }
+
+    pub fn contains(&self, handle: Handle) -> bool {
+        self.storage.elements.element(handle).is_some()
+    }
+
+    pub fn len(&self) -> usize {
+        self.storage.elements.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    ///Remove every element.
+    pub fn clear(&mut self) {
+        self.storage.elements.clear();
+    }
+
+    pub fn uniforms<'a>(
+        &'a mut self,
+        sys: &'a mut SimpleCanvas,
+        texture: &'a sprite::Texture,
+        radius: f32,
+    ) -> Uniforms<'a> {
+        let un = SpriteProgramUniformValues {
+            texture,
+            radius: radius * core::f32::consts::SQRT_2,
+            format: VertexFormat::Plain,
+        };
+        uniforms(sys, UniformVals::Sprite(un), self.storage.upload())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //Elements of two vertices each, that are both the number of the element.
+    fn add(e: &mut Elements<u32>, v: u32) -> Handle {
+        e.insert(&[v, v])
+    }
+
+    #[test]
+    fn swap_remove() {
+        let mut e = Elements::new(2);
+        let h: Vec<Handle> = (0..4).map(|v| add(&mut e, v)).collect();
+        e.take_changes();
+
+        assert!(e.remove(h[1]));
+        assert_eq!(e.verts, vec![0, 0, 3, 3, 2, 2]);
+        assert_eq!(e.len(), 3);
+        //The moved element can still be found with its handle.
+        assert_eq!(e.element(h[3]), Some(1));
+        assert!(e.set(h[3], &[7, 7]));
+        assert_eq!(e.verts, vec![0, 0, 7, 7, 2, 2]);
+
+        //Removing the last element moves nothing.
+        assert!(e.remove(h[2]));
+        assert_eq!(e.verts, vec![0, 0, 7, 7]);
+        assert!(e.remove(h[0]));
+        assert_eq!(e.verts, vec![7, 7]);
+        assert_eq!(e.element(h[3]), Some(0));
+    }
+
+    #[test]
+    fn stale_handles() {
+        let mut e = Elements::new(2);
+        let a = add(&mut e, 0);
+        let b = add(&mut e, 1);
+        assert!(e.remove(a));
+        assert!(!e.remove(a));
+
+        //The new element reuses the slot of a, but a does not refer to it.
+        let c = add(&mut e, 2);
+        assert_eq!(c.index, a.index);
+        assert_ne!(c, a);
+        assert_eq!(e.element(a), None);
+        assert!(!e.set(a, &[9, 9]));
+        assert!(!e.remove(a));
+        assert_eq!(e.verts, vec![1, 1, 2, 2]);
+
+        e.clear();
+        assert_eq!(e.len(), 0);
+        assert_eq!(e.element(b), None);
+        assert_eq!(e.element(c), None);
+        let d = add(&mut e, 3);
+        assert!(d != b && d != c);
+        assert_eq!(e.element(d), Some(0));
+    }
+
+    #[test]
+    fn merge_changes() {
+        let mut e = Elements::new(2);
+        let h: Vec<Handle> = (0..6).map(|v| add(&mut e, v)).collect();
+        assert_eq!(e.take_changes(), vec![0..12]);
+        assert!(e.take_changes().is_empty());
+
+        //Out of order and repeated changes merge into ranges in order.
+        e.set(h[4], &[4, 4]);
+        e.set(h[1], &[1, 1]);
+        e.set(h[0], &[0, 0]);
+        e.set(h[4], &[4, 4]);
+        e.set(h[3], &[3, 3]);
+        assert_eq!(e.take_changes(), vec![0..4, 6..10]);
+
+        //Changes to elements that are gone are dropped.
+        e.set(h[5], &[5, 5]);
+        e.remove(h[5]);
+        e.set(h[0], &[0, 0]);
+        e.remove(h[0]);
+        assert_eq!(e.len(), 4);
+        assert_eq!(e.verts, vec![4, 4, 1, 1, 2, 2, 3, 3]);
+        assert_eq!(e.take_changes(), vec![0..2]);
+    }
+}
@@ -4,31 +4,39 @@ use super::*;
 
 pub struct SquareSave {
     _ns: NotSend,
-    buffer: vbo::StaticBuffer<circle_program::Vertex>,
+    buffer: compact::SavedVertices<circle_program::Vertex>,
 }
 impl SquareSave {
     pub fn uniforms<'a>(&'a self, sys: &'a mut SimpleCanvas, radius: f32) -> Uniforms<'a> {
-        let common = UniformCommon {
-            color: sys.color,
-            offset: sys.offset,
-        };
-        let un = ProgramUniformValues::new(radius, gl::POINTS);
+        let common = sys.common(sys.offset);
+        let mut un = ProgramUniformValues::new(radius, gl::POINTS);
+        un.precision = self.buffer.precision();
 
         Uniforms {
             sys,
             un: UniformVals::Regular(un),
             common,
-            buffer: self.buffer.get_info(),
+            buffer: self.buffer.vertices(),
         }
     }
 }
 
 pub struct SquareSession {
     pub(crate) verts: Vec<circle_program::Vertex>,
+    pub(crate) precision: compact::Precision,
 }
 impl SquareSession {
     pub fn new() -> Self {
-        SquareSession { verts: Vec::new() }
+        SquareSession {
+            verts: Vec::new(),
+            precision: compact::Precision::Full,
+        }
+    }
+
+    ///Store the positions in less space when sent or saved. See the `compact` module.
+    pub fn with_precision(&mut self, precision: compact::Precision) -> &mut Self {
+        self.precision = precision;
+        self
     }
     #[inline(always)]
     pub fn add(&mut self, point: [f32; 2]) -> &mut Self {
@@ -43,7 +51,7 @@ impl SquareSession {
     pub fn save(&mut self, _sys: &mut SimpleCanvas) -> SquareSave {
         SquareSave {
             _ns: ns(),
-            buffer: vbo::StaticBuffer::new(&self.verts),
+            buffer: compact::SavedVertices::new(&self.verts, self.precision),
         }
     }
 
@@ -52,15 +60,11 @@ impl SquareSession {
         sys: &'a mut SimpleCanvas,
         radius: f32,
     ) -> Uniforms<'a> {
-        sys.circle_buffer.send_to_gpu(&self.verts);
+        let common = sys.common(sys.offset);
+        let mut un = ProgramUniformValues::new(radius, gl::POINTS);
+        un.precision = self.precision;
 
-        let common = UniformCommon {
-            color: sys.color,
-            offset: sys.offset,
-        };
-        let un = ProgramUniformValues::new(radius, gl::POINTS);
-
-        let buffer = sys.circle_buffer.get_info(self.verts.len());
+        let buffer = compact::points(sys, &self.verts, self.precision);
         Uniforms {
             sys,
             common,
@@ -72,17 +76,15 @@ impl SquareSession {
 
 pub struct CircleSave {
     _ns: NotSend,
-    buffer: vbo::StaticBuffer<circle_program::Vertex>,
+    buffer: compact::SavedVertices<circle_program::Vertex>,
 }
 impl CircleSave {
     pub fn uniforms<'a>(&'a self, sys: &'a mut SimpleCanvas, radius: f32) -> Uniforms<'a> {
-        let common = UniformCommon {
-            color: sys.color,
-            offset: sys.offset,
-        };
-        let un = ProgramUniformValues::new(radius, gl::POINTS);
+        let common = sys.common(sys.offset);
+        let mut un = ProgramUniformValues::new(radius, gl::POINTS);
+        un.precision = self.buffer.precision();
 
-        let buffer = self.buffer.get_info();
+        let buffer = self.buffer.vertices();
         Uniforms {
             common,
             sys,
@@ -93,16 +95,26 @@ impl CircleSave {
 }
 pub struct CircleSession {
     pub(crate) verts: Vec<circle_program::Vertex>,
+    pub(crate) precision: compact::Precision,
 }
 
 impl CircleSession {
     pub fn new() -> Self {
-        CircleSession { verts: Vec::new() }
+        CircleSession {
+            verts: Vec::new(),
+            precision: compact::Precision::Full,
+        }
+    }
+
+    ///Store the positions in less space when sent or saved. See the `compact` module.
+    pub fn with_precision(&mut self, precision: compact::Precision) -> &mut Self {
+        self.precision = precision;
+        self
     }
     pub fn save(&mut self, _sys: &mut SimpleCanvas) -> CircleSave {
         CircleSave {
             _ns: ns(),
-            buffer: vbo::StaticBuffer::new(&self.verts),
+            buffer: compact::SavedVertices::new(&self.verts, self.precision),
         }
     }
 
@@ -114,15 +126,11 @@ impl CircleSession {
         sys: &'a mut SimpleCanvas,
         radius: f32,
     ) -> Uniforms<'a> {
-        sys.circle_buffer.send_to_gpu(&self.verts);
-
-        let common = UniformCommon {
-            color: sys.color,
-            offset: sys.offset,
-        };
-        let un = ProgramUniformValues::new(radius, gl::POINTS);
+        let common = sys.common(sys.offset);
+        let mut un = ProgramUniformValues::new(radius, gl::POINTS);
+        un.precision = self.precision;
 
-        let buffer = sys.circle_buffer.get_info(self.verts.len());
+        let buffer = compact::points(sys, &self.verts, self.precision);
         Uniforms {
             sys,
             common,
@@ -140,17 +148,15 @@ impl CircleSession {
 
 pub struct RectSave {
     _ns: NotSend,
-    buffer: vbo::StaticBuffer<circle_program::Vertex>,
+    buffer: compact::SavedVertices<circle_program::Vertex>,
 }
 
 impl RectSave {
     pub fn uniforms<'a>(&'a self, sys: &'a mut SimpleCanvas) -> Uniforms<'a> {
-        let common = UniformCommon {
-            color: sys.color,
-            offset: sys.offset,
-        };
-        let un = ProgramUniformValues::new(0.0, gl::TRIANGLES);
-        let buffer = self.buffer.get_info();
+        let common = sys.common(sys.offset);
+        let mut un = ProgramUniformValues::new(0.0, gl::TRIANGLES);
+        un.precision = self.buffer.precision();
+        let buffer = self.buffer.vertices();
         Uniforms {
             sys,
             common,
@@ -162,17 +168,27 @@ impl RectSave {
 
 pub struct RectSession {
     pub(crate) verts: Vec<circle_program::Vertex>,
+    pub(crate) precision: compact::Precision,
 }
 
 impl RectSession {
     pub fn new() -> Self {
-        RectSession { verts: Vec::new() }
+        RectSession {
+            verts: Vec::new(),
+            precision: compact::Precision::Full,
+        }
+    }
+
+    ///Store the positions in less space when sent or saved. See the `compact` module.
+    pub fn with_precision(&mut self, precision: compact::Precision) -> &mut Self {
+        self.precision = precision;
+        self
     }
 
     pub fn save(&mut self, _sys: &mut SimpleCanvas) -> RectSave {
         RectSave {
             _ns: ns(),
-            buffer: vbo::StaticBuffer::new(&self.verts),
+            buffer: compact::SavedVertices::new(&self.verts, self.precision),
         }
     }
 
@@ -180,14 +196,10 @@ impl RectSession {
         self.verts.append(&mut other.verts);
     }
     pub fn send_and_uniforms<'a>(&'a mut self, sys: &'a mut SimpleCanvas) -> Uniforms<'a> {
-        sys.circle_buffer.send_to_gpu(&self.verts);
-
-        let common = UniformCommon {
-            color: sys.color,
-            offset: vec2same(0.0),
-        };
-        let un = ProgramUniformValues::new(0.0, gl::TRIANGLES);
-        let buffer = sys.circle_buffer.get_info(self.verts.len());
+        let common = sys.common(vec2same(0.0));
+        let mut un = ProgramUniformValues::new(0.0, gl::TRIANGLES);
+        un.precision = self.precision;
+        let buffer = compact::points(sys, &self.verts, self.precision);
         Uniforms {
             sys,
             common,
@@ -197,7 +209,7 @@ impl RectSession {
     }
 
     #[inline(always)]
-    fn create_rect(rect: [f32; 4]) -> [circle_program::Vertex; 6] {
+    pub(crate) fn create_rect(rect: [f32; 4]) -> [circle_program::Vertex; 6] {
         let rect:Rect<f32> = core::convert::From::from(rect);
         let [tl, tr, br, bl] = rect.get_corners();
         //let arr = [tr, tl, bl, bl, br, tr];
@@ -217,26 +229,24 @@ impl RectSession {
 
 pub struct ArrowSave {
     _ns: NotSend,
-    buffer: vbo::StaticBuffer<circle_program::Vertex>,
+    radius: f32,
+    buffer: vbo::StaticBuffer<circle_program::LineVertex>,
 }
 impl ArrowSave {
     pub fn uniforms<'a>(&'a self, sys: &'a mut SimpleCanvas) -> Uniforms<'a> {
-        let common = UniformCommon {
-            color: sys.color,
-            offset: sys.offset,
-        };
-        let un = ProgramUniformValues::new(0.0, gl::TRIANGLES);
+        let common = sys.common(sys.offset);
+        let un = ProgramUniformValues::new_extruded(self.radius);
         Uniforms {
             sys,
             common,
             un: UniformVals::Regular(un),
-            buffer: self.buffer.get_info(),
+            buffer: queue::Vertices::Saved(self.buffer.get_info(), self.buffer.handle()),
         }
     }
 }
 pub struct ArrowSession {
     pub(crate) radius: f32,
-    pub(crate) verts: Vec<circle_program::Vertex>,
+    pub(crate) verts: Vec<circle_program::LineVertex>,
 }
 
 impl ArrowSession {
@@ -249,6 +259,7 @@ impl ArrowSession {
     pub fn save(&mut self, _sys: &mut SimpleCanvas) -> ArrowSave {
         ArrowSave {
             _ns: ns(),
+            radius: self.radius,
             buffer: vbo::StaticBuffer::new(&self.verts),
         }
     }
@@ -257,14 +268,9 @@ impl ArrowSession {
         self.verts.append(&mut other.verts);
     }
     pub fn send_and_uniforms<'a>(&'a mut self, sys: &'a mut SimpleCanvas) -> Uniforms<'a> {
-        sys.circle_buffer.send_to_gpu(&self.verts);
-
-        let common = UniformCommon {
-            color: sys.color,
-            offset: vec2same(0.0),
-        };
-        let un = ProgramUniformValues::new(0.0, gl::TRIANGLES);
-        let buffer = sys.circle_buffer.get_info(self.verts.len());
+        let common = sys.common(vec2same(0.0));
+        let un = ProgramUniformValues::new_extruded(self.radius);
+        let buffer = queue::Vertices::Lines(&self.verts);
         Uniforms {
             sys,
             common,
@@ -274,7 +280,7 @@ impl ArrowSession {
     }
 
     #[inline(always)]
-    fn create_arrow(radius: f32, start: PointType, end: PointType) -> [circle_program::Vertex; 9] {
+    pub(crate) fn create_arrow(start: PointType, end: PointType) -> [circle_program::LineVertex; 9] {
         let start = vec2(start[0], start[1]);
         let end = vec2(end[0], end[1]);
         let offset = end - start;
@@ -282,36 +288,31 @@ impl ArrowSession {
         let arrow_head = start + offset * 0.8;
 
         let k = offset.rotate_90deg_right().normalize_to(1.0);
-        let start1 = start + k * radius;
-        let start2 = start - k * radius;
-
-        let end1 = arrow_head + k * radius;
-        let end2 = arrow_head - k * radius;
 
-        let end11 = arrow_head + k * radius * 2.5;
-        let end22 = arrow_head - k * radius * 2.5;
-        //let arr = [start1, start2, end1, start2, end1, end2, end, end11, end22];
-
-        fn doop(a: Vec2<f32>) -> circle_program::Vertex {
-            circle_program::Vertex([a.x, a.y])
+        //The radius is applied in the shader, along the extrude direction.
+        fn doop(a: Vec2<f32>, extrude: Vec2<f32>) -> circle_program::LineVertex {
+            circle_program::LineVertex {
+                pos: [a.x, a.y],
+                extrude: [extrude.x, extrude.y],
+            }
         }
 
         [
-            doop(start1),
-            doop(start2),
-            doop(end1),
-            doop(start2),
-            doop(end1),
-            doop(end2),
-            doop(end),
-            doop(end11),
-            doop(end22),
+            doop(start, k),
+            doop(start, -k),
+            doop(arrow_head, k),
+            doop(start, -k),
+            doop(arrow_head, k),
+            doop(arrow_head, -k),
+            doop(end, vec2same(0.0)),
+            doop(arrow_head, k * 2.5),
+            doop(arrow_head, -k * 2.5),
         ]
     }
 
     #[inline(always)]
     pub fn add(&mut self, start: PointType, end: PointType) -> &mut Self {
-        let arr = Self::create_arrow(self.radius, start, end);
+        let arr = Self::create_arrow(start, end);
         self.verts.extend_from_slice(&arr);
         self
     }
@@ -319,28 +320,26 @@ impl ArrowSession {
 
 pub struct LineSave {
     _ns: NotSend,
-    buffer: vbo::StaticBuffer<circle_program::Vertex>,
+    radius: f32,
+    buffer: vbo::StaticBuffer<circle_program::LineVertex>,
 }
 
 impl LineSave {
     pub fn uniforms<'a>(&'a self, sys: &'a mut SimpleCanvas) -> Uniforms<'a> {
-        let common = UniformCommon {
-            color: sys.color,
-            offset: sys.offset,
-        };
-        let un = ProgramUniformValues::new(0.0, gl::TRIANGLES);
+        let common = sys.common(sys.offset);
+        let un = ProgramUniformValues::new_extruded(self.radius);
         Uniforms {
             sys,
             common,
             un: UniformVals::Regular(un),
-            buffer: self.buffer.get_info(),
+            buffer: queue::Vertices::Saved(self.buffer.get_info(), self.buffer.handle()),
         }
     }
 }
 
 pub struct LineSession {
     pub(crate) radius: f32,
-    pub(crate) verts: Vec<circle_program::Vertex>,
+    pub(crate) verts: Vec<circle_program::LineVertex>,
 }
 
 impl LineSession {
@@ -354,6 +353,7 @@ impl LineSession {
     pub fn save(&mut self, _sys: &mut SimpleCanvas) -> LineSave {
         LineSave {
             _ns: ns(),
+            radius: self.radius,
             buffer: vbo::StaticBuffer::new(&self.verts),
         }
     }
@@ -362,15 +362,10 @@ impl LineSession {
         self.verts.append(&mut other.verts);
     }
     pub fn send_and_uniforms<'a>(&'a mut self, sys: &'a mut SimpleCanvas) -> Uniforms<'a> {
-        sys.circle_buffer.send_to_gpu(&self.verts);
+        let common = sys.common(sys.offset);
 
-        let common = UniformCommon {
-            color: sys.color,
-            offset: sys.offset,
-        };
-
-        let un = ProgramUniformValues::new(0.0, gl::TRIANGLES);
-        let buffer = sys.circle_buffer.get_info(self.verts.len());
+        let un = ProgramUniformValues::new_extruded(self.radius);
+        let buffer = queue::Vertices::Lines(&self.verts);
         Uniforms {
             sys,
             common,
@@ -380,37 +375,34 @@ impl LineSession {
     }
 
     #[inline(always)]
-    fn create_line(radius: f32, start: PointType, end: PointType) -> [circle_program::Vertex; 6] {
+    pub(crate) fn create_line(start: PointType, end: PointType) -> [circle_program::LineVertex; 6] {
         let start = vec2(start[0], start[1]); //TODO a program that detected bad uses like this would be cool
         let end = vec2(end[0], end[1]);
 
         let offset = end - start;
         let k = offset.rotate_90deg_right().normalize_to(1.0);
-        let start1 = start + k * radius;
-        let start2 = start - k * radius;
-
-        let end1 = end + k * radius;
-        let end2 = end - k * radius;
 
-        //let arr = [start1, start2, end1, start2, end1, end2];
-
-        fn doop(a: Vec2<f32>) -> circle_program::Vertex {
-            circle_program::Vertex([a.x, a.y])
+        //The radius is applied in the shader, along the extrude direction.
+        fn doop(a: Vec2<f32>, extrude: Vec2<f32>) -> circle_program::LineVertex {
+            circle_program::LineVertex {
+                pos: [a.x, a.y],
+                extrude: [extrude.x, extrude.y],
+            }
         }
 
         [
-            doop(start1),
-            doop(start2),
-            doop(end1),
-            doop(start2),
-            doop(end1),
-            doop(end2),
+            doop(start, k),
+            doop(start, -k),
+            doop(end, k),
+            doop(start, -k),
+            doop(end, k),
+            doop(end, -k),
         ]
     }
 
     #[inline(always)]
     pub fn add(&mut self, start: PointType, end: PointType) -> &mut Self {
-        let a = Self::create_line(self.radius, start, end);
+        let a = Self::create_line(start, end);
         self.verts.extend_from_slice(&a);
         self
     }
@@ -4,7 +4,7 @@ use super::*;
 
 pub struct SpriteSave {
     _ns: NotSend,
-    pub(crate) buffer: vbo::StaticBuffer<sprite_program::Vertex>,
+    pub(crate) buffer: compact::SavedVertices<sprite_program::Vertex>,
 }
 impl SpriteSave {
     pub fn uniforms<'a>(
@@ -16,52 +16,77 @@ impl SpriteSave {
         let sqrt2: f32 = 1.41421356237;
         let radius = radius * sqrt2;
 
-        let common = UniformCommon {
-            color: sys.color,
-            offset: sys.offset,
+        let common = sys.common(sys.offset);
+        let un = SpriteProgramUniformValues {
+            radius,
+            texture,
+            format: compact::sprite_format(self.buffer.precision()),
         };
-        let un = SpriteProgramUniformValues { radius, texture };
         Uniforms {
             sys,
             common,
             un: UniformVals::Sprite(un),
-            buffer: self.buffer.get_info(),
+            buffer: self.buffer.vertices(),
         }
     }
 }
 
 pub struct SpriteSession {
     pub(crate) verts: Vec<sprite_program::Vertex>,
+    pub(crate) precision: compact::Precision,
 }
 
 impl SpriteSession {
     pub fn new() -> Self {
-        SpriteSession { verts: Vec::new() }
+        SpriteSession {
+            verts: Vec::new(),
+            precision: compact::Precision::Full,
+        }
+    }
+
+    ///Store the positions in less space when sent or saved. See the `compact` module.
+    pub fn with_precision(&mut self, precision: compact::Precision) -> &mut Self {
+        self.precision = precision;
+        self
     }
     ///Add a point sprite.
     #[inline(always)]
     pub fn add(&mut self, point: PointType, index: u16, rotation: f32) -> &mut Self {
+        self.verts.push(Self::create_sprite(point, index, rotation));
+        self
+    }
+
+    //The rotation is stored as a fraction of a turn.
+    #[inline(always)]
+    pub(crate) fn create_sprite(point: PointType, index: u16, rotation: f32) -> sprite_program::Vertex {
         let k = rotation.rem_euclid(core::f32::consts::PI * 2.);
         let k = k / (core::f32::consts::PI * 2.);
         let k = (k * (core::u16::MAX as f32)) as u16;
 
-        self.verts.push(sprite_program::Vertex {
+        sprite_program::Vertex {
             pos: point,
-            index: index as u16,
+            index,
             rotation: k,
-        });
-        self
+        }
     }
 
     pub fn append(&mut self, other: &mut Self) {
         self.verts.append(&mut other.verts);
     }
 
+    ///Order the sprites from the top of the world to the bottom, so sprites lower down are drawn in front.
+    ///Sprites at the same height keep their order.
+    pub fn y_sort(&mut self) -> &mut Self {
+        self.verts
+            .sort_by(|a, b| a.pos[1].partial_cmp(&b.pos[1]).unwrap_or(core::cmp::Ordering::Equal));
+        self
+    }
+
     ///Save this sprite session to into its own static buffer to be drawn later.
     pub fn save(&mut self, _sys: &mut SimpleCanvas) -> SpriteSave {
         SpriteSave {
             _ns: ns(),
-            buffer: vbo::StaticBuffer::new(&self.verts),
+            buffer: compact::SavedVertices::new(&self.verts, self.precision),
         }
     }
 
@@ -71,18 +96,17 @@ impl SpriteSession {
         texture: &'a Texture,
         radius: f32,
     ) -> Uniforms<'a> {
-        sys.sprite_buffer.send_to_gpu(&self.verts);
-
         let sqrt2: f32 = 1.41421356237;
         let radius = radius * sqrt2;
 
-        let common = UniformCommon {
-            color: sys.color,
-            offset: sys.offset,
+        let common = sys.common(sys.offset);
+        let un = SpriteProgramUniformValues {
+            radius,
+            texture,
+            format: compact::sprite_format(self.precision),
         };
-        let un = SpriteProgramUniformValues { radius, texture };
 
-        let buffer = sys.sprite_buffer.get_info(self.verts.len());
+        let buffer = compact::sprites(sys, &self.verts, self.precision);
         Uniforms {
             common,
             sys,
@@ -92,20 +116,417 @@ impl SpriteSession {
     }
 }
 
+///How a texture is sampled when it is drawn smaller or larger than its size.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Filter {
+    Nearest,
+    Linear,
+    ///Linear filtering that also blends between mipmap levels.
+    ///Mipmaps are always generated for this filter.
+    Trilinear,
+}
+
+///What happens when a texture is sampled outside of the range 0 to 1.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Wrap {
+    Clamp,
+    Repeat,
+    Mirror,
+}
+
+impl Wrap {
+    fn gl_enum(self) -> GLenum {
+        match self {
+            Wrap::Clamp => gl::CLAMP_TO_EDGE,
+            Wrap::Repeat => gl::REPEAT,
+            Wrap::Mirror => gl::MIRRORED_REPEAT,
+        }
+    }
+}
+
+//From the GL_EXT_texture_filter_anisotropic extension, which gles 3.0 does not have in core.
+const TEXTURE_MAX_ANISOTROPY_EXT: GLenum = 0x84FE;
+const MAX_TEXTURE_MAX_ANISOTROPY_EXT: GLenum = 0x84FF;
+
+///Sampling options of a texture. Uses the builder pattern.
+///The default is nearest filtering with no mipmaps and repeat wrapping.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct TextureOptions {
+    pub filter: Filter,
+    pub mipmaps: bool,
+    pub wrap: [Wrap; 2],
+    pub anisotropy: Option<f32>,
+}
+
+impl Default for TextureOptions {
+    fn default() -> Self {
+        TextureOptions {
+            filter: Filter::Nearest,
+            mipmaps: false,
+            wrap: [Wrap::Repeat; 2],
+            anisotropy: None,
+        }
+    }
+}
+
+impl TextureOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_nearest(mut self) -> Self {
+        self.filter = Filter::Nearest;
+        self
+    }
+
+    pub fn with_linear(mut self) -> Self {
+        self.filter = Filter::Linear;
+        self
+    }
+
+    pub fn with_trilinear(mut self) -> Self {
+        self.filter = Filter::Trilinear;
+        self.mipmaps = true;
+        self
+    }
+
+    ///Generate mipmaps with glGenerateMipmap and use them when the texture is drawn smaller than its size.
+    pub fn with_mipmaps(mut self, mipmaps: bool) -> Self {
+        self.mipmaps = mipmaps;
+        self
+    }
+
+    ///Set the wrap mode for both axes.
+    pub fn with_wrap(mut self, wrap: Wrap) -> Self {
+        self.wrap = [wrap; 2];
+        self
+    }
+
+    ///Set the wrap mode for the x and the y axis separately.
+    pub fn with_wrap_xy(mut self, x: Wrap, y: Wrap) -> Self {
+        self.wrap = [x, y];
+        self
+    }
+
+    ///A hint for the level of anisotropic filtering.
+    ///It is clamped to what the gpu supports, and ignored if the gpu does not support it.
+    pub fn with_anisotropy(mut self, anisotropy: f32) -> Self {
+        self.anisotropy = Some(anisotropy);
+        self
+    }
+
+    fn uses_mipmaps(&self) -> bool {
+        self.mipmaps || self.filter == Filter::Trilinear
+    }
+
+    fn min_filter(&self) -> GLenum {
+        match (self.filter, self.uses_mipmaps()) {
+            (Filter::Nearest, false) => gl::NEAREST,
+            (Filter::Linear, false) => gl::LINEAR,
+            (Filter::Nearest, true) => gl::NEAREST_MIPMAP_NEAREST,
+            (Filter::Linear, true) => gl::LINEAR_MIPMAP_NEAREST,
+            (Filter::Trilinear, _) => gl::LINEAR_MIPMAP_LINEAR,
+        }
+    }
+
+    fn mag_filter(&self) -> GLenum {
+        match self.filter {
+            Filter::Nearest => gl::NEAREST,
+            Filter::Linear | Filter::Trilinear => gl::LINEAR,
+        }
+    }
+}
+
+///Where the tiles of a sprite sheet are, in pixels. Uses the builder pattern.
+///The tiles start after the margin, and have the spacing between them.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct SheetLayout {
+    pub tile_dim: [u32; 2],
+    pub margin: u32,
+    pub spacing: u32,
+}
+
+impl SheetLayout {
+    ///Tiles of this size with no margin or spacing.
+    pub fn new(tile_dim: [u32; 2]) -> SheetLayout {
+        SheetLayout {
+            tile_dim,
+            margin: 0,
+            spacing: 0,
+        }
+    }
+
+    pub fn with_margin(mut self, margin: u32) -> SheetLayout {
+        self.margin = margin;
+        self
+    }
+
+    pub fn with_spacing(mut self, spacing: u32) -> SheetLayout {
+        self.spacing = spacing;
+        self
+    }
+
+    ///The number of whole tiles that fit in a sheet of this size.
+    pub fn grid_dim(&self, dim: [u32; 2]) -> [u32; 2] {
+        let fit = |i: usize| {
+            let space = (dim[i] + self.spacing).saturating_sub(2 * self.margin);
+            space / (self.tile_dim[i] + self.spacing).max(1)
+        };
+        [fit(0), fit(1)]
+    }
+
+    ///Returns true if a sheet of this size has between 1 and 255 whole tiles along each axis,
+    ///which is what a texture needs. Tiles with no pixels never fit.
+    pub fn fits(&self, dim: [u32; 2]) -> bool {
+        self.tile_dim.iter().all(|&t| t > 0) && self.grid_dim(dim).iter().all(|&g| (1..=255).contains(&g))
+    }
+
+    ///The pixel position of the top left corner of a tile.
+    pub fn tile_pos(&self, cell: [u32; 2]) -> [u32; 2] {
+        [
+            self.margin + cell[0] * (self.tile_dim[0] + self.spacing),
+            self.margin + cell[1] * (self.tile_dim[1] + self.spacing),
+        ]
+    }
+
+    ///Copy the tiles of a sheet of tightly packed rgba pixels into a new sheet,
+    ///where every tile is surrounded by `amount` pixels that repeat its edge pixels.
+    ///This stops neighboring tiles from bleeding into each other with linear filtering or mipmaps.
+    ///Returns the dimensions, pixels and layout of the new sheet.
+    ///The sheet is returned as it is if there is nothing to extrude, or the tiles have no pixels.
+    pub fn extrude(&self, dim: [u32; 2], pixels: &[u8], amount: u32) -> ([u32; 2], Vec<u8>, SheetLayout) {
+        assert_eq!(
+            pixels.len(),
+            dim[0] as usize * dim[1] as usize * 4,
+            "pixel slice does not match the dimensions"
+        );
+        if amount == 0 || self.tile_dim[0] == 0 || self.tile_dim[1] == 0 {
+            return (dim, pixels.to_vec(), *self);
+        }
+        let grid = self.grid_dim(dim);
+        let [tw, th] = self.tile_dim;
+        let layout = SheetLayout::new(self.tile_dim)
+            .with_margin(amount)
+            .with_spacing(2 * amount);
+        let new_dim = [
+            grid[0] * (tw + 2 * amount),
+            grid[1] * (th + 2 * amount),
+        ];
+
+        let mut out = vec![0u8; new_dim[0] as usize * new_dim[1] as usize * 4];
+        for cy in 0..grid[1] {
+            for cx in 0..grid[0] {
+                let [sx, sy] = self.tile_pos([cx, cy]);
+                let [dx, dy] = layout.tile_pos([cx, cy]);
+
+                //Every destination pixel takes the nearest pixel of the tile.
+                for y in 0..th + 2 * amount {
+                    let ty = (y as i64 - amount as i64).clamp(0, th as i64 - 1) as u32;
+                    for x in 0..tw + 2 * amount {
+                        let tx = (x as i64 - amount as i64).clamp(0, tw as i64 - 1) as u32;
+                        let src = (((sy + ty) * dim[0] + sx + tx) * 4) as usize;
+                        let dst = (((dy + y - amount) * new_dim[0] + dx + x - amount) * 4) as usize;
+                        out[dst..dst + 4].copy_from_slice(&pixels[src..src + 4]);
+                    }
+                }
+            }
+        }
+        (new_dim, out, layout)
+    }
+}
+
+//Set the sampling parameters of the texture bound to the target.
+//Generates mipmaps if they are needed and not there yet, and returns whether it did.
+pub(crate) unsafe fn apply_options(target: GLenum, options: &TextureOptions, has_mipmaps: bool) -> bool {
+    let generate = options.uses_mipmaps() && !has_mipmaps;
+    if generate {
+        gl::GenerateMipmap(target);
+        gl_ok!();
+    }
+
+    gl::TexParameteri(target, gl::TEXTURE_MIN_FILTER, options.min_filter() as i32);
+    gl_ok!();
+    gl::TexParameteri(target, gl::TEXTURE_MAG_FILTER, options.mag_filter() as i32);
+    gl_ok!();
+    gl::TexParameteri(target, gl::TEXTURE_WRAP_S, options.wrap[0].gl_enum() as i32);
+    gl_ok!();
+    gl::TexParameteri(target, gl::TEXTURE_WRAP_T, options.wrap[1].gl_enum() as i32);
+    gl_ok!();
+
+    if let Some(max) = max_anisotropy() {
+        let a = options.anisotropy.unwrap_or(1.0).max(1.0).min(max);
+        gl::TexParameterf(target, TEXTURE_MAX_ANISOTROPY_EXT, a);
+        gl_ok!();
+    }
+    generate
+}
+
+unsafe fn upload_rgba(dim: [u32; 2], pixels: &[u8]) -> GLuint {
+    let mut texture_id: GLuint = 0;
+    gl::GenTextures(1, &mut texture_id);
+    gl_ok!();
+
+    gl::BindTexture(gl::TEXTURE_2D, texture_id);
+    gl_ok!();
+
+    gl::TexImage2D(
+        gl::TEXTURE_2D,
+        0,
+        gl::RGBA as i32,
+        dim[0] as i32,
+        dim[1] as i32,
+        0,
+        gl::RGBA,
+        gl::UNSIGNED_BYTE,
+        pixels.as_ptr() as *const _,
+    );
+    gl_ok!();
+
+    //Match the defaults of TextureOptions.
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::NEAREST as i32);
+    gl_ok!();
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::NEAREST as i32);
+    gl_ok!();
+
+    gl::BindTexture(gl::TEXTURE_2D, 0);
+    gl_ok!();
+    state::forget_textures();
+
+    texture_id
+}
+
+///Returned when a texture update does not fit the texture.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TextureError {
+    ///The region is not completely inside of the texture.
+    OutOfBounds {
+        pos: [u32; 2],
+        dim: [u32; 2],
+        texture_dim: [u32; 2],
+    },
+    ///The pixel slice is not exactly the length of the region.
+    WrongLength { expected: usize, actual: usize },
+}
+
+impl core::fmt::Display for TextureError {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self {
+            TextureError::OutOfBounds {
+                pos,
+                dim,
+                texture_dim,
+            } => write!(
+                f,
+                "region at {:?} of size {:?} is outside of the texture of size {:?}",
+                pos, dim, texture_dim
+            ),
+            TextureError::WrongLength { expected, actual } => write!(
+                f,
+                "expected {} bytes of pixel data but got {}",
+                expected, actual
+            ),
+        }
+    }
+}
+
+impl std::error::Error for TextureError {}
+
+///A pixel buffer object used to stream pixel data to textures.
+///The upload is handed off to the driver, so the cpu does not have to wait for
+///the gpu to finish drawing with the texture before the call returns.
+///The buffer is orphaned on every upload so that a new upload never has to wait
+///for the previous one to be consumed.
+pub struct PixelStream {
+    _ns: NotSend,
+    id: GLuint,
+    capacity: usize,
+}
+
+impl PixelStream {
+    pub fn new(_sys: &mut SimpleCanvas) -> PixelStream {
+        let mut id = 0;
+        unsafe {
+            gl::GenBuffers(1, &mut id);
+            gl_ok!();
+        }
+        PixelStream {
+            _ns: ns(),
+            id,
+            capacity: 0,
+        }
+    }
+}
+
+impl Drop for PixelStream {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteBuffers(1, &self.id);
+        }
+    }
+}
+
+thread_local! {
+    //The largest anisotropy the gpu supports, or None if it does not support anisotropic filtering.
+    //Only looked up the first time a texture is made.
+    static MAX_ANISOTROPY: core::cell::Cell<Option<Option<f32>>> = const { core::cell::Cell::new(None) };
+}
+
+fn max_anisotropy() -> Option<f32> {
+    MAX_ANISOTROPY.with(|m| {
+        if let Some(max) = m.get() {
+            return max;
+        }
+        let max = if has_extension("GL_EXT_texture_filter_anisotropic") {
+            let mut max = 1.0;
+            unsafe {
+                gl::GetFloatv(MAX_TEXTURE_MAX_ANISOTROPY_EXT, &mut max);
+                gl_ok!();
+            }
+            Some(max)
+        } else {
+            None
+        };
+        m.set(Some(max));
+        max
+    })
+}
+
+fn has_extension(name: &str) -> bool {
+    unsafe {
+        let mut num = 0;
+        gl::GetIntegerv(gl::NUM_EXTENSIONS, &mut num);
+        gl_ok!();
+        (0..num as GLuint).any(|i| {
+            let ext = gl::GetStringi(gl::EXTENSIONS, i);
+            !ext.is_null() && std::ffi::CStr::from_ptr(ext as *const _).to_bytes() == name.as_bytes()
+        })
+    }
+}
+
 #[derive(Debug)]
 pub struct Texture {
     _ns: NotSend,
     pub(crate) grid_dim: [u8; 2],
-    pub(crate) dim: [f32; 2],
+    //Interior mutability so an asset manager can reload the texture behind a shared handle.
+    pub(crate) dim: core::cell::Cell<[f32; 2]>,
     pub(crate) id: GLuint,
+    pub(crate) options: TextureOptions,
+    pub(crate) has_mipmaps: core::cell::Cell<bool>,
+    //None if the tiles evenly fill the texture.
+    pub(crate) layout: Option<SheetLayout>,
 }
 
 impl Texture {
+    ///Turn the texture into a handle that can be shared between sessions, saves and structs.
+    pub fn into_handle(self) -> TextureHandle {
+        TextureHandle::new(self)
+    }
+
     pub fn grid_dim(&self) -> [u8; 2] {
         self.grid_dim
     }
     pub fn dim(&self) -> [f32; 2] {
-        self.dim
+        self.dim.get()
     }
     ///Create a texture index from a coordinate in the tile set.
     ///The top left time maps to 0,0.
@@ -117,12 +538,673 @@ impl Texture {
         self.grid_dim[0] as u16 * cell[1] + cell[0]
     }
 
+    pub fn options(&self) -> TextureOptions {
+        self.options
+    }
+
+    ///The layout of the tiles, if it was set with `set_layout()` or a sheet constructor.
+    pub fn layout(&self) -> Option<SheetLayout> {
+        self.layout
+    }
+
+    ///Describe where the tiles are in pixels, instead of evenly splitting the texture by the grid dimensions.
+    ///The grid dimensions are derived from the layout.
+    pub fn set_layout(&mut self, layout: SheetLayout) {
+        let grid = layout.grid_dim(self.dim_u32());
+        assert!(layout.fits(self.dim_u32()), "the layout must fit between 1 and 255 tiles along each axis");
+        self.grid_dim = [grid[0] as u8, grid[1] as u8];
+        self.layout = Some(layout);
+    }
+
+    ///The size of one tile in pixels.
+    pub fn tile_dim(&self) -> [f32; 2] {
+        self.tile_geometry().0
+    }
+
+    //The size of a tile, the distance between the starts of tiles, and the margin, in pixels.
+    pub(crate) fn tile_geometry(&self) -> ([f32; 2], [f32; 2], [f32; 2]) {
+        match self.layout {
+            Some(l) => {
+                let tile = [l.tile_dim[0] as f32, l.tile_dim[1] as f32];
+                let spacing = l.spacing as f32;
+                let margin = l.margin as f32;
+                (tile, [tile[0] + spacing, tile[1] + spacing], [margin; 2])
+            }
+            None => {
+                let dim = self.dim();
+                let tile = [
+                    dim[0] / self.grid_dim[0] as f32,
+                    dim[1] / self.grid_dim[1] as f32,
+                ];
+                (tile, tile, [0.0; 2])
+            }
+        }
+    }
+
+    ///Create a texture from a sprite sheet with the specified layout.
+    ///If extrude is not zero, the tiles are first padded by that many pixels of their edges.
+    ///See `SheetLayout::extrude()`.
+    ///Returns a dimension error if the layout does not fit, see `SheetLayout::fits()`.
+    pub fn from_sheet(
+        sys: &mut SimpleCanvas,
+        img: &image::DynamicImage,
+        layout: SheetLayout,
+        extrude: u32,
+    ) -> image::ImageResult<Texture> {
+        use image::GenericImageView;
+        let (width, height) = img.dimensions();
+        if !layout.fits([width, height]) {
+            return Err(image::ImageError::DimensionError);
+        }
+        let rgba = img.to_rgba();
+        let (dim, pixels, layout) = if extrude > 0 {
+            layout.extrude([width, height], &rgba, extrude)
+        } else {
+            ([width, height], rgba.into_raw(), layout)
+        };
+        let mut texture = Texture::from_rgba(sys, dim, &pixels, [1, 1]);
+        texture.set_layout(layout);
+        Ok(texture)
+    }
+
+    ///Create a texture from a sprite sheet file. See `from_sheet()`.
+    pub fn from_sheet_file(
+        sys: &mut SimpleCanvas,
+        file: impl AsRef<std::path::Path>,
+        layout: SheetLayout,
+        extrude: u32,
+    ) -> image::ImageResult<Texture> {
+        let img = image::open(file)?;
+        Texture::from_sheet(sys, &img, layout, extrude)
+    }
+
+    ///Change how the texture is sampled.
+    ///Mipmaps are generated the first time options that use them are set.
+    pub fn set_options(&mut self, _sys: &mut SimpleCanvas, options: TextureOptions) {
+        unsafe {
+            gl::BindTexture(gl::TEXTURE_2D, self.id);
+            gl_ok!();
+
+            if apply_options(gl::TEXTURE_2D, &options, self.has_mipmaps.get()) {
+                self.has_mipmaps.set(true);
+            }
+
+            gl::BindTexture(gl::TEXTURE_2D, 0);
+            gl_ok!();
+            state::forget_textures();
+        }
+        self.options = options;
+    }
+
+    ///Create a texture from encoded image bytes, for example a png embedded with `include_bytes!`.
+    pub fn from_bytes(
+        sys: &mut SimpleCanvas,
+        bytes: &[u8],
+        grid_dim: [u8; 2],
+    ) -> image::ImageResult<Texture> {
+        let img = image::load_from_memory(bytes)?;
+        Ok(Texture::from_image(sys, &img, grid_dim))
+    }
+
+    ///Create a texture from an image file.
+    pub fn from_file(
+        sys: &mut SimpleCanvas,
+        file: impl AsRef<std::path::Path>,
+        grid_dim: [u8; 2],
+    ) -> image::ImageResult<Texture> {
+        let img = image::open(file)?;
+        Ok(Texture::from_image(sys, &img, grid_dim))
+    }
+
+    pub fn from_image(sys: &mut SimpleCanvas, img: &image::DynamicImage, grid_dim: [u8; 2]) -> Texture {
+        use image::GenericImageView;
+        let (width, height) = img.dimensions();
+        match img {
+            image::DynamicImage::ImageRgba8(img) => {
+                Texture::from_rgba(sys, [width, height], img, grid_dim)
+            }
+            img => Texture::from_rgba(sys, [width, height], &img.to_rgba(), grid_dim),
+        }
+    }
+
+    ///Create a texture from tightly packed rgba pixels, 4 bytes per pixel, in rows starting from the top.
+    pub fn from_rgba(_sys: &mut SimpleCanvas, dim: [u32; 2], pixels: &[u8], grid_dim: [u8; 2]) -> Texture {
+        assert_eq!(
+            pixels.len(),
+            dim[0] as usize * dim[1] as usize * 4,
+            "pixel slice does not match the dimensions"
+        );
+        unsafe {
+            let id = upload_rgba(dim, pixels);
+            Texture::new(id, grid_dim, [dim[0] as f32, dim[1] as f32])
+        }
+    }
+
+    ///Create a texture from tightly packed rgb pixels, 3 bytes per pixel.
+    ///The texture will be fully opaque.
+    pub fn from_rgb(sys: &mut SimpleCanvas, dim: [u32; 2], pixels: &[u8], grid_dim: [u8; 2]) -> Texture {
+        assert_eq!(
+            pixels.len(),
+            dim[0] as usize * dim[1] as usize * 3,
+            "pixel slice does not match the dimensions"
+        );
+        let rgba: Vec<u8> = pixels
+            .chunks(3)
+            .flat_map(|p| [p[0], p[1], p[2], 255])
+            .collect();
+        Texture::from_rgba(sys, dim, &rgba, grid_dim)
+    }
+
+    ///Create a texture from grayscale pixels, 1 byte per pixel.
+    ///The texture will be fully opaque.
+    pub fn from_luma(sys: &mut SimpleCanvas, dim: [u32; 2], pixels: &[u8], grid_dim: [u8; 2]) -> Texture {
+        assert_eq!(
+            pixels.len(),
+            dim[0] as usize * dim[1] as usize,
+            "pixel slice does not match the dimensions"
+        );
+        let rgba: Vec<u8> = pixels.iter().flat_map(|&p| [p, p, p, 255]).collect();
+        Texture::from_rgba(sys, dim, &rgba, grid_dim)
+    }
+
+    ///Create a texture by calling the function for every pixel.
+    ///The function is passed the x and y coordinate and returns an rgba color.
+    pub fn from_fn(
+        sys: &mut SimpleCanvas,
+        dim: [u32; 2],
+        grid_dim: [u8; 2],
+        mut func: impl FnMut(u32, u32) -> [u8; 4],
+    ) -> Texture {
+        let mut rgba = Vec::with_capacity(dim[0] as usize * dim[1] as usize * 4);
+        for y in 0..dim[1] {
+            for x in 0..dim[0] {
+                rgba.extend_from_slice(&func(x, y));
+            }
+        }
+        Texture::from_rgba(sys, dim, &rgba, grid_dim)
+    }
+
+    fn dim_u32(&self) -> [u32; 2] {
+        let dim = self.dim.get();
+        [dim[0] as u32, dim[1] as u32]
+    }
+
+    fn check_region(&self, pos: [u32; 2], dim: [u32; 2], len: usize) -> Result<(), TextureError> {
+        let texture_dim = self.dim_u32();
+        let fits = |i: usize| {
+            pos[i]
+                .checked_add(dim[i])
+                .map(|end| end <= texture_dim[i])
+                .unwrap_or(false)
+        };
+        if !fits(0) || !fits(1) {
+            return Err(TextureError::OutOfBounds {
+                pos,
+                dim,
+                texture_dim,
+            });
+        }
+        let expected = dim[0] as usize * dim[1] as usize * 4;
+        if len != expected {
+            return Err(TextureError::WrongLength {
+                expected,
+                actual: len,
+            });
+        }
+        Ok(())
+    }
+
+    //Keep the mipmaps in sync with the new contents, if they are being used.
+    unsafe fn after_contents_changed(&self) {
+        if self.options.uses_mipmaps() {
+            gl::GenerateMipmap(gl::TEXTURE_2D);
+            gl_ok!();
+            self.has_mipmaps.set(true);
+        } else {
+            self.has_mipmaps.set(false);
+        }
+    }
+
+    ///Replace the pixels of a rectangle of the texture with tightly packed rgba pixels.
+    ///The region must be inside of `dim()`.
+    pub fn update_region(
+        &mut self,
+        _sys: &mut SimpleCanvas,
+        pos: [u32; 2],
+        dim: [u32; 2],
+        pixels: &[u8],
+    ) -> Result<(), TextureError> {
+        self.check_region(pos, dim, pixels.len())?;
+        unsafe {
+            gl::BindTexture(gl::TEXTURE_2D, self.id);
+            gl_ok!();
+
+            gl::TexSubImage2D(
+                gl::TEXTURE_2D,
+                0,
+                pos[0] as i32,
+                pos[1] as i32,
+                dim[0] as i32,
+                dim[1] as i32,
+                gl::RGBA,
+                gl::UNSIGNED_BYTE,
+                pixels.as_ptr() as *const _,
+            );
+            gl_ok!();
+
+            self.after_contents_changed();
+
+            gl::BindTexture(gl::TEXTURE_2D, 0);
+            gl_ok!();
+            state::forget_textures();
+        }
+        Ok(())
+    }
+
+    ///The same as `update_region()` except the pixels are streamed through a pixel buffer object.
+    ///Use this for regions that change every frame.
+    pub fn update_region_streamed(
+        &mut self,
+        _sys: &mut SimpleCanvas,
+        stream: &mut PixelStream,
+        pos: [u32; 2],
+        dim: [u32; 2],
+        pixels: &[u8],
+    ) -> Result<(), TextureError> {
+        self.check_region(pos, dim, pixels.len())?;
+        unsafe {
+            gl::BindBuffer(gl::PIXEL_UNPACK_BUFFER, stream.id);
+            gl_ok!();
+
+            //Orphan the old storage so the driver can give us fresh memory
+            //instead of waiting for the last upload to finish.
+            stream.capacity = stream.capacity.max(pixels.len());
+            gl::BufferData(
+                gl::PIXEL_UNPACK_BUFFER,
+                stream.capacity as GLsizeiptr,
+                core::ptr::null(),
+                gl::STREAM_DRAW,
+            );
+            gl_ok!();
+
+            gl::BufferSubData(
+                gl::PIXEL_UNPACK_BUFFER,
+                0,
+                pixels.len() as GLsizeiptr,
+                pixels.as_ptr() as *const _,
+            );
+            gl_ok!();
+
+            gl::BindTexture(gl::TEXTURE_2D, self.id);
+            gl_ok!();
+
+            //With a pixel unpack buffer bound, the pointer is an offset into the buffer.
+            gl::TexSubImage2D(
+                gl::TEXTURE_2D,
+                0,
+                pos[0] as i32,
+                pos[1] as i32,
+                dim[0] as i32,
+                dim[1] as i32,
+                gl::RGBA,
+                gl::UNSIGNED_BYTE,
+                core::ptr::null(),
+            );
+            gl_ok!();
+
+            gl::BindBuffer(gl::PIXEL_UNPACK_BUFFER, 0);
+            gl_ok!();
+
+            self.after_contents_changed();
+
+            gl::BindTexture(gl::TEXTURE_2D, 0);
+            gl_ok!();
+            state::forget_textures();
+        }
+        Ok(())
+    }
+
+    ///Read back a rectangle of the texture as tightly packed rgba pixels.
+    pub fn read_region(
+        &self,
+        _sys: &mut SimpleCanvas,
+        pos: [u32; 2],
+        dim: [u32; 2],
+    ) -> Result<Vec<u8>, TextureError> {
+        let len = dim[0] as usize * dim[1] as usize * 4;
+        self.check_region(pos, dim, len)?;
+
+        let mut pixels = vec![0u8; len];
+        unsafe {
+            //Gles has no glGetTexImage, so attach the texture to a framebuffer and read from that.
+            let mut fbo = 0;
+            gl::GenFramebuffers(1, &mut fbo);
+            gl_ok!();
+
+            gl::BindFramebuffer(gl::FRAMEBUFFER, fbo);
+            gl_ok!();
+
+            gl::FramebufferTexture2D(
+                gl::FRAMEBUFFER,
+                gl::COLOR_ATTACHMENT0,
+                gl::TEXTURE_2D,
+                self.id,
+                0,
+            );
+            gl_ok!();
+
+            gl::PixelStorei(gl::PACK_ALIGNMENT, 1);
+            gl_ok!();
+
+            gl::ReadPixels(
+                pos[0] as i32,
+                pos[1] as i32,
+                dim[0] as i32,
+                dim[1] as i32,
+                gl::RGBA,
+                gl::UNSIGNED_BYTE,
+                pixels.as_mut_ptr() as *mut _,
+            );
+            gl_ok!();
+
+            gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+            gl_ok!();
+
+            gl::DeleteFramebuffers(1, &fbo);
+            gl_ok!();
+        }
+        Ok(pixels)
+    }
+
+    ///Read back the whole texture as tightly packed rgba pixels.
+    pub fn read_pixels(&self, sys: &mut SimpleCanvas) -> Vec<u8> {
+        self.read_region(sys, [0; 2], self.dim_u32()).unwrap()
+    }
+
+    ///Change the size of the texture.
+    ///The part of the old contents that fits is kept in the top left corner,
+    ///and any new space is transparent.
+    pub fn resize(&mut self, sys: &mut SimpleCanvas, dim: [u32; 2]) {
+        let old_dim = self.dim_u32();
+        let old = self.read_pixels(sys);
+
+        let mut pixels = vec![0u8; dim[0] as usize * dim[1] as usize * 4];
+        let w = old_dim[0].min(dim[0]) as usize * 4;
+        for y in 0..old_dim[1].min(dim[1]) as usize {
+            let src = y * old_dim[0] as usize * 4;
+            let dst = y * dim[0] as usize * 4;
+            pixels[dst..dst + w].copy_from_slice(&old[src..src + w]);
+        }
+
+        self.replace_contents(dim, &pixels);
+    }
+
+    //Reallocate the storage of the texture with new pixels, keeping the same opengl texture.
+    fn replace_contents(&self, dim: [u32; 2], pixels: &[u8]) {
+        unsafe {
+            gl::BindTexture(gl::TEXTURE_2D, self.id);
+            gl_ok!();
+
+            gl::TexImage2D(
+                gl::TEXTURE_2D,
+                0,
+                gl::RGBA as i32,
+                dim[0] as i32,
+                dim[1] as i32,
+                0,
+                gl::RGBA,
+                gl::UNSIGNED_BYTE,
+                pixels.as_ptr() as *const _,
+            );
+            gl_ok!();
+
+            self.after_contents_changed();
+
+            gl::BindTexture(gl::TEXTURE_2D, 0);
+            gl_ok!();
+            state::forget_textures();
+        }
+        self.dim.set([dim[0] as f32, dim[1] as f32]);
+    }
+
+    ///The texture is assumed to have the default options.
+    ///Its mipmaps, if any, are assumed to not be generated yet.
     pub unsafe fn new(textureid: GLuint, grid_dim: [u8; 2], dim: [f32; 2]) -> Texture {
         Texture {
             id: textureid,
             grid_dim,
             _ns: ns(),
-            dim,
+            dim: core::cell::Cell::new(dim),
+            options: TextureOptions::default(),
+            has_mipmaps: core::cell::Cell::new(false),
+            layout: None,
+        }
+    }
+}
+
+impl Drop for Texture {
+    fn drop(&mut self) {
+        //Draws waiting in the render queue still use the texture, so it is deleted once they are done.
+        if !defer_delete(self.id) {
+            delete_texture(self.id);
+        }
+    }
+}
+
+fn delete_texture(id: GLuint) {
+    unsafe {
+        gl::DeleteTextures(1, &id);
+        state::forget_textures();
+    }
+}
+
+thread_local! {
+    //The textures used by queued draws, with the number of draws using them,
+    //and whether the texture was dropped while they were queued.
+    static QUEUED: core::cell::RefCell<Vec<(GLuint, usize, bool)>> = const { core::cell::RefCell::new(Vec::new()) };
+}
+
+//Returns false if no queued draw uses the texture, so it can be deleted right away.
+fn defer_delete(id: GLuint) -> bool {
+    QUEUED.with(|q| match q.borrow_mut().iter_mut().find(|t| t.0 == id) {
+        Some(t) => {
+            t.2 = true;
+            true
+        }
+        None => false,
+    })
+}
+
+fn queue(id: GLuint) {
+    QUEUED.with(|q| {
+        let mut q = q.borrow_mut();
+        match q.iter_mut().find(|t| t.0 == id) {
+            Some(t) => t.1 += 1,
+            None => q.push((id, 1, false)),
+        }
+    })
+}
+
+//Returns true if that was the last queued draw of a texture that was dropped, so it has to be deleted now.
+fn unqueue(id: GLuint) -> bool {
+    QUEUED.with(|q| {
+        let mut q = q.borrow_mut();
+        let i = q.iter().position(|t| t.0 == id).expect("texture was not queued");
+        q[i].1 -= 1;
+        q[i].1 == 0 && q.swap_remove(i).2
+    })
+}
+
+///Keeps a texture alive while a draw that uses it waits in the render queue,
+///like the handle of a buffer keeps a saved buffer alive.
+#[derive(Debug)]
+pub(crate) struct QueuedTexture(GLuint);
+
+impl QueuedTexture {
+    pub(crate) fn new(texture: &Texture) -> QueuedTexture {
+        queue(texture.id);
+        QueuedTexture(texture.id)
+    }
+}
+
+impl Clone for QueuedTexture {
+    fn clone(&self) -> QueuedTexture {
+        queue(self.0);
+        QueuedTexture(self.0)
+    }
+}
+
+impl Drop for QueuedTexture {
+    fn drop(&mut self) {
+        if unqueue(self.0) {
+            delete_texture(self.0);
+        }
+    }
+}
+
+///A cheap to clone, reference counted handle to a texture.
+///The texture is deleted when the last handle is dropped.
+///Derefs to `Texture`, so it can be passed anywhere a `&Texture` is expected.
+#[derive(Clone, Debug)]
+pub struct TextureHandle(std::rc::Rc<Texture>);
+
+impl TextureHandle {
+    pub fn new(texture: Texture) -> TextureHandle {
+        TextureHandle(std::rc::Rc::new(texture))
+    }
+
+    ///Returns true if both handles refer to the same texture.
+    pub fn ptr_eq(&self, other: &TextureHandle) -> bool {
+        std::rc::Rc::ptr_eq(&self.0, &other.0)
+    }
+
+    ///The number of handles that refer to this texture.
+    pub fn handle_count(&self) -> usize {
+        std::rc::Rc::strong_count(&self.0)
+    }
+
+    ///Replace the contents of the texture with a new image, which may be a different size.
+    ///Every handle to the texture sees the new contents, and the options of the texture are kept.
+    pub fn replace_image(&self, _sys: &mut SimpleCanvas, img: &image::DynamicImage) {
+        use image::GenericImageView;
+        let (width, height) = img.dimensions();
+        match img {
+            image::DynamicImage::ImageRgba8(img) => self.0.replace_contents([width, height], img),
+            img => self.0.replace_contents([width, height], &img.to_rgba()),
+        }
+    }
+
+    ///Get the texture back if this is the only handle to it.
+    pub fn try_unwrap(self) -> Result<Texture, TextureHandle> {
+        std::rc::Rc::try_unwrap(self.0).map_err(TextureHandle)
+    }
+}
+
+impl From<Texture> for TextureHandle {
+    fn from(texture: Texture) -> TextureHandle {
+        TextureHandle::new(texture)
+    }
+}
+
+impl core::ops::Deref for TextureHandle {
+    type Target = Texture;
+    fn deref(&self) -> &Texture {
+        &self.0
+    }
+}
+
+impl AsRef<Texture> for TextureHandle {
+    fn as_ref(&self) -> &Texture {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extrude() {
+        //Two 1x2 tiles with a pixel of spacing between them.
+        let px = |v: u8| [v, v, v, 255];
+        let pixels: Vec<u8> = [1, 0, 2, 3, 0, 4].iter().flat_map(|&v| px(v).to_vec()).collect();
+        let layout = SheetLayout::new([1, 2]).with_spacing(1);
+
+        let (dim, out, new) = layout.extrude([3, 2], &pixels, 1);
+        assert_eq!(dim, [6, 4]);
+        assert_eq!(new, SheetLayout::new([1, 2]).with_margin(1).with_spacing(2));
+        let row = |y: usize| -> Vec<u8> { out[y * 24..(y + 1) * 24].chunks(4).map(|p| p[0]).collect() };
+        assert_eq!(row(0), vec![1, 1, 1, 2, 2, 2]);
+        assert_eq!(row(1), vec![1, 1, 1, 2, 2, 2]);
+        assert_eq!(row(2), vec![3, 3, 3, 4, 4, 4]);
+        assert_eq!(row(3), vec![3, 3, 3, 4, 4, 4]);
+    }
+
+    #[test]
+    fn dropped_before_flush() {
+        let texture = |id| Texture {
+            _ns: ns(),
+            grid_dim: [1, 1],
+            dim: core::cell::Cell::new([1.0, 1.0]),
+            id,
+            options: TextureOptions::default(),
+            has_mipmaps: core::cell::Cell::new(false),
+            layout: None,
+        };
+
+        //Two queued draws use the texture. Dropping it must not delete it yet,
+        //which would call into opengl here, where there is no context.
+        let t = texture(1000);
+        let first = QueuedTexture::new(&t);
+        let second = first.clone();
+        drop(t);
+        assert!(defer_delete(1000));
+
+        //Flushing drops the draws. Only the last one deletes the texture.
+        core::mem::forget(first);
+        core::mem::forget(second);
+        assert!(!unqueue(1000));
+        assert!(unqueue(1000));
+        assert!(!defer_delete(1000));
+
+        //A texture that is still alive is not deleted when its draws are done.
+        let t = texture(1001);
+        core::mem::forget(QueuedTexture::new(&t));
+        assert!(!unqueue(1001));
+        core::mem::forget(t);
+    }
+
+    #[test]
+    fn fits() {
+        let layout = SheetLayout::new([16, 16]).with_margin(1).with_spacing(2);
+        assert!(layout.fits([18, 18]));
+        assert!(layout.fits([36, 18]));
+        //Smaller than one tile with its margin.
+        assert!(!layout.fits([17, 18]));
+        assert!(!layout.fits([18, 0]));
+        assert!(!SheetLayout::new([16, 16]).fits([15, 64]));
+        assert!(!SheetLayout::new([0, 16]).fits([15, 64]));
+
+        assert!(SheetLayout::new([1, 1]).fits([255, 1]));
+        assert!(!SheetLayout::new([1, 1]).fits([256, 1]));
+
+        //Extruding keeps the same tiles.
+        let pixels = vec![0; 36 * 18 * 4];
+        let (dim, _, extruded) = layout.extrude([36, 18], &pixels, 3);
+        assert_eq!(extruded.grid_dim(dim), layout.grid_dim([36, 18]));
+    }
+
+    #[test]
+    fn extrude_nothing() {
+        let pixels = vec![7u8; 2 * 2 * 4];
+        for (layout, amount) in [
+            (SheetLayout::new([0, 2]), 1),
+            (SheetLayout::new([2, 0]), 1),
+            (SheetLayout::new([1, 1]).with_spacing(3), 0),
+        ]
+        .iter()
+        {
+            assert_eq!(layout.extrude([2, 2], &pixels, *amount), ([2, 2], pixels.clone(), *layout));
         }
     }
 }
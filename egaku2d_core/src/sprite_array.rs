@@ -0,0 +1,273 @@
+//! Contains the texture array sprite drawing code.
+//!
+//! A `TextureArray` is a stack of same sized tile sets in one `GL_TEXTURE_2D_ARRAY`.
+//! Every sprite picks a layer of the array as well as a tile in it,
+//! so sprites out of different sheets can be mixed in one draw call, keeping the order they were added in.
+//! Tile indicies are 32 bit, so the grid of a sheet can be larger than 256x256.
+
+use super::*;
+use crate::sprite::*;
+use sprite_program::ArrayVertex;
+
+#[derive(Debug)]
+pub struct TextureArray {
+    _ns: NotSend,
+    pub(crate) grid_dim: [u16; 2],
+    pub(crate) dim: [f32; 2],
+    pub(crate) id: GLuint,
+    num_layers: u16,
+    options: TextureOptions,
+    has_mipmaps: bool,
+}
+
+impl TextureArray {
+    ///Create a texture array out of images that must all be the same size.
+    ///Fails with `image::ImageError::DimensionError` if they are not.
+    pub fn from_files(
+        sys: &mut SimpleCanvas,
+        files: &[impl AsRef<std::path::Path>],
+        grid_dim: [u16; 2],
+    ) -> image::ImageResult<TextureArray> {
+        let imgs = files
+            .iter()
+            .map(image::open)
+            .collect::<Result<Vec<_>, _>>()?;
+        TextureArray::from_images(sys, &imgs, grid_dim)
+    }
+
+    ///Create a texture array out of images that must all be the same size.
+    ///Fails with `image::ImageError::DimensionError` if they are not.
+    pub fn from_images(
+        sys: &mut SimpleCanvas,
+        imgs: &[image::DynamicImage],
+        grid_dim: [u16; 2],
+    ) -> image::ImageResult<TextureArray> {
+        let imgs: Vec<_> = imgs.iter().map(|img| img.to_rgba()).collect();
+        let dim = match imgs.first() {
+            Some(img) => [img.width(), img.height()],
+            None => return Err(image::ImageError::DimensionError),
+        };
+        if imgs.iter().any(|img| [img.width(), img.height()] != dim) {
+            return Err(image::ImageError::DimensionError);
+        }
+        let layers: Vec<&[u8]> = imgs.iter().map(|img| &**img).collect();
+        Ok(TextureArray::from_rgba_layers(sys, dim, &layers, grid_dim))
+    }
+
+    ///Create a texture array out of layers of tightly packed rgba pixels,
+    ///each of which must be of the specified dimensions.
+    pub fn from_rgba_layers(
+        _sys: &mut SimpleCanvas,
+        dim: [u32; 2],
+        layers: &[&[u8]],
+        grid_dim: [u16; 2],
+    ) -> TextureArray {
+        assert!(!layers.is_empty(), "a texture array needs at least one layer");
+        assert!(layers.len() <= u16::MAX as usize, "too many layers");
+        for layer in layers.iter() {
+            assert_eq!(
+                layer.len(),
+                dim[0] as usize * dim[1] as usize * 4,
+                "pixel slice does not match the dimensions"
+            );
+        }
+
+        let mut id = 0;
+        unsafe {
+            gl::GenTextures(1, &mut id);
+            gl_ok!();
+
+            gl::BindTexture(gl::TEXTURE_2D_ARRAY, id);
+            gl_ok!();
+
+            gl::TexImage3D(
+                gl::TEXTURE_2D_ARRAY,
+                0,
+                gl::RGBA as i32,
+                dim[0] as i32,
+                dim[1] as i32,
+                layers.len() as i32,
+                0,
+                gl::RGBA,
+                gl::UNSIGNED_BYTE,
+                core::ptr::null(),
+            );
+            gl_ok!();
+
+            for (i, layer) in layers.iter().enumerate() {
+                gl::TexSubImage3D(
+                    gl::TEXTURE_2D_ARRAY,
+                    0,
+                    0,
+                    0,
+                    i as i32,
+                    dim[0] as i32,
+                    dim[1] as i32,
+                    1,
+                    gl::RGBA,
+                    gl::UNSIGNED_BYTE,
+                    layer.as_ptr() as *const _,
+                );
+                gl_ok!();
+            }
+
+            let options = TextureOptions::default();
+            apply_options(gl::TEXTURE_2D_ARRAY, &options, false);
+
+            gl::BindTexture(gl::TEXTURE_2D_ARRAY, 0);
+            gl_ok!();
+            state::forget_textures();
+        }
+
+        TextureArray {
+            _ns: ns(),
+            grid_dim,
+            dim: [dim[0] as f32, dim[1] as f32],
+            id,
+            num_layers: layers.len() as u16,
+            options: TextureOptions::default(),
+            has_mipmaps: false,
+        }
+    }
+
+    pub fn grid_dim(&self) -> [u16; 2] {
+        self.grid_dim
+    }
+
+    ///The dimensions of one layer.
+    pub fn dim(&self) -> [f32; 2] {
+        self.dim
+    }
+
+    pub fn num_layers(&self) -> u16 {
+        self.num_layers
+    }
+
+    ///Create a texture index from a coordinate in the tile sets.
+    ///The top left tile maps to 0,0.
+    pub fn coord_to_index(&self, cell: [u16; 2]) -> u32 {
+        cell[0] as u32 + cell[1] as u32 * self.grid_dim[0] as u32
+    }
+
+    pub fn options(&self) -> TextureOptions {
+        self.options
+    }
+
+    ///Change how the texture array is sampled. Works the same as `Texture::set_options()`.
+    pub fn set_options(&mut self, _sys: &mut SimpleCanvas, options: TextureOptions) {
+        unsafe {
+            gl::BindTexture(gl::TEXTURE_2D_ARRAY, self.id);
+            gl_ok!();
+
+            if apply_options(gl::TEXTURE_2D_ARRAY, &options, self.has_mipmaps) {
+                self.has_mipmaps = true;
+            }
+
+            gl::BindTexture(gl::TEXTURE_2D_ARRAY, 0);
+            gl_ok!();
+            state::forget_textures();
+        }
+        self.options = options;
+    }
+}
+
+impl Drop for TextureArray {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteTextures(1, &self.id);
+            state::forget_textures();
+        }
+    }
+}
+
+pub struct SpriteArraySave {
+    _ns: NotSend,
+    buffer: vbo::StaticBuffer<ArrayVertex>,
+}
+
+impl SpriteArraySave {
+    pub fn uniforms<'a>(
+        &'a self,
+        sys: &'a mut SimpleCanvas,
+        texture: &'a TextureArray,
+        radius: f32,
+    ) -> Uniforms<'a> {
+        let common = sys.common(sys.offset);
+        let un = sprite_program::SpriteArrayUniformValues {
+            radius: radius * core::f32::consts::SQRT_2,
+            texture,
+        };
+        Uniforms {
+            sys,
+            common,
+            un: UniformVals::SpriteArray(un),
+            buffer: queue::Vertices::Uploaded(self.buffer.get_info()),
+        }
+    }
+}
+
+pub struct SpriteArraySession {
+    pub(crate) verts: Vec<ArrayVertex>,
+}
+
+impl Default for SpriteArraySession {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SpriteArraySession {
+    pub fn new() -> Self {
+        SpriteArraySession { verts: Vec::new() }
+    }
+
+    ///Add a point sprite using the tile at the index in the layer of the texture array.
+    #[inline(always)]
+    pub fn add(&mut self, point: PointType, layer: u16, index: u32, rotation: f32) -> &mut Self {
+        let k = rotation.rem_euclid(core::f32::consts::PI * 2.);
+        let k = k / (core::f32::consts::PI * 2.);
+        let k = (k * (u16::MAX as f32)) as u16;
+
+        self.verts.push(ArrayVertex {
+            pos: point,
+            index,
+            layer,
+            rotation: k,
+        });
+        self
+    }
+
+    pub fn append(&mut self, other: &mut Self) {
+        self.verts.append(&mut other.verts);
+    }
+
+    pub fn save(&mut self, _sys: &mut SimpleCanvas) -> SpriteArraySave {
+        SpriteArraySave {
+            _ns: ns(),
+            buffer: vbo::StaticBuffer::new(&self.verts),
+        }
+    }
+
+    pub fn send_and_uniforms<'a>(
+        &'a mut self,
+        sys: &'a mut SimpleCanvas,
+        texture: &'a TextureArray,
+        radius: f32,
+    ) -> Uniforms<'a> {
+        sys.sprite_array_buffer.send_to_gpu(&self.verts);
+
+        let common = sys.common(sys.offset);
+        let un = sprite_program::SpriteArrayUniformValues {
+            radius: radius * core::f32::consts::SQRT_2,
+            texture,
+        };
+
+        let buffer = sys.sprite_array_buffer.get_info(self.verts.len());
+        Uniforms {
+            sys,
+            common,
+            un: UniformVals::SpriteArray(un),
+            buffer: queue::Vertices::Uploaded(buffer),
+        }
+    }
+}
@@ -4,6 +4,7 @@ use std::ffi::CString;
 use std::str;
 
 use super::*;
+use crate::state::{GlState, UniformValue, VaoKey};
 use crate::vbo::BufferInfo;
 
 // Shader sources
@@ -12,9 +13,16 @@ static VS_SRC: &'static str = "
 in vec2 position;
 in float rotation;
 in uint cellindex;
+in vec4 color;
+in vec2 flip;
+in vec2 instance_offset;
+in vec4 instance_matrix;
+in vec4 instance_color;
 
 out vec2 texture_offset;
 out mat2 rot_matrix;
+out vec4 vcolor;
+out vec2 vflip;
 
 uniform vec2 offset;
 uniform ivec2 grid_dim;
@@ -23,15 +31,21 @@ uniform vec2 sprite_dim;
 
 uniform mat3 mmatrix;
 uniform float point_size;
-
-const float PI = 3.1415926535897932384626433832795;
+uniform mat3 transform;
+uniform float rotation_scale;
+uniform vec2 position_origin;
+uniform float position_step;
 
 void main() {
     gl_PointSize = point_size;
-    vec3 pp = vec3(position.xy+offset,1.0);
+    vec2 p = position*position_step+position_origin;
+    vec3 t = transform*vec3(mat2(instance_matrix)*p+instance_offset,1.0);
+    vec3 pp = vec3(t.xy/t.z+offset,1.0);
     gl_Position = vec4(mmatrix*pp.xyz, 1.0);
+    vcolor = color*instance_color;
+    vflip = flip;
 
-    float rot=rotation*(PI*2.0);
+    float rot=rotation*rotation_scale;
     float c=cos(rot);
     float s=sin(rot);
 
@@ -54,8 +68,12 @@ static FS_SRC: &'static str = "
 precision mediump float;
 in vec2 texture_offset;
 in mat2 rot_matrix;
-uniform highp ivec2 grid_dim;
+in vec4 vcolor;
+in vec2 vflip;
 uniform highp vec2 sprite_dim;
+uniform highp vec2 tile_uv;
+uniform highp vec2 stride_uv;
+uniform highp vec2 margin_uv;
 uniform sampler2D tex0;
 uniform vec4 bcol;
 out vec4 out_color;
@@ -71,11 +89,125 @@ const vec2 mid=vec2(0.5,0.5);
 
 void main() 
 {
-    vec2 dim=vec2(float(grid_dim.x),float(grid_dim.y));
-    mat2 grid_dim2=mat2(1.0/dim.x,0.0,0.0,1.0/dim.y);
+    //Handle rotation before we do anything.`
+    vec2 pos=  (rot_matrix*( (gl_PointCoord.xy-mid)*vflip) + mid);
+    
+    vec2 extra=vec2(max(0.0,(sprite_dim.y-sprite_dim.x)/3.0),max(0.0,(sprite_dim.x-sprite_dim.y)/3.0)) ;
+    extra.x+=0.01; //TODO why is this needed?
+    extra.y+=0.01; // I think some of the math needs to be simplified. floating point loss ofprecision??
+
+    //Now we make sure we don't draw anything in the wasted areas of the outer
+    //rectangle.
+    if (pos.x>=(1.0-s2-extra.x) || pos.x<(0.0+s2+extra.x) || pos.y>=(1.0-s2-extra.y) || pos.y<(0.0+s2+extra.y)){
+        discard;
+    }else{     
     
+        vec2 pp1=(pos-mid)/sprite_dim+mid;
+        
+        //We must start drawing the sprite at the inner rectangle top left corder,
+        //instead of the default 0,0 since that would be the start of the
+        //outer rectangle.
+        //Here we also make sure we draw the right tile in the tileset
+        vec2 pp2=(pp1-vec2(s2,s2))*SQRT2;
+                
+
+
+        //Skip the margin, and the tiles and spacing before this tile.
+        vec2 foo = margin_uv + texture_offset*stride_uv + pp2*tile_uv;
+
+        out_color=texture(tex0,foo)*bcol*vcolor;
+    }
+}
+";
+
+//The same as the shaders above, except they sample a layer of a texture array.
+static ARRAY_VS_SRC: &str = "
+#version 300 es
+in vec2 position;
+in float rotation;
+in uint cellindex;
+in vec4 color;
+in vec2 flip;
+in vec2 instance_offset;
+in vec4 instance_matrix;
+in vec4 instance_color;
+in uint layer;
+
+out vec2 texture_offset;
+out mat2 rot_matrix;
+out vec4 vcolor;
+out vec2 vflip;
+flat out float vlayer;
+
+uniform vec2 offset;
+uniform ivec2 grid_dim;
+uniform vec2 sprite_dim;
+
+
+uniform mat3 mmatrix;
+uniform float point_size;
+uniform mat3 transform;
+uniform float rotation_scale;
+uniform vec2 position_origin;
+uniform float position_step;
+
+void main() {
+    gl_PointSize = point_size;
+    vec2 p = position*position_step+position_origin;
+    vec3 t = transform*vec3(mat2(instance_matrix)*p+instance_offset,1.0);
+    vec3 pp = vec3(t.xy/t.z+offset,1.0);
+    gl_Position = vec4(mmatrix*pp.xyz, 1.0);
+    vcolor = color*instance_color;
+    vflip = flip;
+    vlayer = float(layer);
+
+    float rot=rotation*rotation_scale;
+    float c=cos(rot);
+    float s=sin(rot);
+
+    rot_matrix[0]=vec2(c,-s);
+    rot_matrix[1]=vec2(s,c);
+
+    int cellindex = int(cellindex);
+
+    //Force cellindex to be in a valid range
+    cellindex = cellindex % (grid_dim.x * grid_dim.y);
+
+    ivec2 ce=ivec2(cellindex % (grid_dim.x), cellindex / (grid_dim.x));
+
+    texture_offset.x=float(ce.x);
+    texture_offset.y=float(ce.y);
+}";
+
+static ARRAY_FS_SRC: &str = "
+#version 300 es
+precision mediump float;
+in vec2 texture_offset;
+in mat2 rot_matrix;
+in vec4 vcolor;
+in vec2 vflip;
+flat in float vlayer;
+uniform highp vec2 sprite_dim;
+uniform highp vec2 tile_uv;
+uniform highp vec2 stride_uv;
+uniform highp vec2 margin_uv;
+uniform highp sampler2DArray tex0;
+uniform vec4 bcol;
+out vec4 out_color;
+
+const float SQRT2=1.41421356237;
+
+//This is the offset from the outer rectangle to the inner rectangle.
+//We need a larger outer rectangle since if the sprite rotates, its corners would clip.
+//The width of the outer rectangle needs to be sqrt(2)*normal rectangle.
+const float s2=(SQRT2-1.0)/(2.0*SQRT2);
+
+const vec2 mid=vec2(0.5,0.5);
+
+void main() 
+{
     //Handle rotation before we do anything.`
-    vec2 pos=  (rot_matrix*( (gl_PointCoord.xy-mid)) + mid);
+    vec2 pos=  (rot_matrix*( (gl_PointCoord.xy-mid)*vflip) + mid);
     
     vec2 extra=vec2(max(0.0,(sprite_dim.y-sprite_dim.x)/3.0),max(0.0,(sprite_dim.x-sprite_dim.y)/3.0)) ;
     extra.x+=0.01; //TODO why is this needed?
@@ -97,9 +229,10 @@ void main()
                 
 
 
-        vec2 foo =  (pp2+ texture_offset)*grid_dim2;
+        //Skip the margin, and the tiles and spacing before this tile.
+        vec2 foo = margin_uv + texture_offset*stride_uv + pp2*tile_uv;
 
-        out_color=texture(tex0,foo)*bcol;
+        out_color=texture(tex0,vec3(foo,vlayer))*bcol*vcolor;
     }
 }
 ";
@@ -107,111 +240,250 @@ void main()
 #[repr(packed(4))]
 #[derive(Copy, Clone, Debug, Default)]
 pub struct Vertex {
-    pub pos: [f32; 2], //TODO use half floats??
+    pub pos: [f32; 2],
     pub index: u16,
     pub rotation: u16,
 }
 
+///A sprite vertex with its position stored in half the space. See the `compact` module.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct CompactVertex {
+    pub pos: [u16; 2],
+    pub index: u16,
+    pub rotation: u16,
+}
+
+///A sprite vertex that also carries its own color.
+///Used for text where every glyph can have a different color
+///while still being drawn in one draw call.
+#[repr(C, packed(4))]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct ColoredVertex {
+    pub pos: [f32; 2],
+    pub index: u16,
+    pub rotation: u16,
+    pub color: [u8; 4],
+}
+
+///A sprite vertex that can also be mirrored along x and y.
+///Each flip component is either 1 or -1.
+///Combined with rotation this can express every flip of a tile.
+#[repr(C, packed(4))]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct FlippedVertex {
+    pub pos: [f32; 2],
+    pub index: u16,
+    pub rotation: u16,
+    pub flip: [i8; 2],
+    pub _pad: [i8; 2],
+}
+
+///A sprite vertex for texture arrays, that picks a layer of the array as well as a tile in it.
+#[repr(C, packed(4))]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct ArrayVertex {
+    pub pos: [f32; 2],
+    pub index: u32,
+    pub layer: u16,
+    pub rotation: u16,
+}
+
+///The vertex type in the buffer being drawn.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum VertexFormat {
+    Plain,
+    Colored,
+    Flipped,
+    Array,
+    ///A batch of user structs.
+    Batch(batch::Layout),
+    ///`CompactVertex`, stored with the precision.
+    Compact(compact::Precision),
+}
+
+impl VertexFormat {
+    //Tells vertex array objects of the different formats apart.
+    fn variant(self) -> u32 {
+        match self {
+            VertexFormat::Plain => 0,
+            VertexFormat::Colored => 1,
+            VertexFormat::Flipped => 2,
+            VertexFormat::Array => 3,
+            VertexFormat::Batch(_) => 4,
+            VertexFormat::Compact(precision) => 5 + precision.variant(),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct SpriteProgram {
     pub program: GLuint,
     pub matrix_uniform: GLint,
+    pub transform_uniform: GLint,
+    pub(crate) instance_attrs: instance::InstanceAttrs,
     pub square_uniform: GLint,
     pub offset_uniform: GLint,
     pub point_size_uniform: GLint,
     pub grid_dim_uniform: GLint,
     pub sprite_dim_uniform: GLint,
+    pub tile_uv_uniform: GLint,
+    pub stride_uv_uniform: GLint,
+    pub margin_uv_uniform: GLint,
     pub bcol_uniform: GLint,
     pub pos_attr: GLint,
     pub rotation_attr: GLint,
     pub index_attr: GLint,
+    pub color_attr: GLint,
+    pub flip_attr: GLint,
+    pub layer_attr: GLint,
     pub sample_location: GLint,
+    pub rotation_scale_uniform: GLint,
+    pub position_origin_uniform: GLint,
+    pub position_step_uniform: GLint,
 }
 
-#[derive(Debug)]
-pub struct PointMul(pub f32);
-
 #[derive(Copy, Clone, Debug)]
 pub struct SpriteProgramUniformValues<'a> {
     pub texture: &'a crate::sprite::Texture,
     pub radius: f32,
+    pub format: VertexFormat,
 }
 
-impl SpriteProgram {
-    pub fn set_viewport(
-        &mut self,
-        window_dim: FixedAspectVec2,
-        game_width: f32,
-    ) -> PointMul {
-        let game_height = window_dim.ratio.height_over_width() as f32 * game_width;
-
-        let scalex = 2.0 / game_width;
-        let scaley = 2.0 / game_height;
+#[derive(Copy, Clone, Debug)]
+pub struct SpriteArrayUniformValues<'a> {
+    pub texture: &'a crate::sprite_array::TextureArray,
+    pub radius: f32,
+}
 
-        let tx = -1.0;
-        let ty = 1.0;
+//What the program needs to know about the texture being drawn.
+//The tile, stride and margin are in pixels.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub(crate) struct SpriteTexture {
+    target: GLenum,
+    pub(crate) id: GLuint,
+    dim: [f32; 2],
+    grid_dim: [i32; 2],
+    tile: [f32; 2],
+    stride: [f32; 2],
+    margin: [f32; 2],
+}
 
-        let matrix = [[scalex, 0.0, 0.0], [0.0, -scaley, 0.0], [tx, ty, 1.0]];
+impl SpriteTexture {
+    pub(crate) fn new(texture: &crate::sprite::Texture) -> SpriteTexture {
+        let (tile, stride, margin) = texture.tile_geometry();
+        SpriteTexture {
+            target: gl::TEXTURE_2D,
+            id: texture.id,
+            dim: texture.dim(),
+            grid_dim: [texture.grid_dim[0] as i32, texture.grid_dim[1] as i32],
+            tile,
+            stride,
+            margin,
+        }
+    }
+}
 
+impl SpriteProgram {
+    pub fn set_matrix(&mut self, matrix: &[[f32; 3]; 3]) {
         unsafe {
             gl::UseProgram(self.program);
             gl_ok!();
-            gl::UniformMatrix3fv(
-                self.matrix_uniform,
-                1,
-                0,
-                std::mem::transmute(&matrix[0][0]),
-            );
+            gl::UniformMatrix3fv(self.matrix_uniform, 1, 0, matrix[0].as_ptr());
             gl_ok!();
         }
-
-        PointMul(window_dim.width as f32 / game_width)
     }
 
     pub(crate) fn set_buffer_and_draw(
         &mut self,
+        state: &mut GlState,
         common: &UniformCommon,
         un: &SpriteProgramUniformValues,
         buffer_info: BufferInfo,
+    ) {
+        let texture = SpriteTexture::new(un.texture);
+        self.draw(state, common, texture, un.radius, un.format, buffer_info);
+    }
+
+    ///Only valid for a program made with `new_array()`.
+    pub(crate) fn set_array_buffer_and_draw(
+        &mut self,
+        state: &mut GlState,
+        common: &UniformCommon,
+        un: &SpriteArrayUniformValues,
+        buffer_info: BufferInfo,
+    ) {
+        let dim = un.texture.dim();
+        let grid_dim = un.texture.grid_dim;
+        let tile = [dim[0] / grid_dim[0] as f32, dim[1] / grid_dim[1] as f32];
+        let texture = SpriteTexture {
+            target: gl::TEXTURE_2D_ARRAY,
+            id: un.texture.id,
+            dim,
+            grid_dim: [grid_dim[0] as i32, grid_dim[1] as i32],
+            tile,
+            stride: tile,
+            margin: [0.0; 2],
+        };
+        self.draw(state, common, texture, un.radius, VertexFormat::Array, buffer_info);
+    }
+
+    pub(crate) fn draw(
+        &mut self,
+        state: &mut GlState,
+        common: &UniformCommon,
+        texture: SpriteTexture,
+        radius: f32,
+        format: VertexFormat,
+        buffer_info: BufferInfo,
     ) {
         let col = common.color;
         let buffer_id = buffer_info.id;
-        let length = buffer_info.length;
-        let point_size = un.radius;
+        let point_size = radius;
         let mode = gl::POINTS;
-        let texture = un.texture;
-        let texture_id = un.texture.id;
+        let texture_id = texture.id;
         let offset = common.offset;
+        let stride = match format {
+            VertexFormat::Plain => core::mem::size_of::<Vertex>(),
+            VertexFormat::Colored => core::mem::size_of::<ColoredVertex>(),
+            VertexFormat::Flipped => core::mem::size_of::<FlippedVertex>(),
+            VertexFormat::Array => core::mem::size_of::<ArrayVertex>(),
+            VertexFormat::Batch(layout) => layout.size,
+            VertexFormat::Compact(_) => core::mem::size_of::<CompactVertex>(),
+        } as i32;
 
         unsafe {
-            gl::UseProgram(self.program);
-            gl_ok!();
-
-            gl::Uniform1f(self.point_size_uniform, point_size);
-            gl_ok!();
+            state.use_program(self.program);
 
-            gl::Uniform2f(self.offset_uniform, offset.x, offset.y);
-            gl_ok!();
-
-            gl::Uniform4fv(self.bcol_uniform, 1, col.as_ptr() as *const _);
-            gl_ok!();
+            state.uniform(self.point_size_uniform, UniformValue::Float(point_size));
+            state.uniform(self.offset_uniform, UniformValue::Vec2([offset.x, offset.y]));
+            state.uniform(self.transform_uniform, UniformValue::Mat3(common.transform.matrix()));
+            state.uniform(self.bcol_uniform, UniformValue::Vec4(col));
 
-            gl::BindBuffer(gl::ARRAY_BUFFER, buffer_id);
-            gl_ok!();
-
-            gl::ActiveTexture(gl::TEXTURE0);
-            gl_ok!();
+            //Batches are rotated in radians, and vertices in turns stored in a normalized u16.
+            let rotation_scale = match format {
+                VertexFormat::Batch(_) => 1.0,
+                _ => core::f32::consts::PI * 2.0,
+            };
+            state.uniform(self.rotation_scale_uniform, UniformValue::Float(rotation_scale));
 
-            gl::BindTexture(gl::TEXTURE_2D, texture_id);
-            gl_ok!();
+            let (origin, step) = match format {
+                VertexFormat::Compact(precision) => precision.origin_and_step(),
+                _ => ([0.0; 2], 1.0),
+            };
+            state.uniform(self.position_origin_uniform, UniformValue::Vec2(origin));
+            state.uniform(self.position_step_uniform, UniformValue::Float(step));
 
-            gl::Uniform1i(self.sample_location, 0);
-            gl_ok!();
+            state.bind_texture(texture.target, texture_id);
+            state.uniform(self.sample_location, UniformValue::Int(0));
 
             assert_eq!(core::mem::size_of::<Vertex>(), 4 * 3);
+            assert_eq!(core::mem::size_of::<ColoredVertex>(), 4 * 4);
+            assert_eq!(core::mem::size_of::<FlippedVertex>(), 4 * 4);
+            assert_eq!(core::mem::size_of::<ArrayVertex>(), 4 * 4);
+            assert_eq!(core::mem::size_of::<CompactVertex>(), 4 * 2);
 
-            let sx = texture.dim[0] / (texture.grid_dim[0] as f32);
-            let sy = texture.dim[1] / (texture.grid_dim[1] as f32);
+            let [sx, sy] = texture.tile;
 
             let sprite_dim = if sx > sy {
                 [1.0, sy / sx]
@@ -221,79 +493,223 @@ impl SpriteProgram {
 
             //Either the x or y component MUST be 1.0
             //The other component must be less than or equal to 1.0.
-            gl::Uniform2f(self.sprite_dim_uniform, sprite_dim[0], sprite_dim[1]);
-            gl_ok!();
+            state.uniform(self.sprite_dim_uniform, UniformValue::Vec2(sprite_dim));
+            state.uniform(self.grid_dim_uniform, UniformValue::IVec2(texture.grid_dim));
 
-            gl::Uniform2i(
-                self.grid_dim_uniform,
-                texture.grid_dim[0] as i32,
-                texture.grid_dim[1] as i32,
+            let [w, h] = texture.dim;
+            state.uniform(
+                self.tile_uv_uniform,
+                UniformValue::Vec2([texture.tile[0] / w, texture.tile[1] / h]),
+            );
+            state.uniform(
+                self.stride_uv_uniform,
+                UniformValue::Vec2([texture.stride[0] / w, texture.stride[1] / h]),
+            );
+            state.uniform(
+                self.margin_uv_uniform,
+                UniformValue::Vec2([texture.margin[0] / w, texture.margin[1] / h]),
             );
-            gl_ok!();
 
-            gl::EnableVertexAttribArray(self.pos_attr as GLuint);
-            gl_ok!();
+            let batch = match format {
+                VertexFormat::Batch(layout) => Some(layout),
+                _ => None,
+            };
 
-            gl::VertexAttribPointer(
-                self.pos_attr as GLuint,
-                2,
-                gl::FLOAT,
-                gl::FALSE as GLboolean,
-                3 * 4 as i32,
-                0 as *const _,
-            );
-            gl_ok!();
+            let key = VaoKey {
+                program: self.program,
+                buffer: buffer_id,
+                stride,
+                variant: format.variant(),
+                batch,
+            };
+            state.bind_vertex_array(key, || self.setup_attributes(buffer_id, stride, format));
 
-            gl::EnableVertexAttribArray(self.index_attr as GLuint);
-            gl_ok!();
+            if batch.is_some_and(|l| l.index.is_none()) {
+                gl::VertexAttribI4ui(self.index_attr as GLuint, 0, 0, 0, 0);
+                gl_ok!();
+            }
+            if batch.is_some_and(|l| l.rotation.is_none()) {
+                gl::VertexAttrib1f(self.rotation_attr as GLuint, 0.0);
+                gl_ok!();
+            }
 
-            gl::VertexAttribIPointer(
-                self.index_attr as GLuint,
-                1,
-                gl::UNSIGNED_SHORT,
-                (3 * 4) as i32,
-                (4 * 2) as *const _,
-            );
-            gl_ok!();
+            if format != VertexFormat::Colored && batch.and_then(|l| l.color).is_none() {
+                //With the attribute array disabled, every vertex gets this constant value.
+                gl::VertexAttrib4f(self.color_attr as GLuint, 1.0, 1.0, 1.0, 1.0);
+                gl_ok!();
+            }
 
-            gl::EnableVertexAttribArray(self.rotation_attr as GLuint);
-            gl_ok!();
+            if format != VertexFormat::Flipped {
+                gl::VertexAttrib2f(self.flip_attr as GLuint, 1.0, 1.0);
+                gl_ok!();
+            }
 
-            gl::VertexAttribPointer(
-                self.rotation_attr as GLuint,
-                1,
-                gl::UNSIGNED_SHORT,
-                gl::TRUE,
-                3 * 4 as i32,
-                ((4 * 2) + 2) as *const _,
-            );
-            gl_ok!();
+            self.instance_attrs.enable(common.instances);
 
-            gl::DrawArrays(mode, 0 as i32, length as i32);
+            self.instance_attrs.draw(mode, buffer_info, common.instances);
 
-            gl_ok!();
+            self.instance_attrs.disable(common.instances);
+        }
+    }
 
-            gl::DisableVertexAttribArray(self.pos_attr as GLuint);
-            gl_ok!();
+    //Set up the attributes of a new vertex array object. Returns the number of calls made.
+    unsafe fn setup_attributes(&self, buffer_id: GLuint, stride: i32, format: VertexFormat) -> usize {
+        gl::BindBuffer(gl::ARRAY_BUFFER, buffer_id);
+        gl_ok!();
 
-            gl::DisableVertexAttribArray(self.index_attr as GLuint);
-            gl_ok!();
+        if let VertexFormat::Batch(layout) = format {
+            return 1 + self.setup_batch_attributes(stride, layout);
+        }
+
+        //Compact vertices have a smaller position, with everything else moved up.
+        let (pos_type, pos_size) = match format {
+            VertexFormat::Compact(precision) => (precision.gl_type(), 2 * 2),
+            _ => (gl::FLOAT, 4 * 2),
+        };
+
+        gl::EnableVertexAttribArray(self.pos_attr as GLuint);
+        gl_ok!();
+
+        gl::VertexAttribPointer(
+            self.pos_attr as GLuint,
+            2,
+            pos_type,
+            gl::FALSE as GLboolean,
+            stride,
+            core::ptr::null(),
+        );
+        gl_ok!();
+
+        gl::EnableVertexAttribArray(self.index_attr as GLuint);
+        gl_ok!();
+
+        //Array vertices have a larger index, with the layer after it.
+        let (index_type, rotation_offset) = if format == VertexFormat::Array {
+            (gl::UNSIGNED_INT, pos_size + 4 + 2)
+        } else {
+            (gl::UNSIGNED_SHORT, pos_size + 2)
+        };
+
+        gl::VertexAttribIPointer(
+            self.index_attr as GLuint,
+            1,
+            index_type,
+            stride,
+            pos_size as *const _,
+        );
+        gl_ok!();
+
+        gl::EnableVertexAttribArray(self.rotation_attr as GLuint);
+        gl_ok!();
+
+        gl::VertexAttribPointer(
+            self.rotation_attr as GLuint,
+            1,
+            gl::UNSIGNED_SHORT,
+            gl::TRUE,
+            stride,
+            rotation_offset as *const _,
+        );
+        gl_ok!();
+
+        match format {
+            //Batches are set up above.
+            VertexFormat::Plain | VertexFormat::Compact(_) | VertexFormat::Batch(_) => {}
+            VertexFormat::Array => {
+                gl::EnableVertexAttribArray(self.layer_attr as GLuint);
+                gl_ok!();
+
+                gl::VertexAttribIPointer(
+                    self.layer_attr as GLuint,
+                    1,
+                    gl::UNSIGNED_SHORT,
+                    stride,
+                    ((4 * 2) + 4) as *const _,
+                );
+                gl_ok!();
+            }
+            VertexFormat::Colored => {
+                gl::EnableVertexAttribArray(self.color_attr as GLuint);
+                gl_ok!();
+
+                gl::VertexAttribPointer(
+                    self.color_attr as GLuint,
+                    4,
+                    gl::UNSIGNED_BYTE,
+                    gl::TRUE,
+                    stride,
+                    (4 * 3) as *const _,
+                );
+                gl_ok!();
+            }
+            VertexFormat::Flipped => {
+                gl::EnableVertexAttribArray(self.flip_attr as GLuint);
+                gl_ok!();
+
+                gl::VertexAttribPointer(
+                    self.flip_attr as GLuint,
+                    2,
+                    gl::BYTE,
+                    gl::FALSE,
+                    stride,
+                    (4 * 3) as *const _,
+                );
+                gl_ok!();
+            }
+        }
+
+        match format {
+            VertexFormat::Plain | VertexFormat::Compact(_) => 7,
+            _ => 9,
+        }
+    }
 
-            gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+    //The fields of a batch that are missing get constant values when drawing.
+    unsafe fn setup_batch_attributes(&self, stride: i32, layout: batch::Layout) -> usize {
+        let mut calls = 0;
+        let floats = [
+            (self.pos_attr, 2, layout.position),
+            (self.rotation_attr, 1, layout.rotation),
+            (self.color_attr, 4, layout.color),
+        ];
+        for &(attr, size, offset) in floats.iter() {
+            if let Some(offset) = offset {
+                gl::EnableVertexAttribArray(attr as GLuint);
+                gl_ok!();
+
+                gl::VertexAttribPointer(attr as GLuint, size, gl::FLOAT, gl::FALSE, stride, offset as *const _);
+                gl_ok!();
+                calls += 2;
+            }
+        }
+
+        if let Some(offset) = layout.index {
+            gl::EnableVertexAttribArray(self.index_attr as GLuint);
             gl_ok!();
 
-            gl::BindTexture(gl::TEXTURE_2D, 0);
+            gl::VertexAttribIPointer(self.index_attr as GLuint, 1, gl::UNSIGNED_SHORT, stride, offset as *const _);
             gl_ok!();
+            calls += 2;
         }
+        calls
     }
 
     pub fn new() -> SpriteProgram {
+        SpriteProgram::with_sources(VS_SRC, FS_SRC)
+    }
+
+    ///A program that draws sprites out of a texture array.
+    pub fn new_array() -> SpriteProgram {
+        SpriteProgram::with_sources(ARRAY_VS_SRC, ARRAY_FS_SRC)
+    }
+
+    fn with_sources(vs_src: &str, fs_src: &str) -> SpriteProgram {
         unsafe {
             // Create GLSL shaders
-            let vs = compile_shader(VS_SRC, gl::VERTEX_SHADER);
+            let vs = compile_shader(vs_src, gl::VERTEX_SHADER);
             gl_ok!();
 
-            let fs = compile_shader(FS_SRC, gl::FRAGMENT_SHADER);
+            let fs = compile_shader(fs_src, gl::FRAGMENT_SHADER);
             gl_ok!();
 
             let program = link_program(vs, fs);
@@ -313,6 +729,21 @@ impl SpriteProgram {
                 gl::GetUniformLocation(program, temp.as_ptr());
             gl_ok!();
 
+            let temp=CString::new("tile_uv").unwrap();
+            let tile_uv_uniform: GLint =
+                gl::GetUniformLocation(program, temp.as_ptr());
+            gl_ok!();
+
+            let temp=CString::new("stride_uv").unwrap();
+            let stride_uv_uniform: GLint =
+                gl::GetUniformLocation(program, temp.as_ptr());
+            gl_ok!();
+
+            let temp=CString::new("margin_uv").unwrap();
+            let margin_uv_uniform: GLint =
+                gl::GetUniformLocation(program, temp.as_ptr());
+            gl_ok!();
+
             let temp=CString::new("sprite_dim").unwrap();
             let sprite_dim_uniform: GLint =
                 gl::GetUniformLocation(program, temp.as_ptr());
@@ -333,6 +764,13 @@ impl SpriteProgram {
                 gl::GetUniformLocation(program, temp.as_ptr());
             gl_ok!();
 
+            let temp=CString::new("transform").unwrap();
+            let transform_uniform: GLint =
+                gl::GetUniformLocation(program, temp.as_ptr());
+            gl_ok!();
+
+            let instance_attrs = instance::InstanceAttrs::new(program);
+
             let temp=CString::new("bcol").unwrap();
             let bcol_uniform: GLint =
                 gl::GetUniformLocation(program, temp.as_ptr());
@@ -358,13 +796,47 @@ impl SpriteProgram {
                 gl::GetAttribLocation(program, temp.as_ptr());
             gl_ok!();
 
+            let temp=CString::new("color").unwrap();
+            let color_attr =
+                gl::GetAttribLocation(program, temp.as_ptr());
+            gl_ok!();
+
+            let temp=CString::new("flip").unwrap();
+            let flip_attr =
+                gl::GetAttribLocation(program, temp.as_ptr());
+            gl_ok!();
+
+            //Only the array program has this attribute, otherwise it is -1.
+            let temp=CString::new("layer").unwrap();
+            let layer_attr =
+                gl::GetAttribLocation(program, temp.as_ptr());
+            gl_ok!();
+
             let temp=CString::new("tex0").unwrap();
             let sample_location =
                 gl::GetAttribLocation(program, temp.as_ptr());
             gl_ok!();
 
+            let temp=CString::new("rotation_scale").unwrap();
+            let rotation_scale_uniform =
+                gl::GetUniformLocation(program, temp.as_ptr());
+            gl_ok!();
+
+            let temp=CString::new("position_origin").unwrap();
+            let position_origin_uniform =
+                gl::GetUniformLocation(program, temp.as_ptr());
+            gl_ok!();
+
+            let temp=CString::new("position_step").unwrap();
+            let position_step_uniform =
+                gl::GetUniformLocation(program, temp.as_ptr());
+            gl_ok!();
+
             SpriteProgram {
                 sample_location,
+                rotation_scale_uniform,
+                position_origin_uniform,
+                position_step_uniform,
                 program,
                 square_uniform,
                 rotation_attr,
@@ -372,10 +844,18 @@ impl SpriteProgram {
                 point_size_uniform,
                 grid_dim_uniform,
                 sprite_dim_uniform,
+                tile_uv_uniform,
+                stride_uv_uniform,
+                margin_uv_uniform,
                 matrix_uniform,
+                transform_uniform,
+                instance_attrs,
                 bcol_uniform,
                 pos_attr,
                 index_attr,
+                color_attr,
+                flip_attr,
+                layer_attr,
             }
         }
     }
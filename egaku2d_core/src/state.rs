@@ -0,0 +1,243 @@
+//! Contains the opengl state tracker.
+//!
+//! The programs switch programs, bind textures, set uniforms and bind vertex array objects through the tracker.
+//! It remembers what it last set, and skips calls that would not change anything.
+//! Every combination of program, buffer and vertex layout gets its own vertex array object
+//! the first time it is drawn, so the attributes only have to be set up once.
+
+use super::*;
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+#[derive(Default)]
+struct Forgotten {
+    textures: bool,
+    buffers: Vec<GLuint>,
+}
+
+thread_local! {
+    //Filled by code that changes opengl state without going through the tracker,
+    //like texture uploads and deleting buffers, which do not have access to the canvas.
+    static FORGOTTEN: RefCell<Forgotten> = RefCell::new(Forgotten::default());
+}
+
+///Tell the tracker that textures were bound or deleted without it.
+pub(crate) fn forget_textures() {
+    FORGOTTEN.with(|f| f.borrow_mut().textures = true);
+}
+
+///Tell the tracker that a buffer was deleted, so the vertex array objects that use it get deleted too.
+pub(crate) fn forget_buffer(id: GLuint) {
+    FORGOTTEN.with(|f| f.borrow_mut().buffers.push(id));
+}
+
+///Counts the opengl calls that went through the state tracker.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct GlStats {
+    pub calls_made: usize,
+    ///Calls that were skipped because they would not have changed anything.
+    pub calls_saved: usize,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub(crate) enum UniformValue {
+    Float(f32),
+    Int(i32),
+    Vec2([f32; 2]),
+    IVec2([i32; 2]),
+    Vec4([f32; 4]),
+    Mat3([[f32; 3]; 3]),
+}
+
+///Identifies a vertex array object.
+///The variant is up to the program, for things like optional attributes that change the layout.
+///Batches of user structs are told apart by their whole layout, since any two can share a buffer and stride.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub(crate) struct VaoKey {
+    pub program: GLuint,
+    pub buffer: GLuint,
+    pub stride: i32,
+    pub variant: u32,
+    pub batch: Option<batch::Layout>,
+}
+
+#[derive(Debug)]
+struct Vao {
+    id: GLuint,
+    setup_calls: usize,
+}
+
+#[derive(Debug)]
+pub(crate) struct GlState {
+    program: Option<GLuint>,
+    active_texture: Option<GLenum>,
+    textures: HashMap<GLenum, GLuint>,
+    uniforms: HashMap<(GLuint, GLint), UniformValue>,
+    vaos: HashMap<VaoKey, Vao>,
+    bound_vao: Option<GLuint>,
+    blend: Option<(GLenum, GLenum)>,
+    frame: GlStats,
+    last_frame: GlStats,
+}
+
+impl GlState {
+    pub(crate) fn new() -> GlState {
+        GlState {
+            program: None,
+            active_texture: None,
+            textures: HashMap::new(),
+            uniforms: HashMap::new(),
+            vaos: HashMap::new(),
+            bound_vao: None,
+            blend: None,
+            frame: GlStats::default(),
+            last_frame: GlStats::default(),
+        }
+    }
+
+    fn skip(&mut self, changed: bool, calls: usize) -> bool {
+        if changed {
+            self.frame.calls_made += calls;
+        } else {
+            self.frame.calls_saved += calls;
+        }
+        !changed
+    }
+
+    //Catch up on what changed behind the back of the tracker.
+    unsafe fn sync(&mut self) {
+        let (textures, buffers) = FORGOTTEN.with(|f| {
+            let mut f = f.borrow_mut();
+            (core::mem::take(&mut f.textures), core::mem::take(&mut f.buffers))
+        });
+        if textures {
+            self.textures.clear();
+        }
+        if !buffers.is_empty() {
+            let mut deleted = Vec::new();
+            self.vaos.retain(|key, vao| {
+                let keep = !buffers.contains(&key.buffer);
+                if !keep {
+                    deleted.push(vao.id);
+                }
+                keep
+            });
+            for id in deleted {
+                gl::DeleteVertexArrays(1, &id);
+                gl_ok!();
+                if self.bound_vao == Some(id) {
+                    self.bound_vao = None;
+                }
+            }
+        }
+    }
+
+    ///Switch programs. Also catches up on state changed outside the tracker, so call it first.
+    pub(crate) unsafe fn use_program(&mut self, program: GLuint) {
+        self.sync();
+        if self.skip(self.program != Some(program), 1) {
+            return;
+        }
+        gl::UseProgram(program);
+        gl_ok!();
+        self.program = Some(program);
+    }
+
+    ///For when a program was used without the tracker.
+    pub(crate) fn forget_program(&mut self) {
+        self.program = None;
+    }
+
+    ///Bind a texture to texture unit zero.
+    pub(crate) unsafe fn bind_texture(&mut self, target: GLenum, id: GLuint) {
+        if !self.skip(self.active_texture != Some(gl::TEXTURE0), 1) {
+            gl::ActiveTexture(gl::TEXTURE0);
+            gl_ok!();
+            self.active_texture = Some(gl::TEXTURE0);
+        }
+        if self.skip(self.textures.get(&target) != Some(&id), 1) {
+            return;
+        }
+        gl::BindTexture(target, id);
+        gl_ok!();
+        self.textures.insert(target, id);
+    }
+
+    ///Set a uniform of the program in use.
+    pub(crate) unsafe fn uniform(&mut self, location: GLint, value: UniformValue) {
+        let key = (self.program.unwrap_or(0), location);
+        if self.skip(self.uniforms.get(&key) != Some(&value), 1) {
+            return;
+        }
+        match value {
+            UniformValue::Float(a) => gl::Uniform1f(location, a),
+            UniformValue::Int(a) => gl::Uniform1i(location, a),
+            UniformValue::Vec2(a) => gl::Uniform2f(location, a[0], a[1]),
+            UniformValue::IVec2(a) => gl::Uniform2i(location, a[0], a[1]),
+            UniformValue::Vec4(a) => gl::Uniform4fv(location, 1, a.as_ptr()),
+            UniformValue::Mat3(a) => gl::UniformMatrix3fv(location, 1, 0, a[0].as_ptr()),
+        }
+        gl_ok!();
+        self.uniforms.insert(key, value);
+    }
+
+    ///Bind the vertex array object for the key. If it does not exist yet, it is made,
+    ///and set up by the function, which returns how many calls it made.
+    ///The setup calls are counted as saved every time the object is reused.
+    pub(crate) unsafe fn bind_vertex_array(&mut self, key: VaoKey, setup: impl FnOnce() -> usize) {
+        if let Some(vao) = self.vaos.get(&key) {
+            let (id, setup_calls) = (vao.id, vao.setup_calls);
+            self.frame.calls_saved += setup_calls;
+            if self.skip(self.bound_vao != Some(id), 1) {
+                return;
+            }
+            gl::BindVertexArray(id);
+            gl_ok!();
+            self.bound_vao = Some(id);
+            return;
+        }
+
+        let mut id = 0;
+        gl::GenVertexArrays(1, &mut id);
+        gl_ok!();
+        gl::BindVertexArray(id);
+        gl_ok!();
+        self.bound_vao = Some(id);
+
+        let setup_calls = setup();
+        self.frame.calls_made += 2 + setup_calls;
+        self.vaos.insert(key, Vao { id, setup_calls });
+    }
+
+    ///Turn on blending with the function.
+    pub(crate) unsafe fn set_blend(&mut self, src: GLenum, dst: GLenum) {
+        if self.blend.is_none() {
+            gl::Enable(gl::BLEND);
+            gl_ok!();
+        }
+        if self.skip(self.blend != Some((src, dst)), 1) {
+            return;
+        }
+        gl::BlendFunc(src, dst);
+        gl_ok!();
+        self.blend = Some((src, dst));
+    }
+
+    pub(crate) fn finish_frame(&mut self) {
+        self.last_frame = core::mem::take(&mut self.frame);
+    }
+
+    pub(crate) fn last_frame(&self) -> GlStats {
+        self.last_frame
+    }
+}
+
+impl Drop for GlState {
+    fn drop(&mut self) {
+        for vao in self.vaos.values() {
+            unsafe {
+                gl::DeleteVertexArrays(1, &vao.id);
+            }
+        }
+    }
+}
@@ -0,0 +1,477 @@
+//! Contains the text layout, measurement and drawing code.
+//!
+//! Text is drawn with point sprites out of a tile set texture where each tile is one glyph
+//! (a bitmap font such as `ascii.png` in the demo). All glyph positions are relative to the
+//! top left corner of the text, and are in the same units as the size passed in, so that
+//! they line up with the sprites when the viewport maps one world unit to one pixel.
+//!
+//! Rich text is built out of [`Span`]s. Each span can have its own color and font.
+//! All the spans are still sent to the gpu as one buffer and drawn in one draw call,
+//! so all the fonts used must be glyph ranges of the same texture.
+
+use super::*;
+use crate::sprite::Texture;
+use sprite_program::ColoredVertex;
+
+///A monospaced bitmap font. Describes how characters map to tiles of a tile set texture.
+#[derive(Copy, Clone, Debug)]
+pub struct Font {
+    first_char: u32,
+    first_index: u16,
+    num_glyphs: u16,
+    cell: [f32; 2],
+    advance: f32,
+    line_spacing: f32,
+}
+
+impl Font {
+    ///Create a font where the first tile of the texture is the glyph for `first_char`,
+    ///and every tile after that is the glyph of the next character.
+    pub fn new(texture: &Texture, first_char: char) -> Font {
+        let grid = texture.grid_dim();
+        let [sx, sy] = texture.tile_dim();
+
+        //Same normalization the sprite shader uses.
+        //The larger dimension of a glyph is the size passed when drawing.
+        let cell = if sx > sy { [1.0, sy / sx] } else { [sx / sy, 1.0] };
+
+        Font {
+            first_char: first_char as u32,
+            first_index: 0,
+            num_glyphs: (grid[0] as u16) * (grid[1] as u16),
+            cell,
+            advance: 1.0,
+            line_spacing: 1.0,
+        }
+    }
+
+    ///Only use the tiles in the range `[first_index,first_index+num_glyphs)`.
+    ///This lets one texture hold multiple fonts, for example a regular and bold one,
+    ///that can then be mixed in one draw.
+    ///Panics if the range goes past the largest tile index.
+    pub fn with_glyph_range(mut self, first_index: u16, num_glyphs: u16) -> Font {
+        assert!(
+            first_index as u32 + num_glyphs as u32 <= u16::MAX as u32 + 1,
+            "the glyph range has to fit in u16 tile indices"
+        );
+        self.first_index = first_index;
+        self.num_glyphs = num_glyphs;
+        self
+    }
+
+    ///Set the horizontal distance between glyphs as a fraction of the glyph width.
+    pub fn with_advance(mut self, advance: f32) -> Font {
+        self.advance = advance;
+        self
+    }
+
+    ///Set the vertical distance between lines as a fraction of the glyph height.
+    pub fn with_line_spacing(mut self, line_spacing: f32) -> Font {
+        self.line_spacing = line_spacing;
+        self
+    }
+
+    ///Returns the tile index of the glyph for this character,
+    ///or None if the font does not have one.
+    pub fn glyph_index(&self, c: char) -> Option<u16> {
+        let c = (c as u32).checked_sub(self.first_char)?;
+        if c < self.num_glyphs as u32 {
+            Some(self.first_index + c as u16)
+        } else {
+            None
+        }
+    }
+
+    ///The dimensions of one drawn glyph at the specified size.
+    pub fn glyph_dim(&self, size: f32) -> [f32; 2] {
+        [self.cell[0] * size, self.cell[1] * size]
+    }
+
+    ///The horizontal distance between glyphs at the specified size.
+    pub fn advance(&self, size: f32) -> f32 {
+        self.cell[0] * size * self.advance
+    }
+
+    ///The vertical distance between lines at the specified size.
+    pub fn line_height(&self, size: f32) -> f32 {
+        self.cell[1] * size * self.line_spacing
+    }
+
+    ///Layout a string that is all in this font.
+    pub fn layout(&self, text: &str, size: f32) -> TextLayout {
+        TextLayout::new(&[Span::new(text, self)], size)
+    }
+}
+
+///A piece of text with its own font and color.
+#[derive(Copy, Clone, Debug)]
+pub struct Span<'a> {
+    pub text: &'a str,
+    pub font: &'a Font,
+    pub color: [f32; 4],
+}
+
+impl<'a> Span<'a> {
+    pub fn new(text: &'a str, font: &'a Font) -> Span<'a> {
+        Span {
+            text,
+            font,
+            color: [1.0; 4],
+        }
+    }
+
+    pub fn with_color(mut self, color: [f32; 4]) -> Span<'a> {
+        self.color = color;
+        self
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+struct Glyph {
+    x: f32,
+    width: f32,
+    advance: f32,
+    line: usize,
+    index: Option<u16>,
+    color: [u8; 4],
+}
+
+#[derive(Copy, Clone, Debug)]
+struct Line {
+    start: usize,
+    end: usize,
+    width: f32,
+}
+
+///The computed position of every character in a group of spans.
+///Character indicies count chars, not bytes, across all the spans.
+///All positions are relative to the top left corner of the text.
+#[derive(Clone, Debug)]
+pub struct TextLayout {
+    glyphs: Vec<Glyph>,
+    lines: Vec<Line>,
+    line_height: f32,
+}
+
+impl TextLayout {
+    ///Every line has the height of the tallest line height out of all the fonts used.
+    pub fn new(spans: &[Span], size: f32) -> TextLayout {
+        let line_height = spans
+            .iter()
+            .map(|s| s.font.line_height(size))
+            .fold(0.0, f32::max);
+
+        let mut glyphs = Vec::new();
+        let mut lines = Vec::new();
+        let mut line = Line {
+            start: 0,
+            end: 0,
+            width: 0.0,
+        };
+
+        for span in spans.iter() {
+            let color = to_color_u8(span.color);
+            for c in span.text.chars() {
+                if c == '\n' {
+                    glyphs.push(Glyph {
+                        x: line.width,
+                        width: 0.0,
+                        advance: 0.0,
+                        line: lines.len(),
+                        index: None,
+                        color,
+                    });
+                    line.end = glyphs.len() - 1;
+                    lines.push(line);
+                    line = Line {
+                        start: glyphs.len(),
+                        end: glyphs.len(),
+                        width: 0.0,
+                    };
+                } else {
+                    let advance = span.font.advance(size);
+                    let index = if c.is_whitespace() {
+                        None
+                    } else {
+                        span.font.glyph_index(c)
+                    };
+                    glyphs.push(Glyph {
+                        x: line.width,
+                        width: span.font.glyph_dim(size)[0],
+                        advance,
+                        line: lines.len(),
+                        index,
+                        color,
+                    });
+                    line.width += advance;
+                }
+            }
+        }
+        line.end = glyphs.len();
+        lines.push(line);
+
+        TextLayout {
+            glyphs,
+            lines,
+            line_height,
+        }
+    }
+
+    ///The number of chars in the text.
+    pub fn num_chars(&self) -> usize {
+        self.glyphs.len()
+    }
+
+    pub fn line_height(&self) -> f32 {
+        self.line_height
+    }
+
+    ///The width of every line.
+    pub fn line_widths(&self) -> Vec<f32> {
+        self.lines.iter().map(|l| l.width).collect()
+    }
+
+    ///The rectangle that contains all of the text.
+    pub fn bounding_box(&self) -> Rect<f32> {
+        let width = self.lines.iter().map(|l| l.width).fold(0.0, f32::max);
+        let height = self.lines.len() as f32 * self.line_height;
+        Rect::new(0.0, width, 0.0, height)
+    }
+
+    ///The top of the caret placed in front of the char at `char_index`.
+    ///Passing the number of chars gives the position after the last char.
+    ///The caret is `line_height()` tall.
+    pub fn caret_position(&self, char_index: usize) -> [f32; 2] {
+        let char_index = char_index.min(self.glyphs.len());
+        match self.glyphs.get(char_index) {
+            Some(g) => [g.x, g.line as f32 * self.line_height],
+            None => {
+                let last = self.lines.len() - 1;
+                [self.lines[last].width, last as f32 * self.line_height]
+            }
+        }
+    }
+
+    ///Returns the index of the caret position closest to the point.
+    ///The result is in the range `[0,num_chars()]`.
+    pub fn hit_test(&self, point: [f32; 2]) -> usize {
+        let line = if point[1] <= 0.0 || self.line_height <= 0.0 {
+            0
+        } else {
+            ((point[1] / self.line_height) as usize).min(self.lines.len() - 1)
+        };
+        let line = &self.lines[line];
+
+        for (i, g) in self.glyphs[line.start..line.end].iter().enumerate() {
+            if point[0] < g.x + g.advance * 0.5 {
+                return line.start + i;
+            }
+        }
+        line.end
+    }
+
+    fn verts(&self, origin: PointType) -> impl Iterator<Item = ColoredVertex> + '_ {
+        let line_height = self.line_height;
+        self.glyphs.iter().filter_map(move |g| {
+            g.index.map(|index| ColoredVertex {
+                pos: [
+                    origin[0] + g.x + g.width * 0.5,
+                    origin[1] + (g.line as f32 + 0.5) * line_height,
+                ],
+                index,
+                rotation: 0,
+                color: g.color,
+            })
+        })
+    }
+}
+
+fn to_color_u8(color: [f32; 4]) -> [u8; 4] {
+    let mut c = [0; 4];
+    for (a, b) in c.iter_mut().zip(color.iter()) {
+        *a = (b.clamp(0.0, 1.0) * 255.0).round() as u8;
+    }
+    c
+}
+
+pub struct TextSave {
+    _ns: NotSend,
+    size: f32,
+    buffer: vbo::StaticBuffer<ColoredVertex>,
+}
+impl TextSave {
+    pub fn uniforms<'a>(&'a self, sys: &'a mut SimpleCanvas, texture: &'a Texture) -> Uniforms<'a> {
+        let common = sys.common(sys.offset);
+        let un = SpriteProgramUniformValues {
+            radius: self.size * core::f32::consts::SQRT_2,
+            texture,
+            format: VertexFormat::Colored,
+        };
+        Uniforms {
+            sys,
+            common,
+            un: UniformVals::Sprite(un),
+            buffer: queue::Vertices::Uploaded(self.buffer.get_info()),
+        }
+    }
+}
+
+pub struct TextSession {
+    pub(crate) size: f32,
+    pub(crate) verts: Vec<ColoredVertex>,
+}
+
+impl TextSession {
+    pub fn new(size: f32) -> Self {
+        TextSession {
+            size,
+            verts: Vec::new(),
+        }
+    }
+
+    ///Add a string in one font. The origin is the top left corner of the text.
+    pub fn add(&mut self, origin: PointType, text: &str, font: &Font) -> &mut Self {
+        self.add_spans(origin, &[Span::new(text, font)])
+    }
+
+    ///Add a group of spans that are laid out one after the other.
+    pub fn add_spans(&mut self, origin: PointType, spans: &[Span]) -> &mut Self {
+        let layout = TextLayout::new(spans, self.size);
+        self.add_layout(origin, &layout)
+    }
+
+    ///Add text that was already laid out, for example after it was measured.
+    ///The layout should have been made with the same size as this session.
+    pub fn add_layout(&mut self, origin: PointType, layout: &TextLayout) -> &mut Self {
+        self.verts.extend(layout.verts(origin));
+        self
+    }
+
+    pub fn append(&mut self, other: &mut Self) {
+        self.verts.append(&mut other.verts);
+    }
+
+    pub fn save(&mut self, _sys: &mut SimpleCanvas) -> TextSave {
+        TextSave {
+            _ns: ns(),
+            size: self.size,
+            buffer: vbo::StaticBuffer::new(&self.verts),
+        }
+    }
+
+    pub fn send_and_uniforms<'a>(
+        &'a mut self,
+        sys: &'a mut SimpleCanvas,
+        texture: &'a Texture,
+    ) -> Uniforms<'a> {
+        sys.text_buffer.send_to_gpu(&self.verts);
+
+        let common = sys.common(sys.offset);
+        let un = SpriteProgramUniformValues {
+            radius: self.size * core::f32::consts::SQRT_2,
+            texture,
+            format: VertexFormat::Colored,
+        };
+
+        let buffer = sys.text_buffer.get_info(self.verts.len());
+        Uniforms {
+            sys,
+            common,
+            un: UniformVals::Sprite(un),
+            buffer: queue::Vertices::Uploaded(buffer),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //Square glyphs for the printable ascii chars, so a glyph is as wide as the size.
+    fn font() -> Font {
+        Font {
+            first_char: ' ' as u32,
+            first_index: 0,
+            num_glyphs: 95,
+            cell: [1.0, 1.0],
+            advance: 1.0,
+            line_spacing: 1.0,
+        }
+    }
+
+    #[test]
+    fn glyph_range() {
+        let f = font().with_glyph_range(100, 95);
+        assert_eq!(f.glyph_index(' '), Some(100));
+        assert_eq!(f.glyph_index('~'), Some(194));
+        assert_eq!(f.glyph_index('\u{7f}'), None);
+        assert_eq!(f.glyph_index('\n'), None);
+
+        let f = font().with_glyph_range(u16::MAX - 94, 95);
+        assert_eq!(f.glyph_index('~'), Some(u16::MAX));
+    }
+
+    #[test]
+    #[should_panic]
+    fn glyph_range_past_u16() {
+        font().with_glyph_range(u16::MAX - 93, 95);
+    }
+
+    #[test]
+    fn lines() {
+        let layout = font().layout("ab\ncde", 10.0);
+        assert_eq!(layout.num_chars(), 6);
+        assert_eq!(layout.line_widths(), vec![20.0, 30.0]);
+        assert_eq!(layout.bounding_box(), Rect::new(0.0, 30.0, 0.0, 20.0));
+
+        //An empty text still has one empty line.
+        let layout = font().layout("", 10.0);
+        assert_eq!(layout.line_widths(), vec![0.0]);
+        assert_eq!(layout.bounding_box(), Rect::new(0.0, 0.0, 0.0, 10.0));
+
+        let layout = font().layout("a\n", 10.0);
+        assert_eq!(layout.line_widths(), vec![10.0, 0.0]);
+    }
+
+    #[test]
+    fn spans() {
+        let wide = font().with_advance(2.0).with_line_spacing(1.5);
+        let f = font();
+        let layout = TextLayout::new(&[Span::new("ab", &f), Span::new("c d", &wide)], 10.0);
+        assert_eq!(layout.line_height(), 15.0);
+        assert_eq!(layout.line_widths(), vec![80.0]);
+        assert_eq!(layout.caret_position(3), [40.0, 0.0]);
+
+        //Whitespace takes up space but is not drawn.
+        assert_eq!(layout.verts([0.0; 2]).count(), 4);
+    }
+
+    #[test]
+    fn caret() {
+        let layout = font().layout("ab\ncde", 10.0);
+        assert_eq!(layout.caret_position(0), [0.0, 0.0]);
+        assert_eq!(layout.caret_position(2), [20.0, 0.0]);
+        assert_eq!(layout.caret_position(3), [0.0, 10.0]);
+        assert_eq!(layout.caret_position(6), [30.0, 10.0]);
+        assert_eq!(layout.caret_position(100), [30.0, 10.0]);
+    }
+
+    #[test]
+    fn hit_test() {
+        let layout = font().layout("ab\ncde", 10.0);
+        assert_eq!(layout.hit_test([-5.0, -5.0]), 0);
+        assert_eq!(layout.hit_test([4.0, 5.0]), 0);
+        assert_eq!(layout.hit_test([6.0, 5.0]), 1);
+        //Past the end of a line is in front of the newline.
+        assert_eq!(layout.hit_test([100.0, 5.0]), 2);
+        assert_eq!(layout.hit_test([0.0, 15.0]), 3);
+        assert_eq!(layout.hit_test([26.0, 15.0]), 6);
+        assert_eq!(layout.hit_test([100.0, 100.0]), 6);
+
+        //Every caret position hits itself.
+        for i in 0..=layout.num_chars() {
+            let [x, y] = layout.caret_position(i);
+            assert_eq!(layout.hit_test([x + 0.1, y + 0.1]), i);
+        }
+    }
+}
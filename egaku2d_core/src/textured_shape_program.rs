@@ -1,6 +1,7 @@
 use super::*;
 use crate::gl;
 use crate::shader::*;
+use crate::state::{GlState, UniformValue, VaoKey};
 use crate::vbo::BufferInfo;
 use std::ffi::CString;
 use std::str;
@@ -9,14 +10,34 @@ use std::str;
 pub static VS_SRC: &'static str = "
 #version 300 es
 in vec2 position;
+in vec2 extrude;
+in vec2 instance_offset;
+in vec4 instance_matrix;
+in vec4 instance_color;
+out vec4 vinstance_color;
 out float ps;
 
+uniform vec2 position_origin;
+uniform float position_step;
 uniform vec2 offset;
 uniform mat3 mmatrix;
 uniform float point_size;
+uniform float line_width;
+uniform mat3 transform;
 void main() {
     gl_PointSize = point_size;
-    vec3 pp=vec3(position+offset,1.0);
+    vinstance_color=instance_color;
+    vec2 p=position*position_step+position_origin;
+    vec3 t=transform*vec3(mat2(instance_matrix)*p+instance_offset,1.0);
+
+    //Keep the extrude perpendicular to the transformed line, and keep its length.
+    mat2 m=mat2(transform)*mat2(instance_matrix);
+    vec2 e=mat2(m[1][1],-m[1][0],-m[0][1],m[0][0])*extrude;
+    if(dot(e,e)>0.0){
+        e=normalize(e)*length(extrude);
+    }
+
+    vec3 pp=vec3(t.xy/t.z+e*line_width+offset,1.0);
     ps=gl_PointSize;
     gl_Position = vec4(mmatrix*pp.xyz, 1.0);
 }";
@@ -27,6 +48,7 @@ pub static CIRCLE_FS_SRC: &'static str = "
 precision mediump float;
 uniform vec4 bcol;
 out vec4 out_color;
+in vec4 vinstance_color;
 in float ps;
 uniform sampler2D tex0;
 uniform vec2 texture_dim;
@@ -44,7 +66,7 @@ void main() {
     pos.x=gl_FragCoord.x;
     pos.y=-gl_FragCoord.y;
     
-    out_color = texture(tex0,( ((pos-texture_offset)/texture_dim)/texture_scale))*bcol;
+    out_color = texture(tex0,( ((pos-texture_offset)/texture_dim)/texture_scale))*bcol*vinstance_color;
 }";
 
 pub static REGULAR_FS_SRC: &'static str = "
@@ -52,6 +74,7 @@ pub static REGULAR_FS_SRC: &'static str = "
 precision mediump float;
 uniform vec4 bcol;
 out vec4 out_color;
+in vec4 vinstance_color;
 
 uniform vec2 texture_dim;
 uniform float texture_scale;
@@ -62,7 +85,7 @@ void main() {
     vec2 pos;
     pos.x=gl_FragCoord.x;
     pos.y=-gl_FragCoord.y;
-    out_color = texture(tex0, ((pos-texture_offset)/texture_dim)/texture_scale)*bcol;
+    out_color = texture(tex0, ((pos-texture_offset)/texture_dim)/texture_scale)*bcol*vinstance_color;
 
 }";
 
@@ -74,6 +97,8 @@ pub struct Vertex(pub [f32; 2]);
 pub struct TexturedShapeProgram {
     pub program: GLuint,
     pub matrix_uniform: GLint,
+    pub transform_uniform: GLint,
+    pub(crate) instance_attrs: instance::InstanceAttrs,
     pub offset_uniform: GLint,
     pub texture_dim_uniform: GLint,
     pub texture_offset_uniform: GLint,
@@ -81,45 +106,26 @@ pub struct TexturedShapeProgram {
     pub point_size_uniform: GLint,
     pub bcol_uniform: GLint,
     pub pos_attr: GLint,
+    pub extrude_attr: GLint,
+    pub line_width_uniform: GLint,
     pub sample_location: GLint,
+    pub position_origin_uniform: GLint,
+    pub position_step_uniform: GLint,
 }
 
-#[derive(Debug)]
-pub struct PointMul(pub f32);
-
 impl TexturedShapeProgram {
-    pub fn set_viewport(
-        &mut self,
-        window_dim: FixedAspectVec2,
-        game_width: f32,
-    ) -> PointMul {
-        let game_height = window_dim.ratio.height_over_width() as f32 * game_width;
-
-        let scalex = 2.0 / game_width;
-        let scaley = 2.0 / game_height;
-
-        let tx = -1.0;
-        let ty = 1.0;
-
-        let matrix = [[scalex, 0.0, 0.0], [0.0, -scaley, 0.0], [tx, ty, 1.0]];
-
+    pub fn set_matrix(&mut self, matrix: &[[f32; 3]; 3]) {
         unsafe {
             gl::UseProgram(self.program);
             gl_ok!();
-            gl::UniformMatrix3fv(
-                self.matrix_uniform,
-                1,
-                0,
-                std::mem::transmute(&matrix[0][0]),
-            );
+            gl::UniformMatrix3fv(self.matrix_uniform, 1, 0, matrix[0].as_ptr());
             gl_ok!();
         }
-
-        PointMul(window_dim.width as f32 / game_width)
     }
 
     pub(crate) fn set_buffer_and_draw(
         &mut self,
+        state: &mut GlState,
         common: &UniformCommon,
         un: &ProgramUniformValues,
         buffer_info: BufferInfo,
@@ -129,74 +135,68 @@ impl TexturedShapeProgram {
         let col = common.color;
         let buffer_id = buffer_info.id;
         let offset = common.offset;
-        let length = buffer_info.length;
 
         unsafe {
-            gl::UseProgram(self.program);
-            gl_ok!();
+            state.use_program(self.program);
 
-            gl::Uniform2f(self.offset_uniform, offset.x, offset.y);
-            gl_ok!();
-
-            gl::Uniform1f(self.point_size_uniform, point_size);
-            gl_ok!();
+            state.uniform(self.offset_uniform, UniformValue::Vec2([offset.x, offset.y]));
+            state.uniform(self.transform_uniform, UniformValue::Mat3(common.transform.matrix()));
+            state.uniform(self.point_size_uniform, UniformValue::Float(point_size));
+            state.uniform(self.bcol_uniform, UniformValue::Vec4(col));
 
-            gl::Uniform4fv(self.bcol_uniform, 1, col.as_ptr() as *const _);
-            gl_ok!();
-
-            gl::BindBuffer(gl::ARRAY_BUFFER, buffer_id);
-            gl_ok!();
+            let (origin, step) = un.precision.origin_and_step();
+            state.uniform(self.position_origin_uniform, UniformValue::Vec2(origin));
+            state.uniform(self.position_step_uniform, UniformValue::Float(step));
 
             match un.texture {
                 Some((t, scale, offset)) => {
-                    let texture_id = t.id;
-
-                    gl::ActiveTexture(gl::TEXTURE0);
-                    gl_ok!();
-
-                    gl::BindTexture(gl::TEXTURE_2D, texture_id);
-                    gl_ok!();
-
-                    gl::Uniform1i(self.sample_location, 0);
-                    gl_ok!();
+                    state.bind_texture(gl::TEXTURE_2D, t.id);
+                    state.uniform(self.sample_location, UniformValue::Int(0));
 
                     //dbg!(t.dim);
-                    gl::Uniform2f(self.texture_dim_uniform, t.dim[0] as f32, t.dim[1] as f32);
-                    gl_ok!();
-
-                    gl::Uniform2f(self.texture_offset_uniform, offset[0], offset[1]);
-                    gl_ok!();
-
-                    gl::Uniform1f(self.texture_scale_uniform, scale);
-                    gl_ok!();
+                    state.uniform(self.texture_dim_uniform, UniformValue::Vec2(t.dim()));
+                    state.uniform(self.texture_offset_uniform, UniformValue::Vec2(offset));
+                    state.uniform(self.texture_scale_uniform, UniformValue::Float(scale));
                 }
                 None => {
                     unreachable!();
                 }
             }
 
-            gl::EnableVertexAttribArray(self.pos_attr as GLuint);
-            gl_ok!();
+            let key = VaoKey {
+                program: self.program,
+                buffer: buffer_id,
+                stride: un.stride,
+                variant: un.vao_variant(),
+                batch: None,
+            };
+            state.bind_vertex_array(key, || {
+                gl::BindBuffer(gl::ARRAY_BUFFER, buffer_id);
+                gl_ok!();
 
-            gl::VertexAttribPointer(
-                self.pos_attr as GLuint,
-                2,
-                gl::FLOAT,
-                gl::FALSE as GLboolean,
-                0 as i32,
-                core::ptr::null(),
-            );
-            gl_ok!();
+                gl::EnableVertexAttribArray(self.pos_attr as GLuint);
+                gl_ok!();
 
-            gl::DrawArrays(mode, 0 as i32, length as i32);
+                gl::VertexAttribPointer(
+                    self.pos_attr as GLuint,
+                    2,
+                    un.precision.gl_type(),
+                    gl::FALSE as GLboolean,
+                    un.stride,
+                    un.position_offset() as *const _,
+                );
+                gl_ok!();
 
-            gl_ok!();
+                3 + circle_program::enable_extrude(self.extrude_attr, un)
+            });
 
-            gl::DisableVertexAttribArray(self.pos_attr as GLuint);
-            gl_ok!();
+            circle_program::set_extrude(state, self.extrude_attr, self.line_width_uniform, un);
 
-            gl::BindBuffer(gl::ARRAY_BUFFER, 0);
-            gl_ok!();
+            self.instance_attrs.enable(common.instances);
+
+            self.instance_attrs.draw(mode, buffer_info, common.instances);
+
+            self.instance_attrs.disable(common.instances);
         }
     }
 
@@ -246,6 +246,13 @@ impl TexturedShapeProgram {
                 gl::GetUniformLocation(program, temp.as_ptr());
             gl_ok!();
 
+            let temp=CString::new("transform").unwrap();
+            let transform_uniform: GLint =
+                gl::GetUniformLocation(program, temp.as_ptr());
+            gl_ok!();
+
+            let instance_attrs = instance::InstanceAttrs::new(program);
+
             let temp=CString::new("bcol").unwrap();
             let bcol_uniform: GLint =
                 gl::GetUniformLocation(program, temp.as_ptr());
@@ -261,21 +268,47 @@ impl TexturedShapeProgram {
                 gl::GetAttribLocation(program, temp.as_ptr());
             gl_ok!();
 
+            let temp=CString::new("extrude").unwrap();
+            let extrude_attr =
+                gl::GetAttribLocation(program, temp.as_ptr());
+            gl_ok!();
+
+            let temp=CString::new("line_width").unwrap();
+            let line_width_uniform: GLint =
+                gl::GetUniformLocation(program, temp.as_ptr());
+            gl_ok!();
+
             let temp=CString::new("tex0").unwrap();
             let sample_location =
                 gl::GetAttribLocation(program, temp.as_ptr());
             gl_ok!();
 
+            let temp=CString::new("position_origin").unwrap();
+            let position_origin_uniform =
+                gl::GetUniformLocation(program, temp.as_ptr());
+            gl_ok!();
+
+            let temp=CString::new("position_step").unwrap();
+            let position_step_uniform =
+                gl::GetUniformLocation(program, temp.as_ptr());
+            gl_ok!();
+
             TexturedShapeProgram {
                 program,
+                position_origin_uniform,
+                position_step_uniform,
                 offset_uniform,
                 texture_dim_uniform,
                 texture_offset_uniform,
                 texture_scale_uniform,
                 point_size_uniform,
                 matrix_uniform,
+                transform_uniform,
+                instance_attrs,
                 bcol_uniform,
                 pos_attr,
+                extrude_attr,
+                line_width_uniform,
                 sample_location,
             }
         }
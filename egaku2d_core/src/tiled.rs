@@ -0,0 +1,1145 @@
+//! Contains the importer for maps made with the [Tiled](https://www.mapeditor.org) editor.
+//!
+//! Both the TMX (xml) and the JSON map formats are supported, along with external tilesets
+//! in TSX and JSON form. Only orthogonal, finite maps are supported.
+//!
+//! Tile layers can be turned into ready to draw `tilemap::Tilemap`s, one per tileset,
+//! that are drawn with the texture of that tileset. Object layers are kept as plain data,
+//! with helpers to add their shapes to the existing shape sessions.
+//!
+//! Positions and sizes are in the pixel units of the map, so one world unit is one pixel of a tile.
+
+use super::*;
+use crate::sprite::{SheetLayout, Texture};
+use crate::tilemap::{TileFlip, Tilemap};
+use core::convert::TryFrom;
+use std::path::Path;
+
+const FLIPPED_HORIZONTALLY: u32 = 0x8000_0000;
+const FLIPPED_VERTICALLY: u32 = 0x4000_0000;
+const FLIPPED_DIAGONALLY: u32 = 0x2000_0000;
+const ROTATED_HEXAGONAL: u32 = 0x1000_0000;
+
+///Split a global tile id as stored in a layer into the id and its flip flags.
+pub fn decode_gid(raw: u32) -> (u32, TileFlip) {
+    let flip = TileFlip {
+        horizontal: raw & FLIPPED_HORIZONTALLY != 0,
+        vertical: raw & FLIPPED_VERTICALLY != 0,
+        diagonal: raw & FLIPPED_DIAGONALLY != 0,
+    };
+    let gid = raw
+        & !(FLIPPED_HORIZONTALLY | FLIPPED_VERTICALLY | FLIPPED_DIAGONALLY | ROTATED_HEXAGONAL);
+    (gid, flip)
+}
+
+#[derive(Debug)]
+pub enum TiledError {
+    Io(std::io::Error),
+    Xml(roxmltree::Error),
+    Json(serde_json::Error),
+    ///The map is valid, but uses something this importer does not support,
+    ///or is missing something it needs.
+    Format(String),
+}
+
+impl core::fmt::Display for TiledError {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self {
+            TiledError::Io(e) => write!(f, "io error: {}", e),
+            TiledError::Xml(e) => write!(f, "xml error: {}", e),
+            TiledError::Json(e) => write!(f, "json error: {}", e),
+            TiledError::Format(e) => write!(f, "tiled format error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for TiledError {}
+
+impl From<std::io::Error> for TiledError {
+    fn from(e: std::io::Error) -> Self {
+        TiledError::Io(e)
+    }
+}
+impl From<roxmltree::Error> for TiledError {
+    fn from(e: roxmltree::Error) -> Self {
+        TiledError::Xml(e)
+    }
+}
+impl From<serde_json::Error> for TiledError {
+    fn from(e: serde_json::Error) -> Self {
+        TiledError::Json(e)
+    }
+}
+
+fn format_err<T>(msg: impl Into<String>) -> Result<T, TiledError> {
+    Err(TiledError::Format(msg.into()))
+}
+
+#[derive(Clone, Debug)]
+pub struct Tileset {
+    pub first_gid: u32,
+    pub name: String,
+    ///The path of the external tileset file, if the tileset is not embedded in the map.
+    ///It is None once the tileset has been loaded by `load()`.
+    pub source: Option<String>,
+    ///The path of the tileset image.
+    pub image: Option<String>,
+    pub image_dim: [u32; 2],
+    pub tile_dim: [u32; 2],
+    pub margin: u32,
+    pub spacing: u32,
+    pub tile_count: u32,
+    pub columns: u32,
+}
+
+impl Tileset {
+    ///The grid dimensions to pass when making the texture of this tileset.
+    ///Fails if the tileset has more than 255 columns or rows.
+    pub fn grid_dim(&self) -> Result<[u8; 2], TiledError> {
+        let columns = self.columns.max(1);
+        let rows = self.tile_count.div_ceil(columns).max(1);
+        match (u8::try_from(columns), u8::try_from(rows)) {
+            (Ok(columns), Ok(rows)) => Ok([columns, rows]),
+            _ => format_err(format!(
+                "tileset {} has {} columns and {} rows, but at most 255 of each are supported",
+                self.name, columns, rows
+            )),
+        }
+    }
+
+    ///The layout to pass to `Texture::from_sheet_file()` when making the texture of this tileset.
+    ///Unlike the grid dimensions, this respects the margin and spacing of the tileset.
+    pub fn sheet_layout(&self) -> SheetLayout {
+        SheetLayout::new(self.tile_dim)
+            .with_margin(self.margin)
+            .with_spacing(self.spacing)
+    }
+
+    ///Returns true if the tile with this global id belongs to this tileset.
+    pub fn contains(&self, gid: u32) -> bool {
+        gid >= self.first_gid && gid - self.first_gid < self.tile_count
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct TileLayer {
+    pub name: String,
+    pub dim: [usize; 2],
+    pub visible: bool,
+    pub opacity: f32,
+    ///The raw global tile ids, including the flip flags, in row major order.
+    ///Zero means there is no tile.
+    pub tiles: Vec<u32>,
+}
+
+impl TileLayer {
+    ///The global tile id and flip flags of a cell, or None if it is empty.
+    pub fn tile(&self, cell: [usize; 2]) -> Option<(u32, TileFlip)> {
+        let (gid, flip) = decode_gid(self.tiles[cell[1] * self.dim[0] + cell[0]]);
+        if gid == 0 {
+            None
+        } else {
+            Some((gid, flip))
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum ObjectShape {
+    Rect { width: f32, height: f32 },
+    Ellipse { width: f32, height: f32 },
+    Point,
+    ///Points are relative to the position of the object.
+    Polygon(Vec<[f32; 2]>),
+    ///Points are relative to the position of the object.
+    Polyline(Vec<[f32; 2]>),
+}
+
+#[derive(Clone, Debug)]
+pub struct Object {
+    pub id: u32,
+    pub name: String,
+    ///The type (or class in newer versions of Tiled) of the object.
+    pub kind: String,
+    pub pos: [f32; 2],
+    ///Rotation in degrees clockwise around `pos`.
+    pub rotation: f32,
+    pub visible: bool,
+    ///The raw global tile id for tile objects.
+    pub gid: Option<u32>,
+    pub shape: ObjectShape,
+}
+
+impl Object {
+    ///The outline of the object in world space, with rotation applied.
+    ///Closed shapes repeat their first point at the end.
+    ///Points have an empty outline.
+    pub fn outline(&self) -> Vec<[f32; 2]> {
+        let local: Vec<[f32; 2]> = match &self.shape {
+            ObjectShape::Rect { width, height } => {
+                //Tile objects are positioned by their bottom left corner.
+                let y = if self.gid.is_some() { -height } else { 0.0 };
+                vec![
+                    [0.0, y],
+                    [*width, y],
+                    [*width, y + height],
+                    [0.0, y + height],
+                    [0.0, y],
+                ]
+            }
+            ObjectShape::Ellipse { width, height } => {
+                let num = 24;
+                (0..=num)
+                    .map(|i| {
+                        let a = (i as f32 / num as f32) * core::f32::consts::PI * 2.0;
+                        [
+                            width * 0.5 * (1.0 + a.cos()),
+                            height * 0.5 * (1.0 + a.sin()),
+                        ]
+                    })
+                    .collect()
+            }
+            ObjectShape::Point => Vec::new(),
+            ObjectShape::Polygon(points) => {
+                let mut p = points.clone();
+                if let Some(&first) = points.first() {
+                    p.push(first);
+                }
+                p
+            }
+            ObjectShape::Polyline(points) => points.clone(),
+        };
+
+        let (s, c) = self.rotation.to_radians().sin_cos();
+        local
+            .into_iter()
+            .map(|[x, y]| {
+                [
+                    self.pos[0] + x * c - y * s,
+                    self.pos[1] + x * s + y * c,
+                ]
+            })
+            .collect()
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ObjectLayer {
+    pub name: String,
+    pub visible: bool,
+    pub objects: Vec<Object>,
+}
+
+impl ObjectLayer {
+    ///Add the outline of every visible object with an outline.
+    pub fn add_outlines(&self, lines: &mut shapes::LineSession) {
+        for o in self.objects.iter().filter(|o| o.visible) {
+            for w in o.outline().windows(2) {
+                lines.add(w[0], w[1]);
+            }
+        }
+    }
+
+    ///Add every visible rectangle object that is not rotated.
+    pub fn add_rects(&self, rects: &mut shapes::RectSession) {
+        for o in self.objects.iter().filter(|o| o.visible && o.rotation == 0.0) {
+            if let ObjectShape::Rect { width, height } = o.shape {
+                let y = if o.gid.is_some() { o.pos[1] - height } else { o.pos[1] };
+                rects.add([o.pos[0], o.pos[0] + width, y, y + height]);
+            }
+        }
+    }
+
+    ///Add every visible point object.
+    pub fn add_points(&self, circles: &mut shapes::CircleSession) {
+        for o in self.objects.iter().filter(|o| o.visible) {
+            if let ObjectShape::Point = o.shape {
+                circles.add(o.pos);
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum Layer {
+    Tiles(TileLayer),
+    Objects(ObjectLayer),
+}
+
+///A parsed Tiled map. Layers inside of groups are flattened out in draw order.
+#[derive(Clone, Debug)]
+pub struct Map {
+    pub dim: [usize; 2],
+    pub tile_dim: [u32; 2],
+    pub tilesets: Vec<Tileset>,
+    pub layers: Vec<Layer>,
+}
+
+///Load a map from a file, along with any external tilesets it references.
+///Files ending in `.tmj` or `.json` are read as JSON, everything else as TMX.
+///Tileset image paths are made relative to the current directory instead of the map file.
+pub fn load(path: impl AsRef<Path>) -> Result<Map, TiledError> {
+    let path = path.as_ref();
+    let src = std::fs::read_to_string(path)?;
+    let mut map = if is_json(path) {
+        Map::from_json(&src)?
+    } else {
+        Map::from_tmx(&src)?
+    };
+
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    for ts in map.tilesets.iter_mut() {
+        if let Some(source) = ts.source.take() {
+            let ts_path = dir.join(&source);
+            let src = std::fs::read_to_string(&ts_path)?;
+            let mut external = if is_json(&ts_path) {
+                let v: serde_json::Value = serde_json::from_str(&src)?;
+                json::tileset(&v, ts.first_gid)?
+            } else {
+                let doc = roxmltree::Document::parse(&src)?;
+                tmx::tileset(doc.root_element(), ts.first_gid)?
+            };
+            let ts_dir = ts_path.parent().unwrap_or_else(|| Path::new(""));
+            external.image = external
+                .image
+                .map(|i| ts_dir.join(i).to_string_lossy().into_owned());
+            *ts = external;
+        } else {
+            ts.image = ts.image.take().map(|i| dir.join(i).to_string_lossy().into_owned());
+        }
+    }
+    Ok(map)
+}
+
+fn is_json(path: &Path) -> bool {
+    matches!(
+        path.extension().and_then(|e| e.to_str()),
+        Some("tmj") | Some("tsj") | Some("json")
+    )
+}
+
+impl Map {
+    ///Parse a map in the TMX format. External tilesets are not loaded.
+    pub fn from_tmx(src: &str) -> Result<Map, TiledError> {
+        let doc = roxmltree::Document::parse(src)?;
+        tmx::map(doc.root_element())
+    }
+
+    ///Parse a map in the JSON format. External tilesets are not loaded.
+    pub fn from_json(src: &str) -> Result<Map, TiledError> {
+        let v: serde_json::Value = serde_json::from_str(src)?;
+        json::map(&v)
+    }
+
+    ///Returns the index of the tileset the global id belongs to,
+    ///and the index of the tile inside of that tileset.
+    ///Fails if no tileset has the tile, or if its index does not fit in a tile index.
+    pub fn tileset_of(&self, gid: u32) -> Result<(usize, u16), TiledError> {
+        let (gid, _) = decode_gid(gid);
+        let i = match self.tilesets.iter().position(|t| t.contains(gid)) {
+            Some(i) => i,
+            None => return format_err(format!("tile id {} is not in any tileset", gid)),
+        };
+        match u16::try_from(gid - self.tilesets[i].first_gid) {
+            Ok(index) => Ok((i, index)),
+            Err(_) => format_err(format!("tile id {} is past the largest tile index", gid)),
+        }
+    }
+
+    pub fn tile_layers(&self) -> impl Iterator<Item = &TileLayer> {
+        self.layers.iter().filter_map(|l| match l {
+            Layer::Tiles(t) => Some(t),
+            Layer::Objects(_) => None,
+        })
+    }
+
+    pub fn object_layers(&self) -> impl Iterator<Item = &ObjectLayer> {
+        self.layers.iter().filter_map(|l| match l {
+            Layer::Objects(o) => Some(o),
+            Layer::Tiles(_) => None,
+        })
+    }
+
+    ///Build ready to draw tilemaps out of all the tile layers.
+    ///Every tileset gets its own tilemap that has one layer per tile layer of the map.
+    pub fn build_tile_layers(&self, chunk_size: usize) -> Result<TileLayers, TiledError> {
+        if self.tile_dim[0] != self.tile_dim[1] {
+            return format_err("only square tiles are supported");
+        }
+        let tile_size = self.tile_dim[0] as f32;
+        let layers: Vec<&TileLayer> = self.tile_layers().collect();
+
+        let mut tilemaps: Vec<Tilemap> = self
+            .tilesets
+            .iter()
+            .map(|_| Tilemap::new(self.dim, layers.len(), tile_size, chunk_size))
+            .collect();
+
+        for (li, layer) in layers.iter().enumerate() {
+            let w = layer.dim[0].min(self.dim[0]);
+            let h = layer.dim[1].min(self.dim[1]);
+            for y in 0..h {
+                for x in 0..w {
+                    if let Some((gid, flip)) = layer.tile([x, y]) {
+                        let (ts, index) = self.tileset_of(gid)?;
+                        tilemaps[ts].set_tile_flipped(li, [x, y], index, flip);
+                    }
+                }
+            }
+        }
+
+        Ok(TileLayers {
+            visible: layers.iter().map(|l| l.visible).collect(),
+            opacity: layers.iter().map(|l| l.opacity).collect(),
+            tilemaps,
+        })
+    }
+}
+
+///The tile layers of a map, ready to draw.
+pub struct TileLayers {
+    visible: Vec<bool>,
+    opacity: Vec<f32>,
+    tilemaps: Vec<Tilemap>,
+}
+
+impl TileLayers {
+    ///One tilemap per tileset, in the same order as `Map::tilesets`.
+    pub fn tilemaps(&mut self) -> &mut [Tilemap] {
+        &mut self.tilemaps
+    }
+
+    pub fn num_layers(&self) -> usize {
+        self.visible.len()
+    }
+
+    ///Draw every visible layer in order.
+    ///The textures must be in the same order as `Map::tilesets`.
+    pub fn draw(&mut self, sys: &mut SimpleCanvas, textures: &[&Texture]) {
+        for layer in 0..self.visible.len() {
+            if self.visible[layer] {
+                self.draw_layer(layer, sys, textures);
+            }
+        }
+    }
+
+    ///Draw one layer, using every tileset. The opacity of the layer multiplies the alpha of the default color.
+    pub fn draw_layer(&mut self, layer: usize, sys: &mut SimpleCanvas, textures: &[&Texture]) {
+        assert_eq!(
+            textures.len(),
+            self.tilemaps.len(),
+            "there must be one texture per tileset"
+        );
+        for (tilemap, texture) in self.tilemaps.iter_mut().zip(textures.iter()) {
+            tilemap.draw_layer_faded(layer, sys, texture, self.opacity[layer]);
+        }
+    }
+}
+
+fn decode_base64(src: &str) -> Result<Vec<u8>, TiledError> {
+    fn val(c: u8) -> Option<u32> {
+        match c {
+            b'A'..=b'Z' => Some((c - b'A') as u32),
+            b'a'..=b'z' => Some((c - b'a') as u32 + 26),
+            b'0'..=b'9' => Some((c - b'0') as u32 + 52),
+            b'+' => Some(62),
+            b'/' => Some(63),
+            _ => None,
+        }
+    }
+
+    let mut out = Vec::new();
+    let mut acc = 0u32;
+    let mut bits = 0;
+    for &c in src.as_bytes() {
+        if c.is_ascii_whitespace() || c == b'=' {
+            continue;
+        }
+        let v = match val(c) {
+            Some(v) => v,
+            None => return format_err("invalid base64 data"),
+        };
+        acc = (acc << 6) | v;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((acc >> bits) as u8);
+            acc &= (1 << bits) - 1;
+        }
+    }
+    Ok(out)
+}
+
+fn decode_tile_data(
+    encoding: Option<&str>,
+    compression: Option<&str>,
+    text: &str,
+) -> Result<Vec<u32>, TiledError> {
+    match compression {
+        None | Some("") => {}
+        Some(c) => return format_err(format!("{} compressed layer data is not supported", c)),
+    }
+    match encoding {
+        Some("csv") => text
+            .split(',')
+            .map(|s| s.trim())
+            .filter(|s| !s.is_empty())
+            .map(|s| {
+                s.parse::<u32>()
+                    .or_else(|_| format_err(format!("invalid tile id {}", s)))
+            })
+            .collect(),
+        Some("base64") => {
+            let bytes = decode_base64(text)?;
+            if bytes.len() % 4 != 0 {
+                return format_err("base64 layer data is not a multiple of 4 bytes");
+            }
+            Ok(bytes
+                .chunks(4)
+                .map(|c| u32::from_le_bytes([c[0], c[1], c[2], c[3]]))
+                .collect())
+        }
+        Some(e) => format_err(format!("unknown layer encoding {}", e)),
+        None => format_err("layer data has no encoding"),
+    }
+}
+
+mod tmx {
+    use super::*;
+    use roxmltree::Node;
+
+    fn attr<T: core::str::FromStr>(node: Node, name: &str) -> Result<Option<T>, TiledError> {
+        match node.attribute(name) {
+            Some(a) => match a.parse() {
+                Ok(a) => Ok(Some(a)),
+                Err(_) => format_err(format!("invalid value {} for attribute {}", a, name)),
+            },
+            None => Ok(None),
+        }
+    }
+
+    fn req<T: core::str::FromStr>(node: Node, name: &str) -> Result<T, TiledError> {
+        match attr(node, name)? {
+            Some(a) => Ok(a),
+            None => format_err(format!("<{}> is missing attribute {}", node.tag_name().name(), name)),
+        }
+    }
+
+    fn visible(node: Node) -> Result<bool, TiledError> {
+        Ok(attr::<u32>(node, "visible")?.unwrap_or(1) != 0)
+    }
+
+    pub(super) fn map(node: Node) -> Result<Map, TiledError> {
+        if node.tag_name().name() != "map" {
+            return format_err("root element is not <map>");
+        }
+        if let Some(o) = node.attribute("orientation") {
+            if o != "orthogonal" {
+                return format_err(format!("{} maps are not supported", o));
+            }
+        }
+        if attr::<u32>(node, "infinite")?.unwrap_or(0) != 0 {
+            return format_err("infinite maps are not supported");
+        }
+
+        let mut tilesets = Vec::new();
+        for ts in node.children().filter(|n| n.has_tag_name("tileset")) {
+            let first_gid = req(ts, "firstgid")?;
+            match ts.attribute("source") {
+                Some(source) => tilesets.push(Tileset {
+                    first_gid,
+                    name: String::new(),
+                    source: Some(source.to_string()),
+                    image: None,
+                    image_dim: [0; 2],
+                    tile_dim: [0; 2],
+                    margin: 0,
+                    spacing: 0,
+                    tile_count: 0,
+                    columns: 0,
+                }),
+                None => tilesets.push(tileset(ts, first_gid)?),
+            }
+        }
+
+        let mut layers = Vec::new();
+        add_layers(node, &mut layers)?;
+
+        Ok(Map {
+            dim: [req(node, "width")?, req(node, "height")?],
+            tile_dim: [req(node, "tilewidth")?, req(node, "tileheight")?],
+            tilesets,
+            layers,
+        })
+    }
+
+    pub(super) fn tileset(node: Node, first_gid: u32) -> Result<Tileset, TiledError> {
+        let image = node.children().find(|n| n.has_tag_name("image"));
+        let tile_dim = [req(node, "tilewidth")?, req(node, "tileheight")?];
+        let tile_count = req(node, "tilecount")?;
+        Ok(Tileset {
+            first_gid,
+            name: node.attribute("name").unwrap_or("").to_string(),
+            source: None,
+            image: image.and_then(|i| i.attribute("source")).map(|s| s.to_string()),
+            image_dim: match image {
+                Some(i) => [
+                    attr(i, "width")?.unwrap_or(0),
+                    attr(i, "height")?.unwrap_or(0),
+                ],
+                None => [0; 2],
+            },
+            tile_dim,
+            margin: attr(node, "margin")?.unwrap_or(0),
+            spacing: attr(node, "spacing")?.unwrap_or(0),
+            tile_count,
+            columns: attr(node, "columns")?.unwrap_or(tile_count),
+        })
+    }
+
+    fn add_layers(node: Node, layers: &mut Vec<Layer>) -> Result<(), TiledError> {
+        for child in node.children() {
+            match child.tag_name().name() {
+                "layer" => layers.push(Layer::Tiles(tile_layer(child)?)),
+                "objectgroup" => layers.push(Layer::Objects(object_layer(child)?)),
+                "group" => add_layers(child, layers)?,
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn tile_layer(node: Node) -> Result<TileLayer, TiledError> {
+        let dim = [req(node, "width")?, req(node, "height")?];
+        let data = match node.children().find(|n| n.has_tag_name("data")) {
+            Some(d) => d,
+            None => return format_err("<layer> has no <data>"),
+        };
+        if data.children().any(|n| n.has_tag_name("chunk")) {
+            return format_err("infinite maps are not supported");
+        }
+
+        let tiles = match data.attribute("encoding") {
+            None => data
+                .children()
+                .filter(|n| n.has_tag_name("tile"))
+                .map(|t| Ok(attr(t, "gid")?.unwrap_or(0)))
+                .collect::<Result<Vec<u32>, TiledError>>()?,
+            encoding => decode_tile_data(
+                encoding,
+                data.attribute("compression"),
+                data.text().unwrap_or(""),
+            )?,
+        };
+
+        if tiles.len() != dim[0] * dim[1] {
+            return format_err("layer data does not match the layer dimensions");
+        }
+
+        Ok(TileLayer {
+            name: node.attribute("name").unwrap_or("").to_string(),
+            dim,
+            visible: visible(node)?,
+            opacity: attr(node, "opacity")?.unwrap_or(1.0),
+            tiles,
+        })
+    }
+
+    fn points(node: Node) -> Result<Vec<[f32; 2]>, TiledError> {
+        let mut out = Vec::new();
+        for p in node.attribute("points").unwrap_or("").split_whitespace() {
+            let mut it = p.split(',').map(|a| a.parse::<f32>());
+            match (it.next(), it.next()) {
+                (Some(Ok(x)), Some(Ok(y))) => out.push([x, y]),
+                _ => return format_err(format!("invalid point {}", p)),
+            }
+        }
+        Ok(out)
+    }
+
+    fn object_layer(node: Node) -> Result<ObjectLayer, TiledError> {
+        let mut objects = Vec::new();
+        for o in node.children().filter(|n| n.has_tag_name("object")) {
+            let width = attr(o, "width")?.unwrap_or(0.0);
+            let height = attr(o, "height")?.unwrap_or(0.0);
+
+            let mut shape = ObjectShape::Rect { width, height };
+            for c in o.children() {
+                match c.tag_name().name() {
+                    "ellipse" => shape = ObjectShape::Ellipse { width, height },
+                    "point" => shape = ObjectShape::Point,
+                    "polygon" => shape = ObjectShape::Polygon(points(c)?),
+                    "polyline" => shape = ObjectShape::Polyline(points(c)?),
+                    _ => {}
+                }
+            }
+
+            objects.push(Object {
+                id: attr(o, "id")?.unwrap_or(0),
+                name: o.attribute("name").unwrap_or("").to_string(),
+                kind: o
+                    .attribute("type")
+                    .or_else(|| o.attribute("class"))
+                    .unwrap_or("")
+                    .to_string(),
+                pos: [attr(o, "x")?.unwrap_or(0.0), attr(o, "y")?.unwrap_or(0.0)],
+                rotation: attr(o, "rotation")?.unwrap_or(0.0),
+                visible: visible(o)?,
+                gid: attr(o, "gid")?,
+                shape,
+            });
+        }
+
+        Ok(ObjectLayer {
+            name: node.attribute("name").unwrap_or("").to_string(),
+            visible: visible(node)?,
+            objects,
+        })
+    }
+}
+
+mod json {
+    use super::*;
+    use serde_json::Value;
+
+    fn get<'a>(v: &'a Value, name: &str) -> Result<&'a Value, TiledError> {
+        match v.get(name) {
+            Some(a) => Ok(a),
+            None => format_err(format!("missing field {}", name)),
+        }
+    }
+
+    fn uint(v: &Value, name: &str) -> Result<u32, TiledError> {
+        match get(v, name)?.as_u64().map(u32::try_from) {
+            Some(Ok(a)) => Ok(a),
+            Some(Err(_)) => format_err(format!("field {} is too large", name)),
+            None => format_err(format!("field {} is not an unsigned integer", name)),
+        }
+    }
+
+    fn uint_or(v: &Value, name: &str, default: u32) -> Result<u32, TiledError> {
+        match v.get(name) {
+            Some(_) => uint(v, name),
+            None => Ok(default),
+        }
+    }
+
+    fn float_or(v: &Value, name: &str, default: f32) -> f32 {
+        v.get(name).and_then(|a| a.as_f64()).map(|a| a as f32).unwrap_or(default)
+    }
+
+    fn string(v: &Value, name: &str) -> String {
+        v.get(name).and_then(|a| a.as_str()).unwrap_or("").to_string()
+    }
+
+    fn visible(v: &Value) -> bool {
+        v.get("visible").and_then(|a| a.as_bool()).unwrap_or(true)
+    }
+
+    pub(super) fn map(v: &Value) -> Result<Map, TiledError> {
+        if let Some(o) = v.get("orientation").and_then(|a| a.as_str()) {
+            if o != "orthogonal" {
+                return format_err(format!("{} maps are not supported", o));
+            }
+        }
+        if v.get("infinite").and_then(|a| a.as_bool()).unwrap_or(false) {
+            return format_err("infinite maps are not supported");
+        }
+
+        let mut tilesets = Vec::new();
+        for ts in get(v, "tilesets")?.as_array().into_iter().flatten() {
+            let first_gid = uint(ts, "firstgid")?;
+            match ts.get("source").and_then(|a| a.as_str()) {
+                Some(source) => tilesets.push(Tileset {
+                    first_gid,
+                    name: String::new(),
+                    source: Some(source.to_string()),
+                    image: None,
+                    image_dim: [0; 2],
+                    tile_dim: [0; 2],
+                    margin: 0,
+                    spacing: 0,
+                    tile_count: 0,
+                    columns: 0,
+                }),
+                None => tilesets.push(tileset(ts, first_gid)?),
+            }
+        }
+
+        let mut layers = Vec::new();
+        add_layers(get(v, "layers")?, &mut layers)?;
+
+        Ok(Map {
+            dim: [uint(v, "width")? as usize, uint(v, "height")? as usize],
+            tile_dim: [uint(v, "tilewidth")?, uint(v, "tileheight")?],
+            tilesets,
+            layers,
+        })
+    }
+
+    pub(super) fn tileset(v: &Value, first_gid: u32) -> Result<Tileset, TiledError> {
+        let tile_count = uint(v, "tilecount")?;
+        Ok(Tileset {
+            first_gid,
+            name: string(v, "name"),
+            source: None,
+            image: v.get("image").and_then(|a| a.as_str()).map(|a| a.to_string()),
+            image_dim: [uint_or(v, "imagewidth", 0)?, uint_or(v, "imageheight", 0)?],
+            tile_dim: [uint(v, "tilewidth")?, uint(v, "tileheight")?],
+            margin: uint_or(v, "margin", 0)?,
+            spacing: uint_or(v, "spacing", 0)?,
+            tile_count,
+            columns: uint_or(v, "columns", tile_count)?,
+        })
+    }
+
+    fn add_layers(v: &Value, layers: &mut Vec<Layer>) -> Result<(), TiledError> {
+        for l in v.as_array().into_iter().flatten() {
+            match l.get("type").and_then(|a| a.as_str()) {
+                Some("tilelayer") => layers.push(Layer::Tiles(tile_layer(l)?)),
+                Some("objectgroup") => layers.push(Layer::Objects(object_layer(l)?)),
+                Some("group") => add_layers(get(l, "layers")?, layers)?,
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn tile_layer(v: &Value) -> Result<TileLayer, TiledError> {
+        if v.get("chunks").is_some() {
+            return format_err("infinite maps are not supported");
+        }
+        let dim = [uint(v, "width")? as usize, uint(v, "height")? as usize];
+
+        let data = get(v, "data")?;
+        let tiles = match data {
+            Value::Array(a) => a
+                .iter()
+                .map(|t| match t.as_u64().map(u32::try_from) {
+                    Some(Ok(t)) => Ok(t),
+                    Some(Err(_)) => format_err(format!("tile id {} is too large", t)),
+                    None => format_err("tile id is not an unsigned integer"),
+                })
+                .collect::<Result<Vec<u32>, TiledError>>()?,
+            Value::String(s) => decode_tile_data(
+                v.get("encoding").and_then(|a| a.as_str()),
+                v.get("compression").and_then(|a| a.as_str()),
+                s,
+            )?,
+            _ => return format_err("invalid layer data"),
+        };
+
+        if tiles.len() != dim[0] * dim[1] {
+            return format_err("layer data does not match the layer dimensions");
+        }
+
+        Ok(TileLayer {
+            name: string(v, "name"),
+            dim,
+            visible: visible(v),
+            opacity: float_or(v, "opacity", 1.0),
+            tiles,
+        })
+    }
+
+    fn points(v: &Value) -> Vec<[f32; 2]> {
+        v.as_array()
+            .into_iter()
+            .flatten()
+            .map(|p| [float_or(p, "x", 0.0), float_or(p, "y", 0.0)])
+            .collect()
+    }
+
+    fn object_layer(v: &Value) -> Result<ObjectLayer, TiledError> {
+        let mut objects = Vec::new();
+        for o in get(v, "objects")?.as_array().into_iter().flatten() {
+            let width = float_or(o, "width", 0.0);
+            let height = float_or(o, "height", 0.0);
+            let flag = |name| o.get(name).and_then(|a: &Value| a.as_bool()).unwrap_or(false);
+
+            let shape = if flag("ellipse") {
+                ObjectShape::Ellipse { width, height }
+            } else if flag("point") {
+                ObjectShape::Point
+            } else if let Some(p) = o.get("polygon") {
+                ObjectShape::Polygon(points(p))
+            } else if let Some(p) = o.get("polyline") {
+                ObjectShape::Polyline(points(p))
+            } else {
+                ObjectShape::Rect { width, height }
+            };
+
+            let kind = match o.get("type").and_then(|a| a.as_str()) {
+                Some(t) if !t.is_empty() => t.to_string(),
+                _ => string(o, "class"),
+            };
+
+            objects.push(Object {
+                id: uint_or(o, "id", 0)?,
+                name: string(o, "name"),
+                kind,
+                pos: [float_or(o, "x", 0.0), float_or(o, "y", 0.0)],
+                rotation: float_or(o, "rotation", 0.0),
+                visible: visible(o),
+                gid: match o.get("gid") {
+                    Some(_) => Some(uint(o, "gid")?),
+                    None => None,
+                },
+                shape,
+            });
+        }
+
+        Ok(ObjectLayer {
+            name: string(v, "name"),
+            visible: visible(v),
+            objects,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //Two 2x2 tilesets, a csv layer with flipped tiles, a base64 layer inside a group, and every object shape.
+    const TMX: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<map version="1.10" orientation="orthogonal" width="2" height="2" tilewidth="16" tileheight="16" infinite="0">
+ <tileset firstgid="1" name="a" tilewidth="16" tileheight="16" tilecount="4" columns="2" margin="1" spacing="2">
+  <image source="a.png" width="36" height="36"/>
+ </tileset>
+ <tileset firstgid="5" name="b" tilewidth="16" tileheight="16" tilecount="4" columns="2">
+  <image source="b.png" width="32" height="32"/>
+ </tileset>
+ <layer id="1" name="ground" width="2" height="2">
+  <data encoding="csv">
+1,1610612742,
+0,2147483650
+</data>
+ </layer>
+ <group id="2" name="group">
+  <layer id="3" name="top" width="2" height="2" visible="0" opacity="0.5">
+   <data encoding="base64">
+   CAAAAAAAAAADAAAAAAAAAA==
+   </data>
+  </layer>
+ </group>
+ <objectgroup id="4" name="objects">
+  <object id="1" name="r" type="wall" x="1" y="2" width="3" height="4"/>
+  <object id="2" x="5" y="6" width="8" height="10"><ellipse/></object>
+  <object id="3" class="spawn" x="7" y="8"><point/></object>
+  <object id="4" x="0" y="0" rotation="90"><polygon points="0,0 4,0 4,4"/></object>
+  <object id="5" x="1" y="1" visible="0"><polyline points="0,0 2,2"/></object>
+  <object id="6" gid="3" x="16" y="32" width="16" height="16"/>
+ </objectgroup>
+</map>
+"#;
+
+    const JSON: &str = r#"{
+ "type": "map", "version": "1.10", "orientation": "orthogonal", "infinite": false,
+ "width": 2, "height": 2, "tilewidth": 16, "tileheight": 16,
+ "tilesets": [
+  {"firstgid": 1, "name": "a", "tilewidth": 16, "tileheight": 16, "tilecount": 4, "columns": 2,
+   "margin": 1, "spacing": 2, "image": "a.png", "imagewidth": 36, "imageheight": 36},
+  {"firstgid": 5, "name": "b", "tilewidth": 16, "tileheight": 16, "tilecount": 4, "columns": 2,
+   "image": "b.png", "imagewidth": 32, "imageheight": 32}
+ ],
+ "layers": [
+  {"type": "tilelayer", "id": 1, "name": "ground", "width": 2, "height": 2, "visible": true, "opacity": 1,
+   "data": [1, 1610612742, 0, 2147483650]},
+  {"type": "group", "id": 2, "name": "group", "layers": [
+   {"type": "tilelayer", "id": 3, "name": "top", "width": 2, "height": 2, "visible": false, "opacity": 0.5,
+    "encoding": "base64", "data": "CAAAAAAAAAADAAAAAAAAAA=="}
+  ]},
+  {"type": "objectgroup", "id": 4, "name": "objects", "visible": true, "objects": [
+   {"id": 1, "name": "r", "type": "wall", "x": 1, "y": 2, "width": 3, "height": 4, "visible": true},
+   {"id": 2, "x": 5, "y": 6, "width": 8, "height": 10, "ellipse": true},
+   {"id": 3, "type": "", "class": "spawn", "x": 7, "y": 8, "point": true},
+   {"id": 4, "x": 0, "y": 0, "rotation": 90, "polygon": [{"x": 0, "y": 0}, {"x": 4, "y": 0}, {"x": 4, "y": 4}]},
+   {"id": 5, "x": 1, "y": 1, "visible": false, "polyline": [{"x": 0, "y": 0}, {"x": 2, "y": 2}]},
+   {"id": 6, "gid": 3, "x": 16, "y": 32, "width": 16, "height": 16}
+  ]}
+ ]
+}"#;
+
+    fn tile_layer(map: &Map, i: usize) -> &TileLayer {
+        map.tile_layers().nth(i).unwrap()
+    }
+
+    fn is_format_err<T>(r: Result<T, TiledError>) -> bool {
+        matches!(r, Err(TiledError::Format(_)))
+    }
+
+    #[test]
+    fn tmx_and_json_match() {
+        let tmx = Map::from_tmx(TMX).unwrap();
+        let json = Map::from_json(JSON).unwrap();
+        assert_eq!(format!("{:?}", tmx), format!("{:?}", json));
+    }
+
+    #[test]
+    fn tile_data() {
+        for map in [Map::from_tmx(TMX).unwrap(), Map::from_json(JSON).unwrap()].iter() {
+            assert_eq!(map.dim, [2, 2]);
+            assert_eq!(map.layers.len(), 3);
+
+            let csv = tile_layer(map, 0);
+            assert_eq!(csv.tiles, vec![1, 0x6000_0006, 0, 0x8000_0002]);
+            assert!(csv.visible);
+
+            let base64 = tile_layer(map, 1);
+            assert_eq!(base64.name, "top");
+            assert_eq!(base64.tiles, vec![8, 0, 3, 0]);
+            assert!(!base64.visible);
+            assert_eq!(base64.opacity, 0.5);
+
+            let ts = &map.tilesets[0];
+            assert_eq!((ts.margin, ts.spacing, ts.image_dim), (1, 2, [36, 36]));
+            assert_eq!(ts.grid_dim().unwrap(), [2, 2]);
+        }
+    }
+
+    #[test]
+    fn flip_flags() {
+        let map = Map::from_tmx(TMX).unwrap();
+        let layer = tile_layer(&map, 0);
+        assert_eq!(layer.tile([0, 0]), Some((1, TileFlip::default())));
+        assert_eq!(
+            layer.tile([1, 0]),
+            Some((
+                6,
+                TileFlip {
+                    horizontal: false,
+                    vertical: true,
+                    diagonal: true
+                }
+            ))
+        );
+        assert_eq!(layer.tile([0, 1]), None);
+        assert_eq!(
+            layer.tile([1, 1]),
+            Some((
+                2,
+                TileFlip {
+                    horizontal: true,
+                    vertical: false,
+                    diagonal: false
+                }
+            ))
+        );
+        assert_eq!(decode_gid(ROTATED_HEXAGONAL | 7), (7, TileFlip::default()));
+    }
+
+    #[test]
+    fn tileset_by_first_gid() {
+        let map = Map::from_tmx(TMX).unwrap();
+        assert_eq!(map.tileset_of(1).unwrap(), (0, 0));
+        assert_eq!(map.tileset_of(4).unwrap(), (0, 3));
+        assert_eq!(map.tileset_of(5).unwrap(), (1, 0));
+        assert_eq!(map.tileset_of(8).unwrap(), (1, 3));
+        assert_eq!(map.tileset_of(0x6000_0006).unwrap(), (1, 1));
+        assert!(is_format_err(map.tileset_of(0)));
+        assert!(is_format_err(map.tileset_of(9)));
+
+        let mut layers = map.build_tile_layers(1).unwrap();
+        assert_eq!(layers.num_layers(), 2);
+        let tilemaps = layers.tilemaps();
+        assert_eq!(tilemaps[0].tile(0, [0, 0]), Some(0));
+        assert_eq!(tilemaps[1].tile(0, [0, 0]), None);
+        assert_eq!(tilemaps[1].tile(0, [1, 0]), Some(1));
+        assert!(tilemaps[0].tile_flipped(0, [1, 1]).unwrap().1.horizontal);
+        assert_eq!(tilemaps[1].tile(1, [0, 0]), Some(3));
+        assert_eq!(tilemaps[0].tile(1, [0, 1]), Some(2));
+    }
+
+    #[test]
+    fn object_shapes() {
+        let map = Map::from_json(JSON).unwrap();
+        let layer = map.object_layers().next().unwrap();
+        let o = &layer.objects;
+        assert_eq!(o.len(), 6);
+
+        assert_eq!((o[0].name.as_str(), o[0].kind.as_str()), ("r", "wall"));
+        assert_eq!(
+            o[0].outline(),
+            vec![[1.0, 2.0], [4.0, 2.0], [4.0, 6.0], [1.0, 6.0], [1.0, 2.0]]
+        );
+
+        assert!(matches!(o[1].shape, ObjectShape::Ellipse { width, height } if width == 8.0 && height == 10.0));
+        let ellipse = o[1].outline();
+        assert_eq!(ellipse.first(), ellipse.last());
+
+        assert!(matches!(o[2].shape, ObjectShape::Point));
+        assert_eq!(o[2].kind, "spawn");
+        assert!(o[2].outline().is_empty());
+
+        //Rotated a quarter turn clockwise, so x goes down.
+        let polygon = o[3].outline();
+        assert_eq!(polygon.len(), 4);
+        for (a, b) in polygon.iter().zip([[0.0, 0.0], [0.0, 4.0], [-4.0, 4.0], [0.0, 0.0]].iter()) {
+            assert!((a[0] - b[0]).abs() < 1e-5 && (a[1] - b[1]).abs() < 1e-5);
+        }
+
+        assert!(!o[4].visible);
+        assert_eq!(o[4].outline(), vec![[1.0, 1.0], [3.0, 3.0]]);
+
+        //Tile objects are positioned by their bottom left corner.
+        assert_eq!(o[5].gid, Some(3));
+        assert_eq!(o[5].outline()[0], [16.0, 16.0]);
+    }
+
+    #[test]
+    fn large_ids() {
+        let map = Map::from_tmx(TMX).unwrap();
+
+        let mut ts = map.tilesets[0].clone();
+        ts.first_gid = 0x0fff_0000;
+        ts.tile_count = u32::MAX;
+        assert!(ts.contains(0x0fff_ffff));
+        assert!(!ts.contains(1));
+
+        let mut big = map.clone();
+        big.tilesets[1].tile_count = 100_000;
+        assert_eq!(big.tileset_of(5 + 65535).unwrap(), (1, 65535));
+        assert!(is_format_err(big.tileset_of(5 + 65536)));
+
+        let json = JSON.replacen("\"firstgid\": 5", "\"firstgid\": 4294967301", 1);
+        assert!(is_format_err(Map::from_json(&json)));
+        let json = JSON.replacen("[1, 1610612742", "[4294967297, 1610612742", 1);
+        assert!(is_format_err(Map::from_json(&json)));
+    }
+
+    #[test]
+    fn grid_dim() {
+        let mut ts = Map::from_tmx(TMX).unwrap().tilesets[0].clone();
+        ts.columns = 255;
+        ts.tile_count = 255 * 255;
+        assert_eq!(ts.grid_dim().unwrap(), [255, 255]);
+        ts.columns = 256;
+        assert!(is_format_err(ts.grid_dim()));
+        ts.columns = 1;
+        ts.tile_count = 256;
+        assert!(is_format_err(ts.grid_dim()));
+    }
+
+    #[test]
+    fn layer_opacity() {
+        let map = Map::from_json(JSON).unwrap();
+        let mut layers = map.build_tile_layers(4).unwrap();
+        assert_eq!(layers.opacity, vec![1.0, 0.5]);
+        assert_eq!(layers.tilemaps().len(), 2);
+    }
+
+    #[test]
+    fn bad_data() {
+        let tmx = TMX.replacen("1,1610612742", "1,x", 1);
+        assert!(is_format_err(Map::from_tmx(&tmx)));
+        let tmx = TMX.replacen("CAAAAAAAAAADAAAAAAAAAA==", "CAAAAAAAAAADAAAAAAAA", 1);
+        assert!(is_format_err(Map::from_tmx(&tmx)));
+        let tmx = TMX.replacen("CAAAAAAAAAADAAAAAAAAAA==", "CAAAAAAAAAADAAAA!AAAAA==", 1);
+        assert!(is_format_err(Map::from_tmx(&tmx)));
+        let tmx = TMX.replacen("encoding=\"base64\"", "encoding=\"base64\" compression=\"zlib\"", 1);
+        assert!(is_format_err(Map::from_tmx(&tmx)));
+        let tmx = TMX.replacen("0,2147483650", "0", 1);
+        assert!(is_format_err(Map::from_tmx(&tmx)));
+    }
+}
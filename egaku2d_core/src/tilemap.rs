@@ -0,0 +1,314 @@
+//! Contains the tilemap drawing code.
+//!
+//! A tilemap is a grid of tile indicies into a `sprite::Texture`, with one grid per layer.
+//! Each layer is split up into square chunks, and each chunk is saved off into its own static buffer.
+//! When drawing, only the chunks that intersect the view of the canvas are drawn.
+//! Changing a tile only marks its chunk as dirty, and only dirty chunks are re-uploaded,
+//! the next time they are visible.
+
+use super::*;
+use crate::sprite::*;
+use sprite_program::FlippedVertex;
+
+///How a tile is mirrored. Follows the same order as the Tiled editor:
+///the diagonal flip is applied first, then the horizontal flip, then the vertical flip.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct TileFlip {
+    pub horizontal: bool,
+    pub vertical: bool,
+    pub diagonal: bool,
+}
+
+impl TileFlip {
+    //Turn the flips into a rotation and a mirroring along x and y that the sprite shader understands.
+    fn rotation_and_flip(&self) -> (u16, [i8; 2]) {
+        let a = if self.horizontal { -1 } else { 1 };
+        let b = if self.vertical { -1 } else { 1 };
+        if self.diagonal {
+            //A diagonal flip is a quarter turn with the x axis mirrored.
+            (u16::MAX / 4 + 1, [-a, b])
+        } else {
+            (0, [a, b])
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+struct Tile {
+    index: u16,
+    flip: TileFlip,
+}
+
+struct Chunk {
+    buffer: Option<vbo::StaticBuffer<FlippedVertex>>,
+    dirty: bool,
+}
+
+struct Layer {
+    tiles: Vec<Option<Tile>>,
+    chunks: Vec<Chunk>,
+}
+
+pub struct Tilemap {
+    dim: [usize; 2],
+    tile_size: f32,
+    chunk_size: usize,
+    chunk_dim: [usize; 2],
+    layers: Vec<Layer>,
+}
+
+impl Tilemap {
+    ///Create an empty tilemap.
+    ///The dimensions and chunk size are in tiles.
+    ///The tile size is the width of one tile in world units.
+    ///The top left corner of the map is at the origin.
+    pub fn new(dim: [usize; 2], num_layers: usize, tile_size: f32, chunk_size: usize) -> Tilemap {
+        assert!(chunk_size > 0, "chunk size must be at least one tile");
+
+        let chunk_dim = [dim[0].div_ceil(chunk_size), dim[1].div_ceil(chunk_size)];
+
+        let layers = (0..num_layers)
+            .map(|_| Layer {
+                tiles: vec![None; dim[0] * dim[1]],
+                chunks: (0..chunk_dim[0] * chunk_dim[1])
+                    .map(|_| Chunk {
+                        buffer: None,
+                        dirty: false,
+                    })
+                    .collect(),
+            })
+            .collect();
+
+        Tilemap {
+            dim,
+            tile_size,
+            chunk_size,
+            chunk_dim,
+            layers,
+        }
+    }
+
+    pub fn dim(&self) -> [usize; 2] {
+        self.dim
+    }
+
+    pub fn num_layers(&self) -> usize {
+        self.layers.len()
+    }
+
+    pub fn tile_size(&self) -> f32 {
+        self.tile_size
+    }
+
+    pub fn tile(&self, layer: usize, cell: [usize; 2]) -> Option<u16> {
+        self.tile_flipped(layer, cell).map(|(index, _)| index)
+    }
+
+    pub fn tile_flipped(&self, layer: usize, cell: [usize; 2]) -> Option<(u16, TileFlip)> {
+        assert!(cell[0] < self.dim[0] && cell[1] < self.dim[1]);
+        self.layers[layer].tiles[cell[1] * self.dim[0] + cell[0]].map(|t| (t.index, t.flip))
+    }
+
+    ///Set the texture index of one tile. Passing None leaves the cell empty.
+    pub fn set_tile(&mut self, layer: usize, cell: [usize; 2], tile: Option<u16>) {
+        self.set_tile_inner(layer, cell, tile.map(|index| Tile {
+            index,
+            flip: TileFlip::default(),
+        }));
+    }
+
+    ///Set the texture index of one tile, and how it is mirrored.
+    pub fn set_tile_flipped(&mut self, layer: usize, cell: [usize; 2], index: u16, flip: TileFlip) {
+        self.set_tile_inner(layer, cell, Some(Tile { index, flip }));
+    }
+
+    fn set_tile_inner(&mut self, layer: usize, cell: [usize; 2], tile: Option<Tile>) {
+        assert!(cell[0] < self.dim[0] && cell[1] < self.dim[1]);
+        let chunk = self.chunk_of(cell);
+        let layer = &mut self.layers[layer];
+        layer.tiles[cell[1] * self.dim[0] + cell[0]] = tile;
+        layer.chunks[chunk].dirty = true;
+    }
+
+    fn chunk_of(&self, cell: [usize; 2]) -> usize {
+        (cell[1] / self.chunk_size) * self.chunk_dim[0] + cell[0] / self.chunk_size
+    }
+
+    ///Draw every layer in order, back to front.
+    pub fn draw(&mut self, sys: &mut SimpleCanvas, texture: &Texture) {
+        for layer in 0..self.layers.len() {
+            self.draw_layer(layer, sys, texture);
+        }
+    }
+
+    ///Draw the chunks of one layer that intersect the view of the canvas.
+    ///Returns the number of chunks that were drawn.
+    pub fn draw_layer(&mut self, layer: usize, sys: &mut SimpleCanvas, texture: &Texture) -> usize {
+        self.draw_layer_faded(layer, sys, texture, 1.0)
+    }
+
+    //Draw a layer with the alpha of the default color multiplied by the opacity.
+    pub(crate) fn draw_layer_faded(&mut self, layer: usize, sys: &mut SimpleCanvas, texture: &Texture, opacity: f32) -> usize {
+        let [xs, ys] = self.visible_chunks(sys.view_rect());
+
+        //Tiles are always sized in world units, so they line up with each other at any zoom.
+        let radius = self.tile_size;
+        let mut drawn = 0;
+        for cy in ys.0..ys.1 {
+            for cx in xs.0..xs.1 {
+                let index = cy * self.chunk_dim[0] + cx;
+                if self.take_dirty(layer, index) {
+                    let verts = self.chunk_verts(layer, [cx, cy]);
+                    self.layers[layer].chunks[index].buffer = if verts.is_empty() {
+                        None
+                    } else {
+                        Some(vbo::StaticBuffer::new(&verts))
+                    };
+                }
+
+                if let Some(buffer) = &self.layers[layer].chunks[index].buffer {
+                    let mut common = UniformCommon {
+                        size_space: SizeSpace::World,
+                        ..sys.common(sys.offset)
+                    };
+                    common.color[3] *= opacity;
+                    let un = SpriteProgramUniformValues {
+                        radius: radius * core::f32::consts::SQRT_2,
+                        texture,
+                        format: VertexFormat::Flipped,
+                    };
+                    Uniforms {
+                        sys: &mut *sys,
+                        common,
+                        un: UniformVals::Sprite(un),
+                        buffer: queue::Vertices::Uploaded(buffer.get_info()),
+                    }
+                    .draw();
+                    drawn += 1;
+                }
+            }
+        }
+        drawn
+    }
+
+    //Returns the half open range of chunks in x and in y that intersect the rect.
+    fn visible_chunks(&self, view: Rect<f32>) -> [(usize, usize); 2] {
+        let chunk_width = self.tile_size * self.chunk_size as f32;
+
+        //The last chunks can reach past the edge of the map, so the view is cut to the map first.
+        let range = |start: f32, end: f32, tiles: usize, max: usize| {
+            let end = end.min(tiles as f32 * self.tile_size);
+            if start >= end {
+                return (0, 0);
+            }
+            let a = (start / chunk_width).floor().max(0.0) as usize;
+            let b = (end / chunk_width).ceil().max(0.0) as usize;
+            (a.min(max), b.min(max))
+        };
+
+        [
+            range(view.x.start, view.x.end, self.dim[0], self.chunk_dim[0]),
+            range(view.y.start, view.y.end, self.dim[1], self.chunk_dim[1]),
+        ]
+    }
+
+    //Returns whether the chunk has to be built again, and marks it as built.
+    fn take_dirty(&mut self, layer: usize, index: usize) -> bool {
+        core::mem::replace(&mut self.layers[layer].chunks[index].dirty, false)
+    }
+
+    fn chunk_verts(&self, layer: usize, chunk: [usize; 2]) -> Vec<FlippedVertex> {
+        let startx = chunk[0] * self.chunk_size;
+        let starty = chunk[1] * self.chunk_size;
+        let endx = (startx + self.chunk_size).min(self.dim[0]);
+        let endy = (starty + self.chunk_size).min(self.dim[1]);
+
+        let tiles = &self.layers[layer].tiles;
+        let mut verts = Vec::new();
+        for y in starty..endy {
+            for x in startx..endx {
+                if let Some(tile) = tiles[y * self.dim[0] + x] {
+                    let (rotation, flip) = tile.flip.rotation_and_flip();
+                    verts.push(FlippedVertex {
+                        pos: [
+                            (x as f32 + 0.5) * self.tile_size,
+                            (y as f32 + 0.5) * self.tile_size,
+                        ],
+                        index: tile.index,
+                        rotation,
+                        flip,
+                        _pad: [0; 2],
+                    });
+                }
+            }
+        }
+        verts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //Ten by ten tiles of size one, in chunks of four, so the last row and column of chunks are two tiles wide.
+    fn map() -> Tilemap {
+        Tilemap::new([10, 10], 2, 1.0, 4)
+    }
+
+    fn view(x: [f32; 2], y: [f32; 2]) -> Rect<f32> {
+        Rect::new(x[0], x[1], y[0], y[1])
+    }
+
+    #[test]
+    fn chunk_borders() {
+        let m = map();
+        assert_eq!(m.chunk_dim, [3, 3]);
+        assert_eq!(m.visible_chunks(view([0.0, 4.0], [0.0, 4.0])), [(0, 1), (0, 1)]);
+        assert_eq!(m.visible_chunks(view([4.0, 8.0], [8.0, 10.0])), [(1, 2), (2, 3)]);
+        assert_eq!(m.visible_chunks(view([3.9, 4.1], [0.5, 0.6])), [(0, 2), (0, 1)]);
+        assert_eq!(m.visible_chunks(view([0.0, 10.0], [0.0, 10.0])), [(0, 3), (0, 3)]);
+    }
+
+    #[test]
+    fn views_outside_of_the_map() {
+        let m = map();
+        let empty = |r: (usize, usize)| r.0 >= r.1;
+
+        let [x, y] = m.visible_chunks(view([-10.0, -1.0], [0.0, 4.0]));
+        assert!(empty(x) && !empty(y));
+        let [x, _] = m.visible_chunks(view([20.0, 30.0], [0.0, 4.0]));
+        assert!(empty(x));
+        let [_, y] = m.visible_chunks(view([0.0, 4.0], [10.0, 11.0]));
+        assert!(empty(y));
+
+        assert_eq!(m.visible_chunks(view([-5.0, 5.0], [-1e9, 1e9])), [(0, 2), (0, 3)]);
+    }
+
+    #[test]
+    fn dirty_chunks() {
+        let mut m = map();
+        assert_eq!(m.chunk_of([0, 0]), 0);
+        assert_eq!(m.chunk_of([3, 4]), 3);
+        assert_eq!(m.chunk_of([9, 9]), 8);
+
+        //Nothing to build in a new map.
+        assert!((0..9).all(|i| !m.take_dirty(0, i)));
+
+        m.set_tile(0, [5, 1], Some(7));
+        m.set_tile(1, [9, 9], None);
+        assert!(m.take_dirty(0, 1));
+        assert!(!m.take_dirty(0, 1));
+        assert!(!m.take_dirty(1, 1));
+        assert!(m.take_dirty(1, 8));
+        assert!((0..9).all(|i| !m.take_dirty(0, i) && !m.take_dirty(1, i)));
+
+        let verts = m.chunk_verts(0, [1, 0]);
+        assert_eq!(verts.len(), 1);
+        assert_eq!((verts[0].pos, verts[0].index), ([5.5, 1.5], 7));
+
+        //Emptying the only tile leaves nothing to draw.
+        m.set_tile(0, [5, 1], None);
+        assert!(m.take_dirty(0, 1));
+        assert!(m.chunk_verts(0, [1, 0]).is_empty());
+    }
+}
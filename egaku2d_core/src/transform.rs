@@ -0,0 +1,147 @@
+//! Contains the per draw transform.
+//!
+//! An `Affine2D` can be passed to `Uniforms::with_transform()` to move, rotate and scale
+//! a saved buffer when it is drawn, without touching its vertices. The transform is applied
+//! in the vertex shaders, to the positions the buffer was made with, before the offset and the camera.
+
+///A 3x3 matrix that takes local positions to world positions.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Affine2D {
+    matrix: [[f32; 3]; 3],
+}
+
+impl Default for Affine2D {
+    fn default() -> Self {
+        Affine2D::identity()
+    }
+}
+
+impl From<[[f32; 3]; 3]> for Affine2D {
+    fn from(matrix: [[f32; 3]; 3]) -> Self {
+        Affine2D::from_matrix(matrix)
+    }
+}
+
+impl Affine2D {
+    pub fn identity() -> Affine2D {
+        Affine2D::from_matrix([[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]])
+    }
+
+    ///Scale, then rotate, then translate.
+    ///Positive rotations turn the x axis towards the y axis, which is clockwise on screen since y grows downwards.
+    pub fn new(translation: [f32; 2], rotation: f32, scale: [f32; 2]) -> Affine2D {
+        let (s, c) = rotation.sin_cos();
+        Affine2D::from_matrix([
+            [c * scale[0], s * scale[0], 0.0],
+            [-s * scale[1], c * scale[1], 0.0],
+            [translation[0], translation[1], 1.0],
+        ])
+    }
+
+    pub fn translation(translation: [f32; 2]) -> Affine2D {
+        Affine2D::new(translation, 0.0, [1.0, 1.0])
+    }
+
+    pub fn rotation(rotation: f32) -> Affine2D {
+        Affine2D::new([0.0, 0.0], rotation, [1.0, 1.0])
+    }
+
+    pub fn scale(scale: [f32; 2]) -> Affine2D {
+        Affine2D::new([0.0, 0.0], 0.0, scale)
+    }
+
+    ///Use any column major matrix. If the last row is not `[0,0,1]`,
+    ///the result is divided by its z component, so projective transforms work too.
+    pub fn from_matrix(matrix: [[f32; 3]; 3]) -> Affine2D {
+        Affine2D { matrix }
+    }
+
+    ///The column major matrix.
+    pub fn matrix(&self) -> [[f32; 3]; 3] {
+        self.matrix
+    }
+
+    ///The transform that applies this one, and then the other one.
+    pub fn then(&self, other: &Affine2D) -> Affine2D {
+        let a = &other.matrix;
+        let b = &self.matrix;
+        let mut matrix = [[0.0; 3]; 3];
+        for (col, out) in matrix.iter_mut().enumerate() {
+            for (row, out) in out.iter_mut().enumerate() {
+                *out = (0..3).map(|k| a[k][row] * b[col][k]).sum();
+            }
+        }
+        Affine2D { matrix }
+    }
+
+    ///Transform a point the same way the shaders do.
+    pub fn transform_point(&self, point: [f32; 2]) -> [f32; 2] {
+        let m = &self.matrix;
+        let x = m[0][0] * point[0] + m[1][0] * point[1] + m[2][0];
+        let y = m[0][1] * point[0] + m[1][1] * point[1] + m[2][1];
+        let z = m[0][2] * point[0] + m[1][2] * point[1] + m[2][2];
+        [x / z, y / z]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: [f32; 2], b: [f32; 2]) -> bool {
+        (a[0] - b[0]).abs() < 1e-4 && (a[1] - b[1]).abs() < 1e-4
+    }
+
+    const POINTS: [[f32; 2]; 4] = [[0.0, 0.0], [1.0, 0.0], [0.0, 1.0], [-3.5, 7.25]];
+
+    #[test]
+    fn then_applies_self_first() {
+        let a = Affine2D::new([3.0, -1.0], 0.4, [2.0, 0.5]);
+        let b = Affine2D::new([-7.0, 2.0], -1.3, [1.5, 3.0]);
+        for &p in POINTS.iter() {
+            let expected = b.transform_point(a.transform_point(p));
+            assert!(close(a.then(&b).transform_point(p), expected));
+        }
+
+        //The order matters.
+        let t = Affine2D::translation([10.0, 0.0]);
+        let r = Affine2D::rotation(core::f32::consts::FRAC_PI_2);
+        assert!(close(t.then(&r).transform_point([0.0, 0.0]), [0.0, 10.0]));
+        assert!(close(r.then(&t).transform_point([0.0, 0.0]), [10.0, 0.0]));
+
+        assert_eq!(a.then(&Affine2D::identity()), a);
+        assert_eq!(Affine2D::identity().then(&a), a);
+    }
+
+    #[test]
+    fn new_scales_then_rotates_then_translates() {
+        let m = Affine2D::new([5.0, 6.0], 0.9, [2.0, -3.0]);
+        let parts = Affine2D::scale([2.0, -3.0])
+            .then(&Affine2D::rotation(0.9))
+            .then(&Affine2D::translation([5.0, 6.0]));
+        for &p in POINTS.iter() {
+            assert!(close(m.transform_point(p), parts.transform_point(p)));
+        }
+
+        //Positive rotations turn x towards y.
+        let r = Affine2D::rotation(core::f32::consts::FRAC_PI_2);
+        assert!(close(r.transform_point([1.0, 0.0]), [0.0, 1.0]));
+    }
+
+    #[test]
+    fn from_matrix() {
+        //Column major, so the translation is in the last column.
+        let m = Affine2D::from([[1.0, 2.0, 0.0], [3.0, 4.0, 0.0], [5.0, 6.0, 1.0]]);
+        assert_eq!(m.matrix()[2], [5.0, 6.0, 1.0]);
+        assert!(close(m.transform_point([1.0, 1.0]), [9.0, 12.0]));
+        assert_eq!(m, Affine2D::from_matrix(m.matrix()));
+
+        //A projective matrix divides by z.
+        let p = Affine2D::from_matrix([[1.0, 0.0, 1.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]]);
+        assert!(close(p.transform_point([1.0, 4.0]), [0.5, 2.0]));
+        for &q in POINTS.iter() {
+            let expected = m.transform_point(p.transform_point(q));
+            assert!(close(p.then(&m).transform_point(q), expected));
+        }
+    }
+}
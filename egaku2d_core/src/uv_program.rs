@@ -0,0 +1,233 @@
+use super::*;
+use crate::gl;
+use crate::shader::*;
+use crate::state::{GlState, UniformValue, VaoKey};
+use crate::vbo::BufferInfo;
+use std::ffi::CString;
+use std::str;
+
+// Shader sources
+static VS_SRC: &str = "
+#version 300 es
+in vec2 position;
+in vec2 uv;
+in vec2 instance_offset;
+in vec4 instance_matrix;
+in vec4 instance_color;
+out vec4 vinstance_color;
+out vec2 vuv;
+uniform vec2 offset;
+uniform vec2 uv_scale;
+uniform mat3 mmatrix;
+uniform mat3 transform;
+void main() {
+    vec3 t=transform*vec3(mat2(instance_matrix)*position+instance_offset,1.0);
+    vec3 pp=vec3(t.xy/t.z+offset,1.0);
+    vuv=uv*uv_scale;
+    vinstance_color=instance_color;
+    gl_Position = vec4(mmatrix*pp.xyz, 1.0);
+}";
+
+static FS_SRC: &str = "
+#version 300 es
+precision mediump float;
+uniform vec4 bcol;
+uniform sampler2D tex0;
+in vec2 vuv;
+in vec4 vinstance_color;
+out vec4 out_color;
+
+void main() {
+    out_color=texture(tex0,vuv)*bcol*vinstance_color;
+}";
+
+///A vertex with a texture coordinate.
+///The texture coordinate is in pixels, with 0,0 at the top left of the texture.
+///It is normalized when drawing, with the size the texture has then.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Vertex {
+    pub pos: [f32; 2],
+    pub uv: [f32; 2],
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct UvProgramUniformValues<'a> {
+    pub texture: &'a sprite::Texture,
+}
+
+#[derive(Debug)]
+pub struct UvProgram {
+    pub program: GLuint,
+    pub matrix_uniform: GLint,
+    pub transform_uniform: GLint,
+    pub(crate) instance_attrs: instance::InstanceAttrs,
+    pub offset_uniform: GLint,
+    pub uv_scale_uniform: GLint,
+    pub bcol_uniform: GLint,
+    pub pos_attr: GLint,
+    pub uv_attr: GLint,
+    pub sample_location: GLint,
+}
+
+impl UvProgram {
+    pub fn set_matrix(&mut self, matrix: &[[f32; 3]; 3]) {
+        unsafe {
+            gl::UseProgram(self.program);
+            gl_ok!();
+            gl::UniformMatrix3fv(self.matrix_uniform, 1, 0, matrix[0].as_ptr());
+            gl_ok!();
+        }
+    }
+
+    pub(crate) fn set_buffer_and_draw(
+        &mut self,
+        state: &mut GlState,
+        common: &UniformCommon,
+        un: &UvProgramUniformValues,
+        buffer_info: BufferInfo,
+    ) {
+        let col = common.color;
+        let offset = common.offset;
+        let stride = core::mem::size_of::<Vertex>() as i32;
+
+        unsafe {
+            state.use_program(self.program);
+
+            state.uniform(self.offset_uniform, UniformValue::Vec2([offset.x, offset.y]));
+            state.uniform(self.transform_uniform, UniformValue::Mat3(common.transform.matrix()));
+            state.uniform(self.bcol_uniform, UniformValue::Vec4(col));
+            let [tw, th] = un.texture.dim();
+            state.uniform(self.uv_scale_uniform, UniformValue::Vec2([1.0 / tw, 1.0 / th]));
+
+            state.bind_texture(gl::TEXTURE_2D, un.texture.id);
+            state.uniform(self.sample_location, UniformValue::Int(0));
+
+            let key = VaoKey {
+                program: self.program,
+                buffer: buffer_info.id,
+                stride,
+                variant: 0,
+                batch: None,
+            };
+            state.bind_vertex_array(key, || {
+                gl::BindBuffer(gl::ARRAY_BUFFER, buffer_info.id);
+                gl_ok!();
+
+                gl::EnableVertexAttribArray(self.pos_attr as GLuint);
+                gl_ok!();
+
+                gl::VertexAttribPointer(
+                    self.pos_attr as GLuint,
+                    2,
+                    gl::FLOAT,
+                    gl::FALSE as GLboolean,
+                    stride,
+                    core::ptr::null(),
+                );
+                gl_ok!();
+
+                gl::EnableVertexAttribArray(self.uv_attr as GLuint);
+                gl_ok!();
+
+                gl::VertexAttribPointer(
+                    self.uv_attr as GLuint,
+                    2,
+                    gl::FLOAT,
+                    gl::FALSE as GLboolean,
+                    stride,
+                    (4 * 2) as *const _,
+                );
+                gl_ok!();
+                5
+            });
+
+            self.instance_attrs.enable(common.instances);
+
+            self.instance_attrs
+                .draw(gl::TRIANGLES, buffer_info, common.instances);
+
+            self.instance_attrs.disable(common.instances);
+        }
+    }
+
+    pub fn new() -> UvProgram {
+        unsafe {
+            // Create GLSL shaders
+            let vs = compile_shader(VS_SRC, gl::VERTEX_SHADER);
+            gl_ok!();
+
+            let fs = compile_shader(FS_SRC, gl::FRAGMENT_SHADER);
+            gl_ok!();
+
+            let program = link_program(vs, fs);
+            gl_ok!();
+
+            gl::DeleteShader(fs);
+            gl_ok!();
+
+            gl::DeleteShader(vs);
+            gl_ok!();
+
+            gl::UseProgram(program);
+            gl_ok!();
+
+            let temp = CString::new("mmatrix").unwrap();
+            let matrix_uniform: GLint = gl::GetUniformLocation(program, temp.as_ptr());
+            gl_ok!();
+
+            let temp = CString::new("transform").unwrap();
+            let transform_uniform: GLint = gl::GetUniformLocation(program, temp.as_ptr());
+            gl_ok!();
+
+            let instance_attrs = instance::InstanceAttrs::new(program);
+
+            let temp = CString::new("bcol").unwrap();
+            let bcol_uniform: GLint = gl::GetUniformLocation(program, temp.as_ptr());
+            gl_ok!();
+
+            let temp = CString::new("offset").unwrap();
+            let offset_uniform: GLint = gl::GetUniformLocation(program, temp.as_ptr());
+            gl_ok!();
+
+            let temp = CString::new("uv_scale").unwrap();
+            let uv_scale_uniform: GLint = gl::GetUniformLocation(program, temp.as_ptr());
+            gl_ok!();
+
+            let temp = CString::new("position").unwrap();
+            let pos_attr = gl::GetAttribLocation(program, temp.as_ptr());
+            gl_ok!();
+
+            let temp = CString::new("uv").unwrap();
+            let uv_attr = gl::GetAttribLocation(program, temp.as_ptr());
+            gl_ok!();
+
+            let temp = CString::new("tex0").unwrap();
+            let sample_location = gl::GetUniformLocation(program, temp.as_ptr());
+            gl_ok!();
+
+            UvProgram {
+                program,
+                matrix_uniform,
+                transform_uniform,
+                instance_attrs,
+                offset_uniform,
+                uv_scale_uniform,
+                bcol_uniform,
+                pos_attr,
+                uv_attr,
+                sample_location,
+            }
+        }
+    }
+}
+
+impl Drop for UvProgram {
+    fn drop(&mut self) {
+        // Cleanup
+        unsafe {
+            gl::DeleteProgram(self.program);
+            gl_ok!();
+        }
+    }
+}
@@ -1,28 +1,43 @@
 use super::*;
 use core::marker::PhantomData;
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub(crate) struct BufferInfo {
     pub id: u32,
+    ///The index of the first element to draw.
+    pub first: usize,
     pub length: usize,
 }
 
+//Deletes the buffer when the last handle to it is dropped.
 #[derive(Debug)]
-pub struct StaticBuffer<V> {
-    info: BufferInfo,
-    _p: PhantomData<V>,
-}
-impl<V> Drop for StaticBuffer<V> {
+pub(crate) struct OwnedBuffer(GLuint);
+
+impl Drop for OwnedBuffer {
     fn drop(&mut self) {
         unsafe {
-            gl::DeleteBuffers(1, &self.info.id);
+            gl::DeleteBuffers(1, &self.0);
+            state::forget_buffer(self.0);
         }
     }
 }
+
+///Keeps a static buffer alive, for example while its draw waits in the render queue.
+pub(crate) type BufferHandle = std::rc::Rc<OwnedBuffer>;
+
+#[derive(Debug)]
+pub struct StaticBuffer<V> {
+    info: BufferInfo,
+    handle: BufferHandle,
+    _p: PhantomData<V>,
+}
 impl<V: core::fmt::Debug + Copy + Clone> StaticBuffer<V> {
     pub(crate) fn get_info(&self) -> BufferInfo {
         self.info
     }
+    pub(crate) fn handle(&self) -> &BufferHandle {
+        &self.handle
+    }
     pub fn new(data: &[V]) -> StaticBuffer<V> {
         let mut vbo = 0;
         unsafe {
@@ -41,206 +56,237 @@ impl<V: core::fmt::Debug + Copy + Clone> StaticBuffer<V> {
         StaticBuffer {
             info: BufferInfo {
                 id: vbo,
+                first: 0,
                 length: data.len(),
             },
+            handle: BufferHandle::new(OwnedBuffer(vbo)),
             _p: PhantomData,
         }
     }
 }
 
-#[derive(Clone, Debug)]
-pub struct GrowableBuffer<B> {
-    vbo: u32,
-    length: usize,
-    _p: PhantomData<B>,
-}
-impl<B> Drop for GrowableBuffer<B> {
-    fn drop(&mut self) {
-        //TODO make sure this is ok to do
-        unsafe {
-            gl::DeleteBuffers(1, &self.vbo);
-        }
-    }
+///A buffer whose elements are updated in place.
+///Only the ranges that changed are sent, unless it has to grow, in which case everything is sent again.
+#[derive(Debug)]
+pub(crate) struct DynamicBuffer<V> {
+    handle: BufferHandle,
+    capacity: usize,
+    _p: PhantomData<V>,
 }
-impl<B> GrowableBuffer<B> {
-    pub(crate) fn new() -> GrowableBuffer<B> {
-        let mut vbo: u32 = 0;
-        // Create a Vertex Buffer Object and copy the vertex data to it
+impl<V> DynamicBuffer<V> {
+    pub(crate) fn new() -> DynamicBuffer<V> {
+        let mut vbo = 0;
         unsafe {
             gl::GenBuffers(1, &mut vbo);
+            gl_ok!();
         }
-        /*
-        gl::BindBuffer(gl::ARRAY_BUFFER, &mut vbo);
-        gl_ok!();
-        gl::BufferData(
-            gl::ARRAY_BUFFER,
-            (0 * mem::size_of::<B>()) as GLsizeiptr,
-            [].as_ptr() as *const _,
-            gl::DYNAMIC_DRAW,
-        );
-        gl_ok!();
-        */
-        GrowableBuffer {
-            vbo,
-            length: 0,
+        DynamicBuffer {
+            handle: BufferHandle::new(OwnedBuffer(vbo)),
+            capacity: 0,
             _p: PhantomData,
         }
     }
 
-    pub(crate) fn send_to_gpu(&mut self, arr: &[B]) {
-        if arr.len() > self.length {
-            let vbo = &mut self.vbo;
-            unsafe {
-                gl::DeleteBuffers(1, vbo);
-                gl_ok!();
-                gl::BindBuffer(gl::ARRAY_BUFFER, *vbo);
-                gl_ok!();
+    pub(crate) fn handle(&self) -> &BufferHandle {
+        &self.handle
+    }
+
+    pub(crate) fn get_info(&self, length: usize) -> BufferInfo {
+        BufferInfo {
+            id: self.handle.0,
+            first: 0,
+            length,
+        }
+    }
+
+    ///Send the ranges of `data` that changed. Returns the number of bytes sent.
+    pub(crate) fn update(&mut self, data: &[V], changed: &[core::ops::Range<usize>]) -> usize {
+        let size = mem::size_of::<V>();
+        if changed.is_empty() && data.len() <= self.capacity {
+            return 0;
+        }
+        unsafe {
+            gl::BindBuffer(gl::ARRAY_BUFFER, self.handle.0);
+            gl_ok!();
+
+            if data.len() > self.capacity {
+                self.capacity = data.len().max(self.capacity * 2).max(MIN_CAPACITY);
                 gl::BufferData(
                     gl::ARRAY_BUFFER,
-                    (arr.len() * mem::size_of::<B>()) as GLsizeiptr,
-                    arr.as_ptr() as *const _,
+                    (self.capacity * size) as GLsizeiptr,
+                    core::ptr::null(),
                     gl::DYNAMIC_DRAW,
                 );
-                gl_ok!()
-            }
-        } else {
-            unsafe {
-                gl::BindBuffer(gl::ARRAY_BUFFER, self.vbo);
-                gl_ok!();
-
-                gl::BufferSubData(
-                    gl::ARRAY_BUFFER,
-                    0,
-                    (arr.len() * mem::size_of::<B>()) as GLsizeiptr,
-                    arr.as_ptr() as *const _,
-                );
                 gl_ok!();
+                return self.send(data, 0..data.len());
             }
-        }
 
-        self.length = arr.len();
+            changed.iter().map(|r| self.send(data, r.clone())).sum()
+        }
     }
 
-    pub(crate) fn get_info(&self, length: usize) -> BufferInfo {
-        assert!(length <= self.length);
-        BufferInfo {
-            id: self.vbo,
-            length,
+    unsafe fn send(&self, data: &[V], range: core::ops::Range<usize>) -> usize {
+        let size = mem::size_of::<V>();
+        let bytes = range.len() * size;
+        if bytes > 0 {
+            gl::BufferSubData(
+                gl::ARRAY_BUFFER,
+                (range.start * size) as GLintptr,
+                bytes as GLsizeiptr,
+                data[range].as_ptr() as *const _,
+            );
+            gl_ok!();
         }
+        bytes
+    }
+}
+
+///Counts the work done by the streaming vertex buffers.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct StreamStats {
+    ///Bytes sent to the gpu.
+    pub bytes_uploaded: usize,
+    ///Times a buffer had to grow to fit an upload.
+    pub reallocations: usize,
+    ///Times a full buffer was orphaned and writing started over at the beginning.
+    pub orphans: usize,
+}
+
+impl core::ops::AddAssign for StreamStats {
+    fn add_assign(&mut self, other: StreamStats) {
+        self.bytes_uploaded += other.bytes_uploaded;
+        self.reallocations += other.reallocations;
+        self.orphans += other.orphans;
     }
 }
 
-/*
-#[derive(Clone, Debug)]
-pub struct GrowableBuffer<V> {
+//The smallest number of elements a stream buffer allocates room for.
+const MIN_CAPACITY: usize = 256;
+
+///A buffer for vertices that change every draw.
+///Every upload is appended after the previous one, so uploads never write over
+///vertices that a draw still in flight might be reading. Once the buffer is full its storage is orphaned,
+///which lets the driver hand out fresh storage without waiting for the gpu.
+///It grows geometrically if an upload does not fit at all.
+#[derive(Debug)]
+pub struct StreamBuffer<B> {
     vbo: u32,
-    buffer: Vec<V>,
-    vbo_size: Option<usize>,
+    capacity: usize,
+    cursor: usize,
+    last: BufferInfo,
+    stats: StreamStats,
+    _p: PhantomData<B>,
 }
-impl<V> Drop for GrowableBuffer<V> {
+impl<B> Drop for StreamBuffer<B> {
     fn drop(&mut self) {
         //TODO make sure this is ok to do
         unsafe {
             gl::DeleteBuffers(1, &self.vbo);
+            state::forget_buffer(self.vbo);
         }
     }
 }
-
-impl<V: Default> GrowableBuffer<V> {
-    pub(crate) fn get_info(&self) -> BufferInfo {
-        BufferInfo {
-            id: self.vbo,
-            length: self.buffer.len(),
+impl<B> StreamBuffer<B> {
+    pub(crate) fn new() -> StreamBuffer<B> {
+        let mut vbo: u32 = 0;
+        unsafe {
+            gl::GenBuffers(1, &mut vbo);
+        }
+        StreamBuffer {
+            vbo,
+            capacity: 0,
+            cursor: 0,
+            last: BufferInfo {
+                id: vbo,
+                first: 0,
+                length: 0,
+            },
+            stats: StreamStats::default(),
+            _p: PhantomData,
         }
     }
-    #[inline(always)]
-    pub fn get_verts(&self) -> &[V] {
-        &self.buffer
-    }
-
-    pub fn new() -> GrowableBuffer<V> {
-        let mut vbo = 0;
 
-        let mut buffer = Vec::new();
-        //TODO add a pre-set capacity???
-        buffer.clear();
+    unsafe fn allocate(&mut self) {
+        gl::BufferData(
+            gl::ARRAY_BUFFER,
+            (self.capacity * mem::size_of::<B>()) as GLsizeiptr,
+            core::ptr::null(),
+            gl::STREAM_DRAW,
+        );
+        gl_ok!();
+        self.cursor = 0;
+    }
 
+    pub(crate) fn send_to_gpu(&mut self, arr: &[B]) {
+        let size = mem::size_of::<B>();
         unsafe {
-            // Create a Vertex Buffer Object and copy the vertex data to it
-            gl::GenBuffers(1, &mut vbo);
+            gl::BindBuffer(gl::ARRAY_BUFFER, self.vbo);
             gl_ok!();
-            gl::BindBuffer(gl::ARRAY_BUFFER, vbo);
-            gl_ok!();
-            gl::BufferData(
-                gl::ARRAY_BUFFER,
-                (buffer.capacity() * mem::size_of::<V>()) as GLsizeiptr,
-                buffer.as_ptr() as *const _,
-                gl::DYNAMIC_DRAW,
-            );
-            gl_ok!();
-        }
 
-        GrowableBuffer {
-            vbo,
-            buffer,
-            vbo_size: None,
-        }
-    }
+            if arr.len() > self.capacity {
+                self.capacity = arr.len().max(self.capacity * 2).max(MIN_CAPACITY);
+                self.allocate();
+                self.stats.reallocations += 1;
+            } else if self.cursor + arr.len() > self.capacity {
+                self.allocate();
+                self.stats.orphans += 1;
+            }
+
+            if !arr.is_empty() {
+                let offset = (self.cursor * size) as GLintptr;
+                let bytes = arr.len() * size;
 
-    pub fn update(&mut self) {
-        let vbo = self.vbo;
+                //Nothing in flight uses this range, so there is no need to synchronize.
+                let ptr = gl::MapBufferRange(
+                    gl::ARRAY_BUFFER,
+                    offset,
+                    bytes as GLsizeiptr,
+                    gl::MAP_WRITE_BIT | gl::MAP_INVALIDATE_RANGE_BIT | gl::MAP_UNSYNCHRONIZED_BIT,
+                );
 
-        match self.vbo_size {
-            Some(l) => {
-                if l < self.buffer.capacity() {
-                    self.re_generate_buffer();
+                if ptr.is_null() {
+                    //Mapping failed and left an error behind. Every earlier call was checked,
+                    //so that is the only error to clear before uploading the slow way instead.
+                    gl::GetError();
+                    gl::BufferSubData(
+                        gl::ARRAY_BUFFER,
+                        offset,
+                        bytes as GLsizeiptr,
+                        arr.as_ptr() as *const _,
+                    );
+                    gl_ok!();
+                } else {
+                    core::ptr::copy_nonoverlapping(arr.as_ptr() as *const u8, ptr as *mut u8, bytes);
+                    gl::UnmapBuffer(gl::ARRAY_BUFFER);
+                    gl_ok!();
                 }
-                assert!(self.vbo_size.unwrap() >= self.buffer.capacity());
-            }
-            None => {
-                self.re_generate_buffer();
+                self.stats.bytes_uploaded += bytes;
             }
         }
 
-        unsafe {
-            gl::BindBuffer(gl::ARRAY_BUFFER, vbo);
-            gl_ok!();
-
-            gl::BufferSubData(
-                gl::ARRAY_BUFFER,
-                0,
-                (self.buffer.len() * mem::size_of::<V>()) as GLsizeiptr,
-                self.buffer.as_ptr() as *const _,
-            );
-            gl_ok!();
-        }
+        self.last = BufferInfo {
+            id: self.vbo,
+            first: self.cursor,
+            length: arr.len(),
+        };
+        self.cursor += arr.len();
     }
 
-    #[inline(always)]
-    pub fn push(&mut self, a: V) {
-        self.buffer.push(a);
+    ///Describes the last upload.
+    pub(crate) fn get_info(&self, length: usize) -> BufferInfo {
+        assert!(length <= self.last.length);
+        BufferInfo {
+            length,
+            ..self.last
+        }
     }
 
-    #[inline(always)]
-    pub fn clear(&mut self) {
-        self.buffer.clear();
+    pub(crate) fn stats(&self) -> StreamStats {
+        self.stats
     }
 
-    fn re_generate_buffer(&mut self) {
-        let vbo = &mut self.vbo;
-        unsafe {
-            gl::BindBuffer(gl::ARRAY_BUFFER, *vbo);
-            gl::BufferData(
-                gl::ARRAY_BUFFER,
-                (self.buffer.capacity() * mem::size_of::<V>()) as GLsizeiptr,
-                self.buffer.as_ptr() as *const _,
-                gl::DYNAMIC_DRAW,
-            );
-            gl_ok!()
-        }
-
-        //TODO first confirm the vbo resized??
-        self.vbo_size = Some(self.buffer.capacity());
+    pub(crate) fn reset_stats(&mut self) {
+        self.stats = StreamStats::default();
     }
 }
-*/
+
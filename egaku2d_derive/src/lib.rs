@@ -0,0 +1,136 @@
+//! Derive macro for `egaku2d_core::batch::BatchVertex`.
+//!
+//! Mark the fields with `#[batch(position)]`, `#[batch(end)]`, `#[batch(bounds)]`, `#[batch(index)]`,
+//! `#[batch(rotation)]`, `#[batch(color)]` and `#[batch(radius)]`. Either the position or the bounds is required.
+//! The position and end must be a `[f32; 2]`, the bounds and color a `[f32; 4]`, the index a `u16`,
+//! and the rotation and radius an `f32`.
+//! The fields have to be exactly those types, so for example a `Box<[f32; 2]>` is not a position.
+//! The layout is checked when the crate is compiled.
+//!
+//! The generated code refers to `::egaku2d_core`. If that is not a dependency, point it at the crate that
+//! re-exports it, for example with `#[batch(crate = "egaku2d::egaku2d_core")]` on the struct.
+
+use proc_macro::TokenStream;
+use proc_macro2::Span;
+use quote::quote;
+use syn::{parse_macro_input, Data, DeriveInput, Error, Fields, Ident, LitStr, Member, Path};
+
+#[proc_macro_derive(BatchVertex, attributes(batch))]
+pub fn derive_batch_vertex(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match expand(&input) {
+        Ok(tokens) => tokens.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+//The fields that can be marked.
+const ROLES: [&str; 7] = ["position", "bounds", "end", "index", "rotation", "color", "radius"];
+
+fn expand(input: &DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    let name = &input.ident;
+    if !input.generics.params.is_empty() {
+        return Err(Error::new_spanned(&input.generics, "BatchVertex can not be derived for generic structs"));
+    }
+
+    let mut krate: Path = syn::parse_quote!(::egaku2d_core);
+    for attr in input.attrs.iter().filter(|a| a.path().is_ident("batch")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("crate") {
+                let s: LitStr = meta.value()?.parse()?;
+                krate = s.parse()?;
+                Ok(())
+            } else {
+                Err(meta.error("expected `crate = \"...\"`"))
+            }
+        })?;
+    }
+
+    let fields = match &input.data {
+        Data::Struct(s) => &s.fields,
+        _ => return Err(Error::new_spanned(name, "BatchVertex can only be derived for structs")),
+    };
+
+    let mut found: [Option<Member>; 7] = Default::default();
+    let members: Vec<(Member, &syn::Field)> = match fields {
+        Fields::Named(f) => f
+            .named
+            .iter()
+            .map(|f| (Member::Named(f.ident.clone().unwrap()), f))
+            .collect(),
+        Fields::Unnamed(f) => f
+            .unnamed
+            .iter()
+            .enumerate()
+            .map(|(i, f)| (Member::Unnamed(i.into()), f))
+            .collect(),
+        Fields::Unit => Vec::new(),
+    };
+    for (member, field) in members.iter() {
+        for attr in field.attrs.iter().filter(|a| a.path().is_ident("batch")) {
+            attr.parse_nested_meta(|meta| {
+                let role = ROLES
+                    .iter()
+                    .position(|r| meta.path.is_ident(r))
+                    .ok_or_else(|| meta.error(format!("expected one of {}", ROLES.join(", "))))?;
+                if found[role].is_some() {
+                    return Err(meta.error(format!("more than one field is marked as the {}", ROLES[role])));
+                }
+                found[role] = Some(member.clone());
+                Ok(())
+            })?;
+        }
+    }
+
+    //Passing the fields to a function that wants them to be exactly the right types makes the compiler check them.
+    //Borrowing them as the right types would let a `Box<[f32; 2]>` through by deref coercion.
+    let mut checks = Vec::new();
+    let mut layout = match (&found[0], &found[1]) {
+        (Some(position), None) => {
+            checks.push(quote! { same(&v.#position, PhantomData::<[f32; 2]>); });
+            quote! { #krate::batch::Layout::new::<#name>(::core::mem::offset_of!(#name, #position)) }
+        }
+        (None, Some(bounds)) => {
+            checks.push(quote! { same(&v.#bounds, PhantomData::<[f32; 4]>); });
+            quote! { #krate::batch::Layout::rect::<#name>(::core::mem::offset_of!(#name, #bounds)) }
+        }
+        (Some(_), Some(_)) => {
+            return Err(Error::new(Span::call_site(), "a field can be marked as the position or as the bounds, not both"));
+        }
+        (None, None) => {
+            return Err(Error::new(
+                Span::call_site(),
+                "one field has to be marked with #[batch(position)] or #[batch(bounds)]",
+            ));
+        }
+    };
+
+    let optional = [
+        (&found[2], quote! { [f32; 2] }, quote! { with_end }),
+        (&found[3], quote! { u16 }, quote! { with_index }),
+        (&found[4], quote! { f32 }, quote! { with_rotation }),
+        (&found[5], quote! { [f32; 4] }, quote! { with_color }),
+        (&found[6], quote! { f32 }, quote! { with_radius }),
+    ];
+    for (member, ty, with) in optional.iter() {
+        if let Some(member) = member {
+            checks.push(quote! { same(&v.#member, PhantomData::<#ty>); });
+            layout = quote! { #layout.#with(::core::mem::offset_of!(#name, #member)) };
+        }
+    }
+
+    let check_fn = Ident::new("_check_batch_fields", Span::call_site());
+    Ok(quote! {
+        unsafe impl #krate::batch::BatchVertex for #name {
+            const LAYOUT: #krate::batch::Layout = {
+                #[allow(dead_code)]
+                fn #check_fn(v: &#name) {
+                    use ::core::marker::PhantomData;
+                    fn same<T>(_: &T, _: PhantomData<T>) {}
+                    #(#checks)*
+                }
+                #layout
+            };
+        }
+    })
+}
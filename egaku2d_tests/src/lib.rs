@@ -0,0 +1,74 @@
+//! Tests of egaku2d that use more than one crate of the workspace. The tests are in `tests/`.
+//!
+//! The derive only accepts fields that are exactly the right type:
+//!
+//! ```compile_fail
+//! use egaku2d_core::batch::BatchVertex;
+//!
+//! #[derive(Copy, Clone, BatchVertex)]
+//! struct Bot {
+//!     #[batch(position)]
+//!     pos: [f64; 2],
+//! }
+//! ```
+//!
+//! ```compile_fail
+//! use egaku2d_core::batch::BatchVertex;
+//!
+//! #[derive(Copy, Clone, BatchVertex)]
+//! struct Bot {
+//!     #[batch(position)]
+//!     pos: [f32; 2],
+//!     #[batch(index)]
+//!     index: u32,
+//! }
+//! ```
+//!
+//! A layout written by hand is checked when it is used:
+//!
+//! ```compile_fail
+//! use egaku2d_core::batch::{BatchVertex, Layout};
+//!
+//! #[derive(Copy, Clone)]
+//! #[repr(C)]
+//! struct Bot {
+//!     id: u16,
+//!     pos: [f32; 2],
+//! }
+//!
+//! unsafe impl BatchVertex for Bot {
+//!     const LAYOUT: Layout = Layout::new::<Bot>(2);
+//! }
+//!
+//! const _: Layout = Bot::LAYOUT;
+//! ```
+//!
+//! So is a layout made for a bigger struct, when it is drawn:
+//!
+//! ```compile_fail
+//! use egaku2d_core::batch::{BatchVertex, Layout};
+//! use egaku2d_core::SimpleCanvas;
+//!
+//! #[derive(Copy, Clone)]
+//! #[repr(C)]
+//! struct Big {
+//!     pos: [f32; 2],
+//!     color: [f32; 4],
+//! }
+//!
+//! #[derive(Copy, Clone)]
+//! #[repr(C)]
+//! struct Small {
+//!     pos: [f32; 2],
+//! }
+//!
+//! unsafe impl BatchVertex for Small {
+//!     const LAYOUT: Layout = Layout::new::<Big>(0);
+//! }
+//!
+//! fn draw(canvas: &mut SimpleCanvas, bots: &[Small]) {
+//!     canvas.batch_circles(bots);
+//! }
+//!
+//! let _: fn(&mut SimpleCanvas, &[Small]) = draw;
+//! ```
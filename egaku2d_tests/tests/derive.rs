@@ -0,0 +1,88 @@
+use core::mem::{offset_of, size_of};
+use egaku2d_core::batch::{BatchVertex, Layout};
+
+#[derive(Copy, Clone, BatchVertex)]
+#[repr(C)]
+struct Bot {
+    #[batch(position)]
+    pos: [f32; 2],
+    vel: [f32; 2],
+    #[batch(color)]
+    color: [f32; 4],
+}
+
+#[derive(Copy, Clone, BatchVertex)]
+#[repr(C)]
+struct Entity {
+    #[batch(position)]
+    pos: [f32; 2],
+    #[batch(rotation)]
+    rotation: f32,
+    #[batch(index)]
+    sprite: u16,
+    health: u16,
+}
+
+#[derive(Copy, Clone, BatchVertex)]
+#[repr(C)]
+struct Wall {
+    id: u32,
+    #[batch(bounds)]
+    bounds: [f32; 4],
+    #[batch(color)]
+    color: [f32; 4],
+}
+
+#[derive(Copy, Clone, BatchVertex)]
+#[repr(C)]
+struct Segment(#[batch(position)] [f32; 2], #[batch(end)] [f32; 2], #[batch(radius)] f32);
+
+//Without repr(C) the compiler picks the order of the fields.
+#[derive(Copy, Clone, BatchVertex)]
+#[batch(crate = "egaku2d_core")]
+struct Packed {
+    flag: u8,
+    #[batch(index)]
+    index: u16,
+    #[batch(position)]
+    pos: [f32; 2],
+    id: u64,
+}
+
+#[derive(Copy, Clone)]
+#[repr(C)]
+struct Manual {
+    _id: u32,
+    pos: [f32; 2],
+}
+
+unsafe impl BatchVertex for Manual {
+    const LAYOUT: Layout = Layout::new::<Manual>(offset_of!(Manual, pos));
+}
+
+#[test]
+fn offsets_and_stride() {
+    assert_eq!(size_of::<Bot>(), 32);
+    assert_eq!(Bot::LAYOUT, Layout::new::<Bot>(0).with_color(16));
+
+    assert_eq!(size_of::<Entity>(), 16);
+    assert_eq!(Entity::LAYOUT, Layout::new::<Entity>(0).with_rotation(8).with_index(12));
+
+    assert_eq!(size_of::<Wall>(), 36);
+    assert_eq!(Wall::LAYOUT, Layout::rect::<Wall>(4).with_color(20));
+
+    assert_eq!(size_of::<Segment>(), 20);
+    assert_eq!(Segment::LAYOUT, Layout::new::<Segment>(0).with_end(8).with_radius(16));
+}
+
+#[test]
+fn same_as_by_hand() {
+    assert_eq!(
+        Packed::LAYOUT,
+        Layout::new::<Packed>(offset_of!(Packed, pos)).with_index(offset_of!(Packed, index))
+    );
+    assert_ne!(Packed::LAYOUT, Layout::new::<Packed>(offset_of!(Packed, pos)));
+
+    assert_eq!(size_of::<Manual>(), 12);
+    assert_eq!(Manual::LAYOUT, Layout::new::<Manual>(4));
+}
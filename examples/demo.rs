@@ -1,4 +1,6 @@
 extern crate egaku2d;
+use egaku2d::sprite::TextureOptions;
+use egaku2d::text::{Font, Span, TextLayout};
 use glutin::event::{Event, VirtualKeyCode, WindowEvent};
 use glutin::event_loop::ControlFlow;
 
@@ -20,7 +22,12 @@ fn main() {
     let ascii_tex = sys.texture("ascii.png", [16, 14]).unwrap();
     let tall_tiles_tex = sys.texture("tall_tiles.png", [2, 3]).unwrap();
     let fat_tiles_tex = sys.texture("fat_tiles.png", [2, 3]).unwrap();
-    let leaves = sys.texture("leaves.png", [1, 1]).unwrap();
+    let leaves = sys
+        .texture_with_options("leaves.png", [1, 1], TextureOptions::new().with_trilinear())
+        .unwrap();
+
+    //The ascii tile set starts at the space character.
+    let font = Font::new(&ascii_tex, ' ');
 
     //Make a bunch of static vbos
     let canvas = sys.canvas_mut();
@@ -176,6 +183,43 @@ fn main() {
                 builder.add([100., 100.], ascii_tex.coord_to_index([2, 2]), 1.0);
                 builder.send_and_uniforms(canvas, &ascii_tex, 20.0).draw();
 
+                //Draw some multi colored text with its bounding box behind it,
+                //and a caret in front of the char closest to the cursor.
+                let text_pos = [330., 420.];
+                let spans = [
+                    Span::new("Hello ", &font).with_color(COL4),
+                    Span::new("rich\ntext!", &font).with_color(COL3),
+                ];
+                let layout = TextLayout::new(&spans, 16.0);
+                let bb = layout.bounding_box();
+                canvas
+                    .rects()
+                    .add([
+                        text_pos[0] + bb.x.start,
+                        text_pos[0] + bb.x.end,
+                        text_pos[1] + bb.y.start,
+                        text_pos[1] + bb.y.end,
+                    ])
+                    .send_and_uniforms(canvas)
+                    .with_color([0.0, 0.0, 0.0, 0.5])
+                    .draw();
+
+                let index = layout.hit_test([cursor[0] - text_pos[0], cursor[1] - text_pos[1]]);
+                let caret = layout.caret_position(index);
+                let caret = [text_pos[0] + caret[0], text_pos[1] + caret[1]];
+                canvas
+                    .lines(1.0)
+                    .add(caret, [caret[0], caret[1] + layout.line_height()])
+                    .send_and_uniforms(canvas)
+                    .with_color(WHITE)
+                    .draw();
+
+                canvas
+                    .text(16.0)
+                    .add_layout(text_pos, &layout)
+                    .send_and_uniforms(canvas, &ascii_tex)
+                    .draw();
+
                 let c = ((counter as f32 * 0.06).sin() * 100.0).abs();
                 canvas
                     .circles()
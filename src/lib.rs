@@ -85,6 +85,78 @@
 //! the api. The rotation is in radians with 0 being no rotation and grows with a clockwise rotation.
 //! 
 //!
+//! # Nine-slice panels
+//!
+//! Resizable UI frames can be drawn with `canvas.nine_slices()`. A `nine_slice::NineSlice` cuts a region of a texture
+//! into corners, edges and a center using border insets. Every rect added becomes a panel whose corners keep their size,
+//! whose edges stretch or tile, and whose center fills the rest. All panels in a session are drawn in one draw call,
+//! and can be saved like any other shape.
+//!
+//! # Creating Textures
+//!
+//! Besides loading a file with `WindowedSystem::texture()`, textures can be made from encoded bytes
+//! (for example with `include_bytes!`), from raw rgba, rgb or grayscale pixel slices, from an `image::DynamicImage`,
+//! or by calling a function for every pixel. These constructors are on `sprite::Texture` in egaku2d_core,
+//! and take the canvas to ensure they happen on the thread with the opengl context.
+//!
+//! Sprite sheets with gaps between the tiles can be described by a `sprite::SheetLayout`
+//! of the tile size in pixels, the outer margin and the spacing, with `Texture::from_sheet_file()`.
+//! The grid dimensions are then derived from the layout. To stop tiles bleeding into each other
+//! with linear filtering, pass a non zero extrude amount to pad every tile with copies of its edge pixels.
+//!
+//! Textures can also be changed after they are made. `Texture::update_region()` replaces a rectangle of pixels,
+//! `Texture::resize()` changes the size, and `Texture::read_pixels()` reads the contents back.
+//! For regions that change every frame, `Texture::update_region_streamed()` goes through a `sprite::PixelStream`
+//! so the upload does not stall on the gpu.
+//!
+//! A `Texture` deletes its opengl texture when it is dropped. To use one texture from many places
+//! without borrowing it, turn it into a `sprite::TextureHandle` with `Texture::into_handle()`.
+//! Handles are cheap to clone and deref to `Texture`, so `&handle` can be passed anywhere a `&Texture` goes.
+//!
+//! # Texture arrays
+//!
+//! A `sprite::SpriteSession` draws out of one texture, so sprites out of different sheets need separate draws.
+//! A `sprite_array::TextureArray` stacks same sized sheets into the layers of one texture,
+//! and a session from `SimpleCanvas::sprite_arrays()` takes a layer along with the tile index of every sprite.
+//! That way sprites from many sheets are drawn in one draw call in the order they were added.
+//! The tile index is 32 bit, so the grids can be larger than 256x256.
+//!
+//! # Using Text
+//!
+//! Text is drawn as sprites out of a tile set where every tile is a glyph.
+//! A `text::Font` maps characters to tiles, and can measure strings before they are drawn:
+//! their bounding box, the width of each line, where the caret goes for a char index,
+//! and which char index a point is closest to.
+//!
+//! A string can be made out of multiple `text::Span`s that each have their own color and font.
+//! Each glyph vertex carries its color, so all the spans are still drawn in one draw call.
+//! For that reason all the fonts used in one draw must be glyph ranges of the same texture.
+//!
+//! # Tilemaps
+//!
+//! Large tile worlds should not be saved into one sprite buffer, since then the whole map is drawn every frame.
+//! A `tilemap::Tilemap` holds layers of tile indicies and splits them into fixed size chunks that are each
+//! saved to their own static buffer. Only the chunks that intersect `SimpleCanvas::view_rect()` are drawn,
+//! and `set_tile()` only causes the chunk the tile is in to be re-uploaded.
+//!
+//! # Tiled maps
+//!
+//! With the `tiled` feature (on by default), maps made with the Tiled editor can be loaded with `tiled::load()`.
+//! TMX and JSON maps and tilesets are supported, including flipped tiles and object layers.
+//! `tiled::Map::build_tile_layers()` turns the tile layers into tilemaps, one per tileset,
+//! which are drawn with the textures of the tilesets in order, faded by the opacity of each layer.
+//! Object layers can add their shapes to line, rect and circle sessions.
+//!
+//! # Assets
+//!
+//! `WindowedSystem::load_texture()` loads textures through an `assets::AssetManager` that caches them by path,
+//! and returns shared `sprite::TextureHandle`s. Calling `WindowedSystem::reload_changed_assets()` every frame
+//! checks the files for changes, and re-uploads changed textures in place, so everything using them updates live.
+//!
+//! To avoid hitches on loading screens, `WindowedSystem::load_texture_async()` decodes the file on a worker thread
+//! and returns an `assets::PendingTexture`. Call `WindowedSystem::upload_pending_textures()` every frame
+//! with an `assets::UploadBudget` to upload finished images, and use the returned progress to draw a loading bar.
+//!
 //! # Batch drawing
 //!
 //! While you can pretty efficiently draw thousands of objects by calling add() a bunch of times,
@@ -97,7 +169,12 @@
 //! tightly packed since you have a bunch of other  data associated with each element,
 //! in which case we might end up sending a lot of useless data to the gpu.
 //!
-//! Currently this is only supported for circle drawing.
+//! Circles, squares, sprites, rects and lines can be drawn this way. The struct implements `batch::BatchVertex` to say
+//! where its fields are, like its position, the bounds of a rect, the end of a line, or the index and rotation
+//! of a sprite. Its `Layout` is checked when the crate is compiled, as is whether it has what the batch needs.
+//! The gpu reads the struct as raw bytes, so the trait is unsafe to implement by hand.
+//! With the `derive` feature, `#[derive(BatchVertex)]` writes the impl, and checks the types of the fields.
+//! Add `#[batch(crate = "egaku2d::egaku2d_core")]` to the struct if you only depend on egaku2d.
 //!
 //! # View
 //!
@@ -113,7 +190,84 @@
 //! shapes. The user can manually set the scale either by x or y and the other axis is automaically inferred
 //! so that to keep a 1:1 ratio.
 //!
-//!  
+//! # Camera
+//!
+//! For more control, give the canvas a `camera::Camera2D` with `SimpleCanvas::set_camera()`.
+//! A camera has a world position at the center of its view, a zoom in pixels per world unit,
+//! a rotation, and a rectangle of the window to draw into. `Camera2D::zoom_at()` zooms around a point,
+//! and `SimpleCanvas::screen_to_world()` turns a mouse position into a world position.
+//! The `set_viewport` functions replace the camera with one that matches the behavior described above.
+//!
+//! # Transforms
+//!
+//! `Uniforms::with_transform()` takes a `transform::Affine2D` that moves, rotates and scales
+//! a buffer in the vertex shader, so one saved outline can be drawn as many different ships.
+//! It is applied to the positions the buffer was made with, before the offset and the camera.
+//! `Affine2D::from_matrix()` accepts any 3x3 matrix.
+//!
+//! # Streaming
+//!
+//! Sessions upload their vertices into streaming buffers owned by the canvas. Each upload is appended after
+//! the last one, so drawing many sessions in a frame never overwrites vertices the gpu might still be reading.
+//! When a buffer fills up its storage is orphaned, and it doubles in size if an upload does not fit at all.
+//! `SimpleCanvas::stream_stats()` returns how many bytes were uploaded and how often the buffers were
+//! reallocated or orphaned since the last `SimpleCanvas::reset_stream_stats()`.
+//!
+//! # State caching
+//!
+//! Every combination of program, buffer and vertex layout gets a vertex array object the first time it is drawn,
+//! so drawing a save again does not set up its attributes again. Programs, textures, uniforms and blending go through
+//! a state tracker that skips calls that would not change anything. `SimpleCanvas::gl_stats()` returns how many calls
+//! the last frame made and how many were skipped. A frame ends at `swap_buffers()`.
+//!
+//! # Render queue
+//!
+//! Instead of `draw()`, the uniforms of a session can be queued with `Uniforms::queue(layer)`.
+//! The canvas keeps a copy of the vertices, and draws everything at `swap_buffers()`, at `SimpleCanvas::flush()`,
+//! or when the camera changes. Lower layers are drawn first, and within a layer things are drawn in the order
+//! they were queued. Neighbouring draws with the same program, texture, blend mode and uniforms are merged
+//! into one draw call, and every kind of vertex is uploaded once per flush. `Uniforms::with_blend()` picks the blend mode.
+//!
+//! Saves can be queued the same way, so shadows, ground, units and overlays can each go in their own layer
+//! no matter what order the code draws them in. For top-down games, `SimpleCanvas::set_y_sort(layer,true)` draws
+//! the queued sprites of a layer from the top of the world to the bottom, so sprites lower down are in front.
+//! `SpriteSession::y_sort()` does the same for the sprites of a single session.
+//!
+//! # Instancing
+//!
+//! To draw the same save or session many times, pass a slice of `instance::Instance`s to `Uniforms::with_instances()`.
+//! Every instance has its own offset, rotation and scale, and a color that tints it, and they are all drawn in one draw call.
+//! Instances that do not change can be uploaded once with `instance::InstanceSave::new()`, and drawn with
+//! `Uniforms::with_saved_instances()`.
+//!
+//! For lots of rects, lines or arrows that change every frame, `SimpleCanvas::rects_instanced()`, `lines_instanced()`
+//! and `arrows_instanced()` make sessions with the same `add()` functions that only upload one record of four floats
+//! per shape. The vertex shader expands each record into the shape.
+//!
+//! # Compact vertices
+//!
+//! Circle, square, rect and sprite sessions can store their positions in half the space with `with_precision()`,
+//! for huge static scenes where memory and bandwidth matter more than precision. Their saves keep the precision.
+//! `compact::Precision::Half` stores `f16`s, and `compact::Precision::Quantized` stores `i16`s that count steps away
+//! from an origin. `Precision::max_error()` says how far a position can move.
+//! Lines and arrows always store full positions, see the `compact` module for why.
+//!
+//! # Retained drawing
+//!
+//! For scenes that mostly stay the same, `SimpleCanvas::retained_circles()` and the other `retained_*()` functions
+//! make objects that keep their vertices on the gpu like a save, but whose elements can still change.
+//! `insert()` returns a `retained::Handle` for the element, which `set()` and `remove()` take. Drawing only sends
+//! the elements that changed since the last draw. Removed elements are filled by moving the last element into
+//! their place, so the order elements are drawn in is not kept.
+//!
+//! # Sizes
+//!
+//! The radius of point sprites, circles and squares, and the thickness of lines and arrows, are either in
+//! window pixels or in world units, picked by a `uniforms::SizeSpace`. With `SizeSpace::Screen`, the default,
+//! they stay the same size on screen no matter the zoom. With `SizeSpace::World` they grow and shrink with the camera,
+//! the same way rects do. `SimpleCanvas::set_size_space()` sets the default for everything drawn afterwards,
+//! and `Uniforms::with_size_space()` overrides it for one draw. Saved buffers store positions only,
+//! so the choice is applied when they are drawn. Tilemaps are always sized in world units.
 //!
 //! # Fullscreen
 //!
@@ -125,7 +279,6 @@
 //! # Example
 //!
 //! ```rust,no_run
-//! use axgeom::*;
 //! let events_loop = glutin::event_loop::EventLoop::new();
 //! let mut glsys = egaku2d::WindowedSystem::new([600, 480], &events_loop,"test window");
 //!
@@ -177,14 +330,30 @@ use egaku2d_core::axgeom;
 pub use glutin;
 use glutin::PossiblyCurrent;
 
-use egaku2d_core;
+pub use egaku2d_core;
 use egaku2d_core::gl;
 
+pub use egaku2d_core::assets;
 pub use egaku2d_core::batch;
+pub use egaku2d_core::camera;
+pub use egaku2d_core::compact;
+pub use egaku2d_core::instance;
+pub use egaku2d_core::instanced_shapes;
+pub use egaku2d_core::nine_slice;
+pub use egaku2d_core::queue;
+pub use egaku2d_core::retained;
 pub use egaku2d_core::shapes;
 pub use egaku2d_core::sprite;
+pub use egaku2d_core::sprite_array;
+pub use egaku2d_core::text;
+pub use egaku2d_core::tilemap;
+pub use egaku2d_core::transform;
+#[cfg(feature = "tiled")]
+pub use egaku2d_core::tiled;
 pub use egaku2d_core::uniforms;
 pub use egaku2d_core::SimpleCanvas;
+pub use egaku2d_core::StreamStats;
+pub use egaku2d_core::GlStats;
 use egaku2d_core::FixedAspectVec2;
 use egaku2d_core::AspectRatio;
 
@@ -359,7 +528,17 @@ pub mod fullscreen {
             file: &str,
             grid_dim: [u8; 2],
         ) -> image::ImageResult<sprite::Texture> {
-            crate::texture(file, grid_dim)
+            crate::texture(&mut self.inner, file, grid_dim, TextureOptions::default())
+        }
+
+        ///Creates a new texture from the specified file with the specified sampling options.
+        pub fn texture_with_options(
+            &mut self,
+            file: &str,
+            grid_dim: [u8; 2],
+            options: TextureOptions,
+        ) -> image::ImageResult<sprite::Texture> {
+            crate::texture(&mut self.inner, file, grid_dim, options)
         }
 
         pub fn canvas(&self) -> &SimpleCanvas {
@@ -373,6 +552,7 @@ pub mod fullscreen {
             self.window_dim.as_vec().inner_as()
         }
         pub fn swap_buffers(&mut self) {
+            self.inner.finish_frame();
             self.windowed_context
                 .as_mut()
                 .unwrap()
@@ -385,6 +565,7 @@ pub mod fullscreen {
 
 ///A version where the user can control the size of the window.
 pub struct WindowedSystem {
+    assets: assets::AssetManager,
     inner: SimpleCanvas,
     window_dim: FixedAspectVec2,
     windowed_context: glutin::WindowedContext<PossiblyCurrent>,
@@ -447,6 +628,7 @@ impl WindowedSystem {
         };
 
         WindowedSystem {
+            assets: assets::AssetManager::new(),
             windowed_context,
             window_dim,
             inner: unsafe { SimpleCanvas::new(window_dim) },
@@ -485,7 +667,66 @@ impl WindowedSystem {
         file: &str,
         grid_dim: [u8; 2],
     ) -> image::ImageResult<sprite::Texture> {
-        crate::texture(file, grid_dim)
+        crate::texture(&mut self.inner, file, grid_dim, TextureOptions::default())
+    }
+
+    ///Creates a new texture from the specified file with the specified sampling options.
+    ///For example `TextureOptions::new().with_trilinear().with_wrap(Wrap::Clamp)`.
+    ///The options can be changed later with `Texture::set_options()`.
+    pub fn texture_with_options(
+        &mut self,
+        file: &str,
+        grid_dim: [u8; 2],
+        options: TextureOptions,
+    ) -> image::ImageResult<sprite::Texture> {
+        crate::texture(&mut self.inner, file, grid_dim, options)
+    }
+
+    ///Get a shared handle to a texture through the asset manager.
+    ///The file is only loaded the first time, after that the cached texture is returned.
+    pub fn load_texture(
+        &mut self,
+        file: &str,
+        grid_dim: [u8; 2],
+    ) -> image::ImageResult<TextureHandle> {
+        self.assets
+            .texture(&mut self.inner, file, grid_dim, TextureOptions::default())
+    }
+
+    ///The same as `load_texture()`, but with sampling options that are used if the texture is not cached yet.
+    pub fn load_texture_with_options(
+        &mut self,
+        file: &str,
+        grid_dim: [u8; 2],
+        options: TextureOptions,
+    ) -> image::ImageResult<TextureHandle> {
+        self.assets.texture(&mut self.inner, file, grid_dim, options)
+    }
+
+    ///Start loading a texture through the asset manager on a worker thread.
+    ///It is uploaded by `upload_pending_textures()`.
+    pub fn load_texture_async(&mut self, file: &str, grid_dim: [u8; 2]) -> assets::PendingTexture {
+        self.assets
+            .texture_async(file, grid_dim, TextureOptions::default())
+    }
+
+    ///Upload the textures that finished decoding, within a budget.
+    ///Call this once a frame while loading, and use the returned progress to draw a loading bar.
+    pub fn upload_pending_textures(&mut self, budget: assets::UploadBudget) -> assets::LoadProgress {
+        self.assets.upload_pending(&mut self.inner, budget)
+    }
+
+    ///Re-upload the textures in the asset manager whose files changed on disk.
+    ///Call this once a frame. See `assets::AssetManager::poll()`.
+    pub fn reload_changed_assets(&mut self) -> Vec<(std::path::PathBuf, image::ImageResult<()>)> {
+        self.assets.poll(&mut self.inner)
+    }
+
+    pub fn assets(&self) -> &assets::AssetManager {
+        &self.assets
+    }
+    pub fn assets_mut(&mut self) -> &mut assets::AssetManager {
+        &mut self.assets
     }
 
     pub fn canvas(&self) -> &SimpleCanvas {
@@ -495,6 +736,7 @@ impl WindowedSystem {
         &mut self.inner
     }
     pub fn swap_buffers(&mut self) {
+        self.inner.finish_frame();
         self.windowed_context.swap_buffers().unwrap();
         assert_eq!(unsafe { gl::GetError() }, gl::NO_ERROR);
     }
@@ -514,8 +756,6 @@ fn prompt_for_monitor(el: &EventLoop<()>) -> MonitorHandle {
     monitor
 }
 
-use egaku2d_core::gl::types::GLuint;
-use egaku2d_core::gl_ok;
 use egaku2d_core::sprite::*;
 
 ///Creates a new texture from the specified file.
@@ -523,58 +763,13 @@ use egaku2d_core::sprite::*;
 ///Ensures that we make the texture in the same thread.
 ///The grid dimensions passed are the tile dimensions is
 ///the texture is a tile set.
-fn texture(file: &str, grid_dim: [u8; 2]) -> image::ImageResult<sprite::Texture> {
-    match image::open(&file.to_string()) {
-        Err(err) => Err(err),
-        Ok(img) => {
-            use image::GenericImageView;
-
-            let (width, height) = img.dimensions();
-
-            let img = match img {
-                image::DynamicImage::ImageRgba8(img) => img,
-                img => img.to_rgba(),
-            };
-
-            let id = build_opengl_mipmapped_texture(width, height, img);
-            Ok(unsafe { Texture::new(id, grid_dim, [width as f32, height as f32]) })
-        }
-    }
-}
-
-fn build_opengl_mipmapped_texture(width: u32, height: u32, image: image::RgbaImage) -> GLuint {
-    unsafe {
-        let mut texture_id: GLuint = 0;
-        gl::GenTextures(1, &mut texture_id);
-        gl_ok!();
-
-        gl::BindTexture(gl::TEXTURE_2D, texture_id);
-        gl_ok!();
-
-        let raw = image.into_raw();
-
-        gl::TexImage2D(
-            gl::TEXTURE_2D,
-            0,
-            gl::RGBA as i32,
-            width as i32,
-            height as i32,
-            0,
-            gl::RGBA,
-            gl::UNSIGNED_BYTE,
-            raw.as_ptr() as *const _,
-        );
-        gl_ok!();
-
-        //TODO convert these into options? with_linear() with_nearest() ??
-        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::NEAREST as i32);
-        gl_ok!();
-        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::NEAREST as i32);
-        gl_ok!();
-
-        gl::BindTexture(gl::TEXTURE_2D, 0);
-        gl_ok!();
-
-        texture_id
-    }
+fn texture(
+    canvas: &mut SimpleCanvas,
+    file: &str,
+    grid_dim: [u8; 2],
+    options: TextureOptions,
+) -> image::ImageResult<sprite::Texture> {
+    let mut texture = Texture::from_file(canvas, file, grid_dim)?;
+    texture.set_options(canvas, options);
+    Ok(texture)
 }